@@ -0,0 +1,283 @@
+use image::imageops::colorops::{brighten_in_place, contrast_in_place, invert};
+use image::{ColorType, DynamicImage, Rgba32FImage, RgbaImage};
+
+// Values sampled to find the display window of large images
+const WINDOW_SAMPLES: usize = 1 << 20;
+
+// How images with more than 8 bits per channel are brought down to 8 bits for display
+#[derive(PartialEq, Clone, Eq, Copy, Debug, Default)]
+pub enum ToneMapping {
+    // Stretches the values between two percentiles, good for 12 bit data in 16 bit files
+    #[default]
+    Window,
+    // The full range of the type, float images are clipped at 1.0
+    FullRange,
+    // For HDR images, compresses the highlights instead of clipping them
+    Reinhard,
+}
+
+impl ToneMapping {
+    pub const ALL: &'static [Self] = &[Self::Window, Self::FullRange, Self::Reinhard];
+
+    pub fn as_display(&self) -> &str {
+        match self {
+            Self::Window => "Window (percentiles)",
+            Self::FullRange => "Full range",
+            Self::Reinhard => "HDR (Reinhard)",
+        }
+    }
+}
+
+impl std::fmt::Display for ToneMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_display())
+    }
+}
+
+pub fn is_high_depth(image: &DynamicImage) -> bool {
+    !matches!(
+        image.color(),
+        ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8
+    )
+}
+
+#[derive(PartialEq, Clone, Eq, Copy, Debug, Default)]
+pub enum ChannelView {
+    #[default]
+    All,
+    Red,
+    Green,
+    Blue,
+    Grayscale,
+}
+
+impl ChannelView {
+    pub const ALL: &'static [Self] = &[
+        Self::All,
+        Self::Red,
+        Self::Green,
+        Self::Blue,
+        Self::Grayscale,
+    ];
+
+    pub fn as_display(&self) -> &str {
+        match self {
+            Self::All => "All channels",
+            Self::Red => "Red",
+            Self::Green => "Green",
+            Self::Blue => "Blue",
+            Self::Grayscale => "Grayscale",
+        }
+    }
+}
+
+impl std::fmt::Display for ChannelView {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_display())
+    }
+}
+
+// Changes to how the image is shown, the file itself is never modified
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisplayAdjustments {
+    // Added to every channel, -100 to 100
+    pub brightness: i32,
+    // In percent, -100 to 100
+    pub contrast: f32,
+    pub gamma: f32,
+    pub invert: bool,
+    pub channel: ChannelView,
+    // Only used for images with more than 8 bits per channel
+    pub tone_mapping: ToneMapping,
+    // Percentiles of the values shown as black and white with `ToneMapping::Window`
+    pub window_low: f32,
+    pub window_high: f32,
+}
+
+impl Default for DisplayAdjustments {
+    fn default() -> Self {
+        DisplayAdjustments {
+            brightness: 0,
+            contrast: 0.0,
+            gamma: 1.0,
+            invert: false,
+            channel: ChannelView::All,
+            tone_mapping: ToneMapping::Window,
+            window_low: 0.5,
+            window_high: 99.5,
+        }
+    }
+}
+
+impl DisplayAdjustments {
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // The 8 bit pixels to display
+    pub fn render(&self, image: DynamicImage) -> RgbaImage {
+        let mut pixels = match is_high_depth(&image) {
+            true => self.tone_map(&image.to_rgba32f()),
+            false => image.to_rgba8(),
+        };
+        self.apply(&mut pixels);
+        pixels
+    }
+
+    fn tone_map(&self, pixels: &Rgba32FImage) -> RgbaImage {
+        let (low, high) = match self.tone_mapping {
+            ToneMapping::Window => percentile_window(pixels, self.window_low, self.window_high),
+            _ => (0.0, 1.0),
+        };
+        let tone_mapping = self.tone_mapping;
+        let map = move |value: f32| match tone_mapping {
+            ToneMapping::Reinhard => {
+                let value = value.max(0.0);
+                (value / (1.0 + value)).powf(1.0 / 2.2)
+            }
+            _ => (value - low) / (high - low).max(f32::EPSILON),
+        };
+        let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        RgbaImage::from_fn(pixels.width(), pixels.height(), |x, y| {
+            let [red, green, blue, alpha] = pixels.get_pixel(x, y).0;
+            image::Rgba([
+                to_byte(map(red)),
+                to_byte(map(green)),
+                to_byte(map(blue)),
+                to_byte(alpha),
+            ])
+        })
+    }
+
+    pub fn apply(&self, pixels: &mut RgbaImage) {
+        if self.is_identity() {
+            return;
+        }
+        if self.brightness != 0 {
+            brighten_in_place(pixels, self.brightness);
+        }
+        if self.contrast != 0.0 {
+            contrast_in_place(pixels, self.contrast);
+        }
+        if self.gamma != 1.0 {
+            let lookup: Vec<u8> = (0..=255u8)
+                .map(|value| ((value as f32 / 255.0).powf(1.0 / self.gamma) * 255.0).round() as u8)
+                .collect();
+            for pixel in pixels.pixels_mut() {
+                for value in pixel.0.iter_mut().take(3) {
+                    *value = lookup[*value as usize];
+                }
+            }
+        }
+        if self.invert {
+            invert(pixels);
+        }
+        if self.channel != ChannelView::All {
+            for pixel in pixels.pixels_mut() {
+                let [red, green, blue, _] = pixel.0;
+                let value = match self.channel {
+                    ChannelView::Red => red,
+                    ChannelView::Green => green,
+                    ChannelView::Blue => blue,
+                    // Same weights as `image::imageops::grayscale`
+                    _ => (0.2126 * red as f32 + 0.7152 * green as f32 + 0.0722 * blue as f32)
+                        .round() as u8,
+                };
+                pixel.0[..3].fill(value);
+            }
+        }
+    }
+}
+
+// The values at the `low` and `high` percentiles of the colour channels
+fn percentile_window(pixels: &Rgba32FImage, low: f32, high: f32) -> (f32, f32) {
+    let step = (pixels.width() as usize * pixels.height() as usize / WINDOW_SAMPLES).max(1);
+    let mut values: Vec<f32> = pixels
+        .pixels()
+        .step_by(step)
+        .flat_map(|pixel| [pixel.0[0], pixel.0[1], pixel.0[2]])
+        .filter(|value| value.is_finite())
+        .collect();
+    if values.is_empty() {
+        return (0.0, 1.0);
+    }
+    values.sort_by(f32::total_cmp);
+    let at = |percent: f32| {
+        let position = (percent.clamp(0.0, 100.0) / 100.0 * (values.len() - 1) as f32).round();
+        values[position as usize]
+    };
+    (at(low), at(high))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample() -> RgbaImage {
+        RgbaImage::from_pixel(2, 2, image::Rgba([200, 100, 50, 255]))
+    }
+
+    #[test]
+    fn test_apply_adjustments() {
+        let mut pixels = sample();
+        DisplayAdjustments::default().apply(&mut pixels);
+        assert_eq!(pixels, sample());
+
+        let mut pixels = sample();
+        DisplayAdjustments {
+            brightness: 20,
+            invert: true,
+            ..Default::default()
+        }
+        .apply(&mut pixels);
+        assert_eq!(pixels.get_pixel(0, 0).0, [35, 135, 185, 255]);
+
+        let mut pixels = sample();
+        DisplayAdjustments {
+            channel: ChannelView::Green,
+            ..Default::default()
+        }
+        .apply(&mut pixels);
+        assert_eq!(pixels.get_pixel(1, 1).0, [100, 100, 100, 255]);
+
+        let mut pixels = sample();
+        DisplayAdjustments {
+            gamma: 2.0,
+            ..Default::default()
+        }
+        .apply(&mut pixels);
+        // Brighter mid-tones, the alpha is kept
+        let [red, green, blue, alpha] = pixels.get_pixel(0, 0).0;
+        assert!(red > 200 && green > 100 && blue > 50);
+        assert_eq!(alpha, 255);
+    }
+
+    #[test]
+    fn test_render_high_depth() {
+        // 12 bit values in a 16 bit image look black in the full range
+        let values: Vec<u16> = (0..100).map(|value| value * 40).collect();
+        let image =
+            DynamicImage::ImageLuma16(image::ImageBuffer::from_raw(10, 10, values).unwrap());
+        let window = DisplayAdjustments::default().render(image.clone());
+        assert_eq!(window.get_pixel(0, 0).0, [0, 0, 0, 255]);
+        assert_eq!(window.get_pixel(9, 9).0, [255, 255, 255, 255]);
+        let full_range = DisplayAdjustments {
+            tone_mapping: ToneMapping::FullRange,
+            ..Default::default()
+        }
+        .render(image);
+        assert!(full_range.get_pixel(9, 9).0[0] < 20);
+
+        // HDR values above 1.0 aren't clipped with Reinhard
+        let hdr = DynamicImage::ImageRgb32F(
+            image::ImageBuffer::from_raw(2, 1, vec![1.0, 1.0, 1.0, 8.0, 8.0, 8.0]).unwrap(),
+        );
+        let reinhard = DisplayAdjustments {
+            tone_mapping: ToneMapping::Reinhard,
+            ..Default::default()
+        }
+        .render(hdr);
+        assert!(reinhard.get_pixel(0, 0).0[0] < reinhard.get_pixel(1, 0).0[0]);
+        assert!(reinhard.get_pixel(1, 0).0[0] < 255);
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::DateTime;
+use once_cell::sync::OnceCell;
+
+use super::render_image::{Properties, Verdict};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReviewRole {
+    #[default]
+    Annotator,
+    // Settles the images the annotators disagree on, without a verdict of their own
+    Adjudicator,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReviewSettings {
+    pub annotator: String,
+    // Blind review hides the verdicts of the other annotators, and the agreement statistics
+    pub blind: bool,
+    pub role: ReviewRole,
+}
+
+static REVIEW_SETTINGS: OnceCell<ReviewSettings> = OnceCell::new();
+
+// Set once at startup when an annotator name is given. Without it there is a single
+// verdict per image, kept in `Properties::annotation` as before.
+pub fn init_review(settings: ReviewSettings) {
+    let _ = REVIEW_SETTINGS.set(settings);
+}
+
+pub fn review_settings() -> Option<&'static ReviewSettings> {
+    REVIEW_SETTINGS.get()
+}
+
+pub fn current_annotator() -> Option<&'static str> {
+    review_settings().map(|settings| settings.annotator.as_str())
+}
+
+pub fn is_adjudicator() -> bool {
+    review_settings().is_some_and(|settings| settings.role == ReviewRole::Adjudicator)
+}
+
+// Timestamps are written with `Local::now().to_string()`
+pub fn parse_timestamp(timestamp: &str) -> Option<DateTime<chrono::FixedOffset>> {
+    DateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S%.f %:z").ok()
+}
+
+fn is_newer(candidate: &Verdict, current: &Verdict) -> bool {
+    let candidate_time = candidate.last_updated.as_deref().and_then(parse_timestamp);
+    let current_time = current.last_updated.as_deref().and_then(parse_timestamp);
+    candidate_time > current_time
+}
+
+// Adds the verdicts of `other` to `record`, keeping the newest verdict of every annotator.
+// Returns true if `record` changed.
+pub fn merge_verdicts(record: &mut Properties, other: &Properties) -> bool {
+    let mut changed = false;
+    for (annotator, verdict) in other.verdicts.iter() {
+        let replace = match record.verdicts.get(annotator) {
+            Some(existing) => existing != verdict && is_newer(verdict, existing),
+            None => true,
+        };
+        if replace {
+            record.verdicts.insert(annotator.clone(), verdict.clone());
+            changed = true;
+        }
+    }
+    changed
+}
+
+// Takes what was saved to `stored` by someone else since `base` was loaded, for every field
+// `record` left as it was in `base`. Without `base`, the record wasn't saved yet when loaded.
+// Verdicts are merged by annotator.
+pub fn merge_saved(record: &mut Properties, base: Option<&Properties>, stored: &Properties) {
+    let default_base = Properties::default();
+    let base = base.unwrap_or(&default_base);
+    let mut taken = false;
+    if (&record.annotation, &record.comments) == (&base.annotation, &base.comments) {
+        taken |= (&record.annotation, &record.comments) != (&stored.annotation, &stored.comments);
+        record.annotation = stored.annotation;
+        record.comments = stored.comments.clone();
+    }
+    if record.resolution == base.resolution {
+        taken |= record.resolution != stored.resolution;
+        record.resolution = stored.resolution.clone();
+    }
+    if record.tags == base.tags {
+        taken |= record.tags != stored.tags;
+        record.tags = stored.tags.clone();
+    }
+    // The crop is drawn on the transformed image, they go together
+    if (&record.transform, &record.crop) == (&base.transform, &base.crop) {
+        taken |= (&record.transform, &record.crop) != (&stored.transform, &stored.crop);
+        record.transform = stored.transform;
+        record.crop = stored.crop;
+    }
+    let record_time = record.last_updated.as_deref().and_then(parse_timestamp);
+    let stored_time = stored.last_updated.as_deref().and_then(parse_timestamp);
+    if taken && stored_time > record_time {
+        record.last_updated = stored.last_updated.clone();
+    }
+    merge_verdicts(record, stored);
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AgreementStats {
+    pub annotators: Vec<String>,
+    // Images with a verdict from at least two annotators
+    pub items: usize,
+    // Share of those images where every verdict is the same
+    pub percent_agreement: Option<f64>,
+    // For every pair of annotators, over the images both have marked
+    pub cohen_kappa: Vec<(String, String, Option<f64>)>,
+    pub fleiss_kappa: Option<f64>,
+    // Images with conflicting verdicts, and how many of them an adjudicator resolved
+    pub conflicts: usize,
+    pub resolved: usize,
+}
+
+impl AgreementStats {
+    pub fn summary(&self) -> String {
+        let format_kappa = |kappa: Option<f64>| match kappa {
+            Some(value) => format!("{:.2}", value),
+            None => "n/a".to_string(),
+        };
+        match self.percent_agreement {
+            Some(percent) => format!(
+                "Agreement: {:.1}% over {} images, Fleiss' kappa: {}",
+                percent * 100.0,
+                self.items,
+                format_kappa(self.fleiss_kappa)
+            ),
+            None => "Agreement: no image marked by two annotators yet".to_string(),
+        }
+    }
+
+    pub fn adjudication_summary(&self) -> String {
+        format!(
+            "Resolved: {} of {} images with conflicting verdicts",
+            self.resolved, self.conflicts
+        )
+    }
+
+    pub fn cohen_summary(&self) -> Vec<String> {
+        self.cohen_kappa
+            .iter()
+            .map(|(first, second, kappa)| match kappa {
+                Some(value) => format!("Cohen's kappa {} / {}: {:.2}", first, second, value),
+                None => format!("Cohen's kappa {} / {}: n/a", first, second),
+            })
+            .collect()
+    }
+}
+
+// Kappa is undefined when agreement by chance is certain
+fn kappa(observed: f64, expected: f64) -> Option<f64> {
+    if (1.0 - expected).abs() < f64::EPSILON {
+        None
+    } else {
+        Some((observed - expected) / (1.0 - expected))
+    }
+}
+
+fn marked_verdicts(record: &Properties) -> BTreeMap<&str, bool> {
+    record
+        .verdicts
+        .iter()
+        .filter_map(|(annotator, verdict)| {
+            verdict.annotation.map(|value| (annotator.as_str(), value))
+        })
+        .collect()
+}
+
+pub fn has_conflict(record: &Properties) -> bool {
+    let marked = marked_verdicts(record);
+    marked.values().any(|value| *value) && marked.values().any(|value| !*value)
+}
+
+// Indices of the images to adjudicate, resolved ones included so they can be revisited
+pub fn adjudication_queue(records: &[Properties]) -> Vec<usize> {
+    records
+        .iter()
+        .filter(|record| has_conflict(record))
+        .map(|record| record.index)
+        .collect()
+}
+
+pub fn compute_agreement(records: &[Properties]) -> AgreementStats {
+    let marked: Vec<BTreeMap<&str, bool>> = records.iter().map(marked_verdicts).collect();
+    let annotators: BTreeSet<&str> = marked
+        .iter()
+        .flat_map(|item| item.keys().copied())
+        .collect();
+    let shared: Vec<&BTreeMap<&str, bool>> = marked.iter().filter(|item| item.len() >= 2).collect();
+
+    let conflicting: Vec<&Properties> = records
+        .iter()
+        .filter(|record| has_conflict(record))
+        .collect();
+    let mut stats = AgreementStats {
+        annotators: annotators.iter().map(|name| name.to_string()).collect(),
+        items: shared.len(),
+        conflicts: conflicting.len(),
+        resolved: conflicting
+            .iter()
+            .filter(|record| record.resolution.is_some())
+            .count(),
+        ..Default::default()
+    };
+    if shared.is_empty() {
+        return stats;
+    }
+
+    let unanimous = shared
+        .iter()
+        .filter(|item| item.values().all(|value| *value) || item.values().all(|value| !*value))
+        .count();
+    stats.percent_agreement = Some(unanimous as f64 / shared.len() as f64);
+
+    // Fleiss' kappa, allowing a different number of annotators per image
+    let mut agreement_sum = 0.0;
+    let (mut correct_total, mut ratings_total) = (0.0, 0.0);
+    for item in shared.iter() {
+        let raters = item.len() as f64;
+        let correct = item.values().filter(|value| **value).count() as f64;
+        let incorrect = raters - correct;
+        agreement_sum +=
+            (correct * correct + incorrect * incorrect - raters) / (raters * (raters - 1.0));
+        correct_total += correct;
+        ratings_total += raters;
+    }
+    let observed = agreement_sum / shared.len() as f64;
+    let correct_share = correct_total / ratings_total;
+    let expected = correct_share * correct_share + (1.0 - correct_share) * (1.0 - correct_share);
+    stats.fleiss_kappa = kappa(observed, expected);
+
+    let names: Vec<&str> = annotators.into_iter().collect();
+    for (position, first) in names.iter().enumerate() {
+        for second in names.iter().skip(position + 1) {
+            let pairs: Vec<(bool, bool)> = marked
+                .iter()
+                .filter_map(|item| Some((*item.get(first)?, *item.get(second)?)))
+                .collect();
+            if pairs.is_empty() {
+                continue;
+            }
+            let total = pairs.len() as f64;
+            let observed = pairs.iter().filter(|(a, b)| a == b).count() as f64 / total;
+            let first_correct = pairs.iter().filter(|(a, _)| *a).count() as f64 / total;
+            let second_correct = pairs.iter().filter(|(_, b)| *b).count() as f64 / total;
+            let expected =
+                first_correct * second_correct + (1.0 - first_correct) * (1.0 - second_correct);
+            stats.cohen_kappa.push((
+                first.to_string(),
+                second.to_string(),
+                kappa(observed, expected),
+            ));
+        }
+    }
+    stats
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::img_visualizer::render_image::Resolution;
+
+    fn record(verdicts: &[(&str, bool)]) -> Properties {
+        Properties {
+            verdicts: verdicts
+                .iter()
+                .map(|(annotator, value)| {
+                    (
+                        annotator.to_string(),
+                        Verdict {
+                            annotation: Some(*value),
+                            ..Default::default()
+                        },
+                    )
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compute_agreement_two_annotators() {
+        let records = vec![
+            record(&[("alice", true), ("bob", true)]),
+            record(&[("alice", true), ("bob", false)]),
+            record(&[("alice", false), ("bob", false)]),
+            record(&[("alice", false), ("bob", false)]),
+            // Marked by a single annotator, left out
+            record(&[("alice", true)]),
+        ];
+        let stats = compute_agreement(&records);
+        assert_eq!(stats.annotators, vec!["alice", "bob"]);
+        assert_eq!(stats.items, 4);
+        assert_eq!(stats.percent_agreement, Some(0.75));
+        // po = 0.75, pe = 0.5 * 0.25 + 0.5 * 0.75 = 0.5
+        let (_, _, cohen) = &stats.cohen_kappa[0];
+        assert!((cohen.unwrap() - 0.5).abs() < 1e-9);
+        assert!(stats.fleiss_kappa.is_some());
+    }
+
+    #[test]
+    fn test_compute_agreement_fleiss_kappa() {
+        // Perfect agreement between three annotators on both categories
+        let records = vec![
+            record(&[("a", true), ("b", true), ("c", true)]),
+            record(&[("a", false), ("b", false), ("c", false)]),
+        ];
+        let stats = compute_agreement(&records);
+        assert_eq!(stats.percent_agreement, Some(1.0));
+        assert!((stats.fleiss_kappa.unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!(stats.cohen_kappa.len(), 3);
+
+        assert_eq!(compute_agreement(&[]), AgreementStats::default());
+    }
+
+    #[test]
+    fn test_adjudication_queue() {
+        let mut records = vec![
+            record(&[("alice", true), ("bob", true)]),
+            record(&[("alice", true), ("bob", false)]),
+            record(&[("alice", false)]),
+            record(&[("alice", false), ("bob", false), ("carol", true)]),
+        ];
+        for (index, item) in records.iter_mut().enumerate() {
+            item.index = index;
+        }
+        assert_eq!(adjudication_queue(&records), vec![1, 3]);
+
+        let raw_verdicts = records[1].verdicts.clone();
+        assert!(records[1].resolve("dave", Some(false), Some("label is wrong".to_string())));
+        let resolution = records[1].resolution.clone().unwrap();
+        assert_eq!(resolution.adjudicator, "dave");
+        assert_eq!(resolution.annotation, Some(false));
+        assert_eq!(records[1].verdicts, raw_verdicts);
+        // Resolved images stay in the queue
+        assert_eq!(adjudication_queue(&records), vec![1, 3]);
+
+        let stats = compute_agreement(&records);
+        assert_eq!((stats.conflicts, stats.resolved), (2, 1));
+
+        assert!(records[1].resolve("dave", None, None));
+        assert_eq!(records[1].resolution, None);
+        assert!(!records[1].resolve("dave", None, None));
+    }
+
+    #[test]
+    fn test_merge_verdicts_keeps_newest() {
+        let verdict = |annotation: bool, last_updated: &str| Verdict {
+            annotation: Some(annotation),
+            comments: None,
+            last_updated: Some(last_updated.to_string()),
+        };
+        let mut local = Properties::default();
+        local.verdicts.insert(
+            "alice".to_string(),
+            verdict(true, "2024-01-02 10:00:00.0 +00:00"),
+        );
+        let mut remote = Properties::default();
+        remote.verdicts.insert(
+            "alice".to_string(),
+            verdict(false, "2024-01-01 10:00:00.0 +00:00"),
+        );
+        remote.verdicts.insert(
+            "bob".to_string(),
+            verdict(false, "2024-01-01 10:00:00.0 +00:00"),
+        );
+        assert!(merge_verdicts(&mut local, &remote));
+        assert_eq!(local.verdicts["alice"].annotation, Some(true));
+        assert_eq!(local.verdicts["bob"].annotation, Some(false));
+        assert!(!merge_verdicts(&mut local, &remote));
+    }
+
+    #[test]
+    fn test_merge_saved_keeps_changes_of_others() {
+        let resolution = Resolution {
+            adjudicator: "carol".to_string(),
+            annotation: Some(true),
+            rationale: None,
+            last_updated: Some("2024-01-02 10:00:00.0 +00:00".to_string()),
+        };
+        let base = Properties {
+            tags: BTreeSet::from(["blurry".to_string()]),
+            last_updated: Some("2024-01-01 10:00:00.0 +00:00".to_string()),
+            ..Default::default()
+        };
+        // Resolved meanwhile by the adjudicator, who also removed the tag
+        let stored = Properties {
+            resolution: Some(resolution.clone()),
+            last_updated: resolution.last_updated.clone(),
+            ..Default::default()
+        };
+        // Only the verdict was changed here
+        let mut record = Properties {
+            annotation: Some(false),
+            ..base.clone()
+        };
+        merge_saved(&mut record, Some(&base), &stored);
+        assert_eq!(record.resolution, Some(resolution.clone()));
+        assert!(record.tags.is_empty());
+        assert_eq!(record.annotation, Some(false));
+        assert_eq!(record.last_updated, resolution.last_updated);
+
+        // A resolution cleared here stays cleared
+        let base = stored.clone();
+        let mut record = Properties {
+            resolution: None,
+            ..stored.clone()
+        };
+        merge_saved(&mut record, Some(&base), &stored);
+        assert_eq!(record.resolution, None);
+
+        // Not saved when loaded, everything saved since is taken
+        let mut record = Properties::default();
+        merge_saved(&mut record, None, &stored);
+        assert_eq!(record.resolution, Some(resolution));
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::BTreeSet;
+
+use super::render_image::Properties;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BulkAction {
+    MarkAsCorrect,
+    // With the comment shared by all the images
+    MarkAsIncorrect(Option<String>),
+    ResetSelection,
+    AddTag(String),
+    RemoveTag(String),
+}
+
+impl BulkAction {
+    pub fn describe(&self) -> String {
+        match self {
+            Self::MarkAsCorrect => "marked as correct".to_string(),
+            Self::MarkAsIncorrect(_) => "marked as incorrect".to_string(),
+            Self::ResetSelection => "reset".to_string(),
+            Self::AddTag(tag) => format!("tagged with \"{}\"", tag),
+            Self::RemoveTag(tag) => format!("untagged from \"{}\"", tag),
+        }
+    }
+}
+
+// Parses ranges like "1-20, 25" into indices. Numbers are 1-based, as in the info bar.
+pub fn parse_index_ranges(ranges: &str, len: usize) -> Result<BTreeSet<usize>, String> {
+    let mut indices = BTreeSet::new();
+    for part in ranges
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+    {
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => (part, part),
+        };
+        let parse = |number: &str| match number.parse::<usize>() {
+            Ok(value) if (1..=len).contains(&value) => Ok(value - 1),
+            _ => Err(format!("Invalid image number: {} (1 to {})", number, len)),
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+        if start > end {
+            return Err(format!("Invalid range: {}", part));
+        }
+        indices.extend(start..=end);
+    }
+    Ok(indices)
+}
+
+// Applies the action to every selected record, the same way as editing them one by one.
+// Returns the number of records that changed.
+pub fn apply_bulk_action(
+    records: &mut [Properties],
+    selection: &BTreeSet<usize>,
+    action: &BulkAction,
+    annotator: Option<&str>,
+) -> usize {
+    records
+        .iter_mut()
+        .filter(|record| selection.contains(&record.index))
+        .map(|record| match action {
+            BulkAction::MarkAsCorrect => record.set_verdict(annotator, Some(true), None),
+            BulkAction::MarkAsIncorrect(comment) => {
+                record.set_verdict(annotator, Some(false), comment.clone())
+            }
+            BulkAction::ResetSelection => record.set_verdict(annotator, None, None),
+            BulkAction::AddTag(tag) => record.add_tag(tag),
+            BulkAction::RemoveTag(tag) => record.remove_tag(tag),
+        })
+        .filter(|changed| *changed)
+        .count()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn records(len: usize) -> Vec<Properties> {
+        (0..len)
+            .map(|index| Properties {
+                index,
+                image_path: format!("test/{}.jpg", index),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_index_ranges() {
+        assert_eq!(
+            parse_index_ranges("1-3, 5", 10).unwrap(),
+            BTreeSet::from([0, 1, 2, 4])
+        );
+        assert_eq!(parse_index_ranges(" ", 10).unwrap(), BTreeSet::new());
+        assert!(parse_index_ranges("0", 10).is_err());
+        assert!(parse_index_ranges("4-2", 10).is_err());
+        assert!(parse_index_ranges("9-11", 10).is_err());
+        assert!(parse_index_ranges("abc", 10).is_err());
+    }
+
+    #[test]
+    fn test_apply_bulk_action() {
+        let mut records = records(4);
+        let selection = BTreeSet::from([1, 2]);
+        let action = BulkAction::MarkAsIncorrect(Some("motion blur".to_string()));
+        assert_eq!(
+            apply_bulk_action(&mut records, &selection, &action, None),
+            2
+        );
+        assert_eq!(records[1].annotation, Some(false));
+        assert_eq!(records[2].comments, Some("motion blur".to_string()));
+        assert!(records[2].last_updated.is_some());
+        assert_eq!(records[0].annotation, None);
+        // Nothing changes the second time
+        assert_eq!(
+            apply_bulk_action(&mut records, &selection, &action, None),
+            0
+        );
+
+        let tag = BulkAction::AddTag("burst".to_string());
+        assert_eq!(apply_bulk_action(&mut records, &selection, &tag, None), 2);
+        assert!(records[1].tags.contains("burst"));
+        let untag = BulkAction::RemoveTag("burst".to_string());
+        assert_eq!(apply_bulk_action(&mut records, &selection, &untag, None), 2);
+        assert!(records[1].tags.is_empty());
+
+        let reset = BulkAction::ResetSelection;
+        assert_eq!(
+            apply_bulk_action(&mut records, &selection, &reset, Some("alice")),
+            0
+        );
+        assert_eq!(
+            apply_bulk_action(
+                &mut records,
+                &selection,
+                &BulkAction::MarkAsCorrect,
+                Some("alice")
+            ),
+            2
+        );
+        assert_eq!(records[1].verdicts["alice"].annotation, Some(true));
+        assert_eq!(records[1].annotation, Some(false));
+    }
+}
@@ -0,0 +1,393 @@
+use super::annotators::{
+    compute_agreement, current_annotator, init_review, ReviewRole, ReviewSettings,
+};
+use super::config::{AppConfig, StorageBackend};
+use super::crop::write_cropped_copies;
+use super::duplicates::{init_duplicate_threshold, MAX_THRESHOLD};
+use super::formats::init_frame_step;
+use super::render_image::Properties;
+use super::server::AnnotationServer;
+use super::sqlite_store::SqliteStore;
+use super::storage::{
+    copy_storage, open_storage, AnnotationStatus, AnnotationStorage, JsonStorage, StorageHandle,
+};
+use super::sync::{init_sync, SyncConfig};
+use super::thumbnails::{default_cache_dir, init_thumbnails, ThumbnailCache};
+use super::transform::write_corrected_copies;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const USAGE: &str = "Usage:
+    annotator-rust [--sqlite <db_path>] [--annotator <name> [--blind | --adjudicate]] [--sync-url <url> [--sync-token <token>] [--sync-interval <seconds>]] [--thumbnail-dir <dir>] [--frame-step <n>] [--duplicate-threshold <bits>]
+    annotator-rust [--sqlite <db_path>] [--annotator <name>] list <folder_path> [correct|incorrect|unreviewed]
+    annotator-rust [--sqlite <db_path>] agreement [<folder_path>]
+    annotator-rust [--sqlite <db_path>] [--addr <host:port>] [--frame-step <n>] serve <folder_path>
+    annotator-rust [--thumbnail-dir <dir>] prune-thumbnails
+    annotator-rust [--sqlite <db_path>] write-corrected <folder_path> <output_dir>
+    annotator-rust [--sqlite <db_path>] write-cropped <folder_path> <output_dir>
+    annotator-rust migrate <output.json> <db_path>
+    annotator-rust export <db_path> <output.json>
+    annotator-rust history <db_path> <image_path>";
+
+// Parses the command line. Returns the exit code if a command was run (or the
+// arguments were invalid), None if the GUI should be started.
+pub fn run(args: &[String]) -> Option<i32> {
+    let mut config = AppConfig::default();
+    let mut positional: Vec<&str> = vec![];
+    // The token can also come from the environment, to keep it out of the shell history
+    let mut sync_token = std::env::var("ANNOTATOR_SYNC_TOKEN").ok();
+    let mut sync_interval = None;
+    let mut server_addr = "127.0.0.1:8080".to_string();
+    let mut blind = false;
+    let mut role = ReviewRole::Annotator;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--sqlite" => match iter.next() {
+                Some(db_path) => config.storage = StorageBackend::Sqlite(db_path.to_string()),
+                None => return Some(usage_error("--sqlite expects a database path")),
+            },
+            "--sync-url" => match iter.next() {
+                Some(url) => config.sync = Some(SyncConfig::new(url)),
+                None => return Some(usage_error("--sync-url expects a URL")),
+            },
+            "--sync-token" => match iter.next() {
+                Some(token) => sync_token = Some(token.to_string()),
+                None => return Some(usage_error("--sync-token expects a token")),
+            },
+            "--sync-interval" => match iter.next().and_then(|secs| secs.parse().ok()) {
+                Some(secs) => sync_interval = Some(Duration::from_secs(secs)),
+                None => return Some(usage_error("--sync-interval expects a number of seconds")),
+            },
+            "--annotator" => match iter.next() {
+                Some(name) => {
+                    config.review = Some(ReviewSettings {
+                        annotator: name.to_string(),
+                        blind: false,
+                        role: ReviewRole::Annotator,
+                    })
+                }
+                None => return Some(usage_error("--annotator expects a name")),
+            },
+            "--blind" => blind = true,
+            "--adjudicate" => role = ReviewRole::Adjudicator,
+            "--thumbnail-dir" => match iter.next() {
+                Some(dir) => config.thumbnail_dir = Some(PathBuf::from(dir)),
+                None => return Some(usage_error("--thumbnail-dir expects a directory")),
+            },
+            "--frame-step" => match iter.next().and_then(|step| step.parse().ok()) {
+                Some(step) if step > 0 => config.frame_step = Some(step),
+                _ => return Some(usage_error("--frame-step expects a number greater than 0")),
+            },
+            "--duplicate-threshold" => match iter.next().and_then(|bits| bits.parse().ok()) {
+                Some(bits) if bits <= MAX_THRESHOLD => config.duplicate_threshold = Some(bits),
+                _ => {
+                    return Some(usage_error(&format!(
+                        "--duplicate-threshold expects a number of bits from 0 to {}",
+                        MAX_THRESHOLD
+                    )))
+                }
+            },
+            "--addr" => match iter.next() {
+                Some(addr) => server_addr = addr.to_string(),
+                None => return Some(usage_error("--addr expects an address like 0.0.0.0:8080")),
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Some(0);
+            }
+            _ => positional.push(arg),
+        }
+    }
+    if let Some(sync_config) = config.sync.as_mut() {
+        sync_config.token = sync_token;
+        if let Some(interval) = sync_interval {
+            sync_config.pull_interval = interval;
+        }
+    }
+
+    // Changes the items listed for a folder, so it applies to the GUI and the server alike
+    if let Some(step) = config.frame_step {
+        init_frame_step(step);
+    }
+
+    match config.review.as_mut() {
+        Some(review) => {
+            review.blind = blind;
+            review.role = role;
+        }
+        None if blind || role == ReviewRole::Adjudicator => {
+            return Some(usage_error("--blind and --adjudicate require --annotator"))
+        }
+        None => {}
+    }
+    // Statuses listed from the command line are the ones of this annotator
+    if let Some(review) = config.review.clone() {
+        init_review(review);
+    }
+
+    match positional.as_slice() {
+        [] => start_gui(config),
+        ["agreement"] => Some(agreement(&config, None)),
+        ["agreement", folder_path] => Some(agreement(&config, Some(folder_path))),
+        ["list", folder_path] => Some(list(&config, folder_path, None)),
+        ["list", folder_path, status] => match parse_status(status) {
+            Some(valid_status) => Some(list(&config, folder_path, Some(valid_status))),
+            None => Some(usage_error(&format!("Unknown status: {}", status))),
+        },
+        ["prune-thumbnails"] => Some(prune_thumbnails(&config)),
+        ["write-corrected", folder_path, output_dir] => Some(write_copies(
+            &config,
+            folder_path,
+            output_dir,
+            write_corrected_copies,
+        )),
+        ["write-cropped", folder_path, output_dir] => Some(write_copies(
+            &config,
+            folder_path,
+            output_dir,
+            write_cropped_copies,
+        )),
+        ["serve", folder_path] => Some(serve(&config, folder_path, &server_addr)),
+        ["migrate", json_path, db_path] => Some(match SqliteStore::open(db_path) {
+            Ok(store) => copy(&JsonStorage::new(json_path), &store, json_path, db_path),
+            Err(e) => open_error(db_path, e),
+        }),
+        ["export", db_path, json_path] => Some(match SqliteStore::open(db_path) {
+            Ok(store) => copy(&store, &JsonStorage::new(json_path), db_path, json_path),
+            Err(e) => open_error(db_path, e),
+        }),
+        ["history", db_path, image_path] => Some(history(db_path, image_path)),
+        _ => Some(usage_error(&format!("Unknown arguments: {:?}", positional))),
+    }
+}
+
+fn start_gui(config: AppConfig) -> Option<i32> {
+    if let Err(e) = StorageHandle::init(&config.storage) {
+        println!(
+            "Couldn't open the storage {:?}, error: {}",
+            config.storage, e
+        );
+        return Some(1);
+    }
+    if let Some(dir) = config.thumbnail_dir {
+        if let Err(e) = init_thumbnails(&dir) {
+            println!("Couldn't open the thumbnail cache {:?}, error: {}", dir, e);
+            return Some(1);
+        }
+    }
+    if let Some(threshold) = config.duplicate_threshold {
+        init_duplicate_threshold(threshold);
+    }
+    if let Some(sync_config) = config.sync {
+        if let Err(e) = init_sync(sync_config) {
+            println!("Couldn't set up the sync client, error: {}", e);
+            return Some(1);
+        }
+    }
+    None
+}
+
+fn usage_error(reason: &str) -> i32 {
+    println!("{}\n{}", reason, USAGE);
+    2
+}
+
+fn parse_status(status: &str) -> Option<AnnotationStatus> {
+    match status {
+        "correct" => Some(AnnotationStatus::Correct),
+        "incorrect" => Some(AnnotationStatus::Incorrect),
+        "unreviewed" => Some(AnnotationStatus::Unreviewed),
+        _ => None,
+    }
+}
+
+fn list(config: &AppConfig, folder_path: &str, status: Option<AnnotationStatus>) -> i32 {
+    let result = open_storage(&config.storage).and_then(|storage| match status {
+        Some(valid_status) => storage.query_by_status(folder_path, valid_status),
+        None => storage
+            .load_folder(folder_path)
+            .map(|records| records.unwrap_or_default()),
+    });
+    match result {
+        Ok(records) => {
+            // The verdict the status was matched against, then the adjudicator's resolution
+            let annotator = current_annotator();
+            for record in records {
+                let resolution = match &record.resolution {
+                    Some(resolution) => format!(
+                        "\tresolved by {}: {:?}\t{}",
+                        resolution.adjudicator,
+                        resolution.annotation,
+                        resolution.rationale.clone().unwrap_or_default()
+                    ),
+                    None => String::new(),
+                };
+                println!(
+                    "{}\t{:?}\t{}{}",
+                    record.image_path,
+                    record.effective_verdict(annotator),
+                    record.verdict_of(annotator).1.unwrap_or_default(),
+                    resolution
+                );
+            }
+            0
+        }
+        Err(e) => {
+            println!(
+                "Failed to read annotations of {}, error: {}",
+                folder_path, e
+            );
+            1
+        }
+    }
+}
+
+fn agreement(config: &AppConfig, folder_path: Option<&str>) -> i32 {
+    let result = open_storage(&config.storage).and_then(|storage| {
+        let folders = match folder_path {
+            Some(valid_path) => vec![valid_path.to_string()],
+            None => storage.list_folders()?,
+        };
+        let mut stats = vec![];
+        for folder in folders {
+            let records = storage.load_folder(&folder)?.unwrap_or_default();
+            stats.push((folder, compute_agreement(&records)));
+        }
+        Ok(stats)
+    });
+    match result {
+        Ok(stats) => {
+            for (folder, folder_stats) in stats {
+                println!("{}", folder);
+                println!("    Annotators: {}", folder_stats.annotators.join(", "));
+                println!("    {}", folder_stats.summary());
+                println!("    {}", folder_stats.adjudication_summary());
+                for line in folder_stats.cohen_summary() {
+                    println!("    {}", line);
+                }
+            }
+            0
+        }
+        Err(e) => {
+            println!("Failed to read annotations, error: {}", e);
+            1
+        }
+    }
+}
+
+fn prune_thumbnails(config: &AppConfig) -> i32 {
+    let dir = config
+        .thumbnail_dir
+        .clone()
+        .unwrap_or_else(default_cache_dir);
+    match ThumbnailCache::open(&dir).and_then(|cache| cache.prune()) {
+        Ok(removed) => {
+            println!("Removed {} stale thumbnails from {:?}", removed, dir);
+            0
+        }
+        Err(e) => {
+            println!("Failed to prune {:?}, error: {}", dir, e);
+            1
+        }
+    }
+}
+
+// Writes edited copies of the images with `write`, the original images are left untouched
+fn write_copies(
+    config: &AppConfig,
+    folder_path: &str,
+    output_dir: &str,
+    write: fn(&[Properties], &Path) -> (usize, Vec<String>),
+) -> i32 {
+    let records =
+        match open_storage(&config.storage).and_then(|storage| storage.load_folder(folder_path)) {
+            Ok(records) => records.unwrap_or_default(),
+            Err(e) => {
+                println!(
+                    "Failed to read annotations of {}, error: {}",
+                    folder_path, e
+                );
+                return 1;
+            }
+        };
+    if let Err(e) = std::fs::create_dir_all(output_dir) {
+        println!("Couldn't create {}, error: {}", output_dir, e);
+        return 1;
+    }
+    let (written, errors) = write(&records, Path::new(output_dir));
+    for error in errors.iter() {
+        println!("Error: {}", error);
+    }
+    println!("Wrote {} images to {}", written, output_dir);
+    match errors.is_empty() {
+        true => 0,
+        false => 1,
+    }
+}
+
+fn serve(config: &AppConfig, folder_path: &str, addr: &str) -> i32 {
+    let server = open_storage(&config.storage)
+        .and_then(|storage| AnnotationServer::bind(addr, folder_path, storage));
+    match server {
+        Ok(valid_server) => {
+            if let Some(local_addr) = valid_server.local_addr() {
+                println!("Serving {} on http://{}", folder_path, local_addr);
+            }
+            valid_server.run();
+            0
+        }
+        Err(e) => {
+            println!("Failed to serve {} on {}, error: {}", folder_path, addr, e);
+            1
+        }
+    }
+}
+
+fn open_error(path: &str, e: sqlx::Error) -> i32 {
+    println!("Failed to open {}, error: {}", path, e);
+    1
+}
+
+fn copy(
+    from: &dyn AnnotationStorage,
+    to: &dyn AnnotationStorage,
+    from_path: &str,
+    to_path: &str,
+) -> i32 {
+    match copy_storage(from, to) {
+        Ok(copied) => {
+            println!(
+                "Copied {} records from {} into {}",
+                copied, from_path, to_path
+            );
+            0
+        }
+        Err(e) => {
+            println!(
+                "Failed to copy {} into {}, error: {}",
+                from_path, to_path, e
+            );
+            1
+        }
+    }
+}
+
+fn history(db_path: &str, image_path: &str) -> i32 {
+    let result = SqliteStore::open(db_path).and_then(|store| store.history(image_path));
+    match result {
+        Ok(entries) => {
+            for entry in entries {
+                println!(
+                    "{}: annotation: {:?}, comments: {:?}",
+                    entry.changed_at, entry.annotation, entry.comments
+                );
+            }
+            0
+        }
+        Err(e) => {
+            println!("Failed to read {}, error: {}", db_path, e);
+            1
+        }
+    }
+}
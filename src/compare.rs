@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use iced_core::event::{self, Event};
+use iced_core::image::{self as core_image, Handle};
+use iced_core::widget::tree::{self, Tree};
+use iced_core::{
+    layout, mouse, renderer, Clipboard, Element, Layout, Length, Point, Rectangle, Shell, Size,
+    Vector, Widget,
+};
+
+const MIN_SCALE: f32 = 0.25;
+const MAX_SCALE: f32 = 10.0;
+const SCALE_STEP: f32 = 0.10;
+
+// Finds the file of `others` matching each image by name: the same file stem first
+// (`img.jpg` and `img.png`), then a stem with a suffix (`img_mask.png`), the shortest one
+// if there are several.
+pub fn pair_by_name(images: &[PathBuf], others: &[PathBuf]) -> Vec<Option<PathBuf>> {
+    let stem = |path: &Path| {
+        path.file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    };
+    // Every file of `others` by its stem, and by each part of its stem before a separator
+    let mut same_stem: HashMap<String, &PathBuf> = HashMap::new();
+    let mut with_suffix: HashMap<String, (usize, &PathBuf)> = HashMap::new();
+    for other in others {
+        let other_stem = stem(other);
+        for (position, _) in other_stem
+            .char_indices()
+            .filter(|(_, separator)| matches!(separator, '_' | '-' | '.'))
+        {
+            let shortest = with_suffix
+                .entry(other_stem[..position].to_string())
+                .or_insert((other_stem.len(), other));
+            if other_stem.len() < shortest.0 {
+                *shortest = (other_stem.len(), other);
+            }
+        }
+        same_stem.entry(other_stem).or_insert(other);
+    }
+    images
+        .iter()
+        .map(|image_path| {
+            let image_stem = stem(image_path);
+            same_stem
+                .get(&image_stem)
+                .copied()
+                .or_else(|| with_suffix.get(&image_stem).map(|(_, other)| *other))
+                .cloned()
+        })
+        .collect()
+}
+
+// Zoom and pan shared by the viewers of a comparison. The offset is in screen pixels from
+// the centre, so the same region is shown when both images have the same aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewTransform {
+    pub scale: f32,
+    pub offset: Vector,
+}
+
+impl Default for ViewTransform {
+    fn default() -> Self {
+        ViewTransform {
+            scale: 1.0,
+            offset: Vector::new(0.0, 0.0),
+        }
+    }
+}
+
+// Like `image::viewer`, but the zoom and pan live in the app, so that several viewers can
+// follow the same transform. Every change is sent with `on_change`.
+pub struct SyncedViewer<Message> {
+    handle: Handle,
+    transform: ViewTransform,
+    on_change: fn(ViewTransform) -> Message,
+    width: Length,
+    height: Length,
+}
+
+// Where the drag started, local to each viewer
+#[derive(Debug, Clone, Copy, Default)]
+struct DragState {
+    grabbed_at: Option<Point>,
+    starting_offset: Vector,
+}
+
+impl<Message> SyncedViewer<Message> {
+    pub fn new(
+        handle: Handle,
+        transform: ViewTransform,
+        on_change: fn(ViewTransform) -> Message,
+    ) -> Self {
+        SyncedViewer {
+            handle,
+            transform,
+            on_change,
+            width: Length::Fill,
+            height: Length::Fill,
+        }
+    }
+
+    pub fn width(mut self, width: impl Into<Length>) -> Self {
+        self.width = width.into();
+        self
+    }
+
+    pub fn height(mut self, height: impl Into<Length>) -> Self {
+        self.height = height.into();
+        self
+    }
+}
+
+// Size of the image fitted in `bounds`, then scaled
+fn scaled_size<Renderer>(renderer: &Renderer, handle: &Handle, scale: f32, bounds: Size) -> Size
+where
+    Renderer: core_image::Renderer<Handle = Handle>,
+{
+    let Size { width, height } = renderer.dimensions(handle);
+    let (width, height) = (width.max(1) as f32, height.max(1) as f32);
+    let fit = (bounds.width / width).min(bounds.height / height).min(1.0);
+    Size::new(width * fit * scale, height * fit * scale)
+}
+
+// The offset kept within the image, so that it can't be dragged out of view
+fn clamp_offset(offset: Vector, image_size: Size, bounds: Size) -> Vector {
+    let hidden_width = (image_size.width - bounds.width / 2.0).max(0.0).round();
+    let hidden_height = (image_size.height - bounds.height / 2.0).max(0.0).round();
+    Vector::new(
+        offset.x.clamp(-hidden_width, hidden_width),
+        offset.y.clamp(-hidden_height, hidden_height),
+    )
+}
+
+impl<Message, Renderer> Widget<Message, Renderer> for SyncedViewer<Message>
+where
+    Renderer: core_image::Renderer<Handle = Handle>,
+{
+    fn tag(&self) -> tree::Tag {
+        tree::Tag::of::<DragState>()
+    }
+
+    fn state(&self) -> tree::State {
+        tree::State::new(DragState::default())
+    }
+
+    fn width(&self) -> Length {
+        self.width
+    }
+
+    fn height(&self) -> Length {
+        self.height
+    }
+
+    fn layout(&self, renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        let Size { width, height } = renderer.dimensions(&self.handle);
+        let size = limits
+            .width(self.width)
+            .height(self.height)
+            .resolve(Size::new(width as f32, height as f32));
+        layout::Node::new(size)
+    }
+
+    fn on_event(
+        &mut self,
+        tree: &mut Tree,
+        event: Event,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        renderer: &Renderer,
+        _clipboard: &mut dyn Clipboard,
+        shell: &mut Shell<'_, Message>,
+        _viewport: &Rectangle,
+    ) -> event::Status {
+        let bounds = layout.bounds();
+        let state = tree.state.downcast_mut::<DragState>();
+        match event {
+            Event::Mouse(mouse::Event::WheelScrolled { delta }) => {
+                let Some(cursor_position) = cursor.position_over(bounds) else {
+                    return event::Status::Ignored;
+                };
+                let y = match delta {
+                    mouse::ScrollDelta::Lines { y, .. } | mouse::ScrollDelta::Pixels { y, .. } => y,
+                };
+                let previous_scale = self.transform.scale;
+                let scale = match y > 0.0 {
+                    true => previous_scale * (1.0 + SCALE_STEP),
+                    false => previous_scale / (1.0 + SCALE_STEP),
+                }
+                .clamp(MIN_SCALE, MAX_SCALE);
+                // Zoom around the cursor
+                let factor = scale / previous_scale - 1.0;
+                let offset = self.transform.offset
+                    + (cursor_position - bounds.center()) * factor
+                    + self.transform.offset * factor;
+                let image_size = scaled_size(renderer, &self.handle, scale, bounds.size());
+                shell.publish((self.on_change)(ViewTransform {
+                    scale,
+                    offset: clamp_offset(offset, image_size, bounds.size()),
+                }));
+                event::Status::Captured
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                let Some(cursor_position) = cursor.position_over(bounds) else {
+                    return event::Status::Ignored;
+                };
+                state.grabbed_at = Some(cursor_position);
+                state.starting_offset = self.transform.offset;
+                event::Status::Captured
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                match state.grabbed_at.take() {
+                    Some(_) => event::Status::Captured,
+                    None => event::Status::Ignored,
+                }
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) => {
+                let Some(origin) = state.grabbed_at else {
+                    return event::Status::Ignored;
+                };
+                let image_size =
+                    scaled_size(renderer, &self.handle, self.transform.scale, bounds.size());
+                let offset = state.starting_offset - (position - origin);
+                shell.publish((self.on_change)(ViewTransform {
+                    scale: self.transform.scale,
+                    offset: clamp_offset(offset, image_size, bounds.size()),
+                }));
+                event::Status::Captured
+            }
+            _ => event::Status::Ignored,
+        }
+    }
+
+    fn mouse_interaction(
+        &self,
+        tree: &Tree,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        _viewport: &Rectangle,
+        _renderer: &Renderer,
+    ) -> mouse::Interaction {
+        let state = tree.state.downcast_ref::<DragState>();
+        if state.grabbed_at.is_some() {
+            mouse::Interaction::Grabbing
+        } else if cursor.is_over(layout.bounds()) {
+            mouse::Interaction::Grab
+        } else {
+            mouse::Interaction::Idle
+        }
+    }
+
+    fn draw(
+        &self,
+        _tree: &Tree,
+        renderer: &mut Renderer,
+        _theme: &Renderer::Theme,
+        _style: &renderer::Style,
+        layout: Layout<'_>,
+        _cursor: mouse::Cursor,
+        _viewport: &Rectangle,
+    ) {
+        let bounds = layout.bounds();
+        let image_size = scaled_size(renderer, &self.handle, self.transform.scale, bounds.size());
+        let offset = clamp_offset(self.transform.offset, image_size, bounds.size());
+        let top_left = Point::new(
+            bounds.center_x() - image_size.width / 2.0 - offset.x,
+            bounds.center_y() - image_size.height / 2.0 - offset.y,
+        );
+        renderer.with_layer(bounds, |renderer| {
+            core_image::Renderer::draw(
+                renderer,
+                self.handle.clone(),
+                Rectangle::new(top_left, image_size),
+            )
+        });
+    }
+}
+
+impl<'a, Message, Renderer> From<SyncedViewer<Message>> for Element<'a, Message, Renderer>
+where
+    Renderer: 'a + core_image::Renderer<Handle = Handle>,
+    Message: 'a,
+{
+    fn from(viewer: SyncedViewer<Message>) -> Element<'a, Message, Renderer> {
+        Element::new(viewer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pair_by_name() {
+        let images: Vec<PathBuf> = ["a/img.jpg", "a/cat.jpg", "a/dog.jpg", "a/bird.jpg"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let others: Vec<PathBuf> = [
+            "b/img_mask.png",
+            "b/img.png",
+            "b/cat_mask_large.png",
+            "b/cat-mask.png",
+            "b/dogs.png",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(
+            pair_by_name(&images, &others),
+            vec![
+                Some(PathBuf::from("b/img.png")),
+                Some(PathBuf::from("b/cat-mask.png")),
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clamp_offset() {
+        let bounds = Size::new(100.0, 100.0);
+        // Fits in the viewer, nothing to pan
+        let offset = clamp_offset(Vector::new(30.0, -30.0), Size::new(40.0, 40.0), bounds);
+        assert_eq!(offset, Vector::new(0.0, 0.0));
+        let offset = clamp_offset(Vector::new(300.0, -20.0), Size::new(200.0, 200.0), bounds);
+        assert_eq!(offset, Vector::new(150.0, -20.0));
+    }
+}
@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+
+use super::annotators::ReviewSettings;
+use super::sync::SyncConfig;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum StorageBackend {
+    // Single JSON file at OUTPUT_PATH, fully rewritten on every export
+    #[default]
+    Json,
+    // SQLite database at the given path, written incrementally
+    Sqlite(String),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AppConfig {
+    pub storage: StorageBackend,
+    pub sync: Option<SyncConfig>,
+    // Multi-annotator mode, when an annotator name is given
+    pub review: Option<ReviewSettings>,
+    // Where thumbnails are kept, `thumbnails::default_cache_dir` if not set
+    pub thumbnail_dir: Option<PathBuf>,
+    // Only every Nth frame of animated images is annotated, every frame if not set
+    pub frame_step: Option<u32>,
+    // Bits that can differ between the hashes of near-duplicate images
+    pub duplicate_threshold: Option<u32>,
+}
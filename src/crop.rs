@@ -0,0 +1,475 @@
+use std::path::{Path, PathBuf};
+
+use iced_core::event::{self, Event};
+use iced_core::image::{self as core_image, Handle};
+use iced_core::widget::tree::{self, Tree};
+use iced_core::{
+    layout, mouse, renderer, Background, Clipboard, Color, Element, Layout, Length, Point,
+    Rectangle, Shell, Size, Widget,
+};
+use serde::{Deserialize, Serialize};
+
+use super::metadata::open_oriented;
+use super::render_image::Properties;
+use super::transform::corrected_path;
+
+// Region to keep, in pixels of the image as shown: after the EXIF orientation and the
+// recorded transform
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CropRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CropRegion {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} x {} at ({}, {})",
+            self.width, self.height, self.x, self.y
+        )
+    }
+}
+
+#[derive(PartialEq, Clone, Eq, Copy, Debug, Default)]
+pub enum AspectRatio {
+    #[default]
+    Free,
+    Square,
+    FourThree,
+    ThreeTwo,
+    SixteenNine,
+}
+
+impl AspectRatio {
+    pub const ALL: &'static [Self] = &[
+        Self::Free,
+        Self::Square,
+        Self::FourThree,
+        Self::ThreeTwo,
+        Self::SixteenNine,
+    ];
+
+    // Width over height, None if any
+    pub fn ratio(&self) -> Option<f32> {
+        match self {
+            Self::Free => None,
+            Self::Square => Some(1.0),
+            Self::FourThree => Some(4.0 / 3.0),
+            Self::ThreeTwo => Some(3.0 / 2.0),
+            Self::SixteenNine => Some(16.0 / 9.0),
+        }
+    }
+
+    pub fn as_display(&self) -> &str {
+        match self {
+            Self::Free => "Free",
+            Self::Square => "1:1",
+            Self::FourThree => "4:3",
+            Self::ThreeTwo => "3:2",
+            Self::SixteenNine => "16:9",
+        }
+    }
+}
+
+impl std::fmt::Display for AspectRatio {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_display())
+    }
+}
+
+// The region dragged from `start` to `end`, in image pixels. The end is kept in the image,
+// and moved towards the start to follow the aspect ratio.
+pub fn region_between(
+    start: Point,
+    end: Point,
+    ratio: Option<f32>,
+    image_size: Size,
+) -> CropRegion {
+    let end = Point::new(
+        end.x.clamp(0.0, image_size.width),
+        end.y.clamp(0.0, image_size.height),
+    );
+    let (mut width, mut height) = ((end.x - start.x).abs(), (end.y - start.y).abs());
+    if let Some(valid_ratio) = ratio {
+        if width > height * valid_ratio {
+            width = height * valid_ratio;
+        } else {
+            height = width / valid_ratio;
+        }
+    }
+    let left = match end.x < start.x {
+        true => start.x - width,
+        false => start.x,
+    };
+    let top = match end.y < start.y {
+        true => start.y - height,
+        false => start.y,
+    };
+    CropRegion {
+        x: left.round() as u32,
+        y: top.round() as u32,
+        width: width.round() as u32,
+        height: height.round() as u32,
+    }
+}
+
+// Shows the whole image, a new region is drawn by dragging over it and sent with
+// `on_change` when the mouse is released
+pub struct CropSelector<Message> {
+    handle: Handle,
+    crop: Option<CropRegion>,
+    aspect_ratio: AspectRatio,
+    on_change: fn(CropRegion) -> Message,
+    width: Length,
+    height: Length,
+}
+
+// The region being drawn, in image pixels
+#[derive(Debug, Clone, Copy, Default)]
+struct DrawState {
+    dragged_from: Option<Point>,
+    drawn: Option<CropRegion>,
+}
+
+impl<Message> CropSelector<Message> {
+    pub fn new(
+        handle: Handle,
+        crop: Option<CropRegion>,
+        aspect_ratio: AspectRatio,
+        on_change: fn(CropRegion) -> Message,
+    ) -> Self {
+        CropSelector {
+            handle,
+            crop,
+            aspect_ratio,
+            on_change,
+            width: Length::Fill,
+            height: Length::Fill,
+        }
+    }
+
+    pub fn width(mut self, width: impl Into<Length>) -> Self {
+        self.width = width.into();
+        self
+    }
+
+    pub fn height(mut self, height: impl Into<Length>) -> Self {
+        self.height = height.into();
+        self
+    }
+}
+
+// Where the image is drawn in `bounds`, fitted and centred, and its size in pixels
+fn image_area<Renderer>(
+    renderer: &Renderer,
+    handle: &Handle,
+    bounds: Rectangle,
+) -> (Rectangle, Size)
+where
+    Renderer: core_image::Renderer<Handle = Handle>,
+{
+    let Size { width, height } = renderer.dimensions(handle);
+    let image_size = Size::new(width.max(1) as f32, height.max(1) as f32);
+    let fit = (bounds.width / image_size.width)
+        .min(bounds.height / image_size.height)
+        .min(1.0);
+    let shown = Size::new(image_size.width * fit, image_size.height * fit);
+    let top_left = Point::new(
+        bounds.center_x() - shown.width / 2.0,
+        bounds.center_y() - shown.height / 2.0,
+    );
+    (Rectangle::new(top_left, shown), image_size)
+}
+
+impl<Message, Renderer> Widget<Message, Renderer> for CropSelector<Message>
+where
+    Renderer: core_image::Renderer<Handle = Handle>,
+{
+    fn tag(&self) -> tree::Tag {
+        tree::Tag::of::<DrawState>()
+    }
+
+    fn state(&self) -> tree::State {
+        tree::State::new(DrawState::default())
+    }
+
+    fn width(&self) -> Length {
+        self.width
+    }
+
+    fn height(&self) -> Length {
+        self.height
+    }
+
+    fn layout(&self, renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        let Size { width, height } = renderer.dimensions(&self.handle);
+        let size = limits
+            .width(self.width)
+            .height(self.height)
+            .resolve(Size::new(width as f32, height as f32));
+        layout::Node::new(size)
+    }
+
+    fn on_event(
+        &mut self,
+        tree: &mut Tree,
+        event: Event,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        renderer: &Renderer,
+        _clipboard: &mut dyn Clipboard,
+        shell: &mut Shell<'_, Message>,
+        _viewport: &Rectangle,
+    ) -> event::Status {
+        let (area, image_size) = image_area(renderer, &self.handle, layout.bounds());
+        let scale = image_size.width / area.width.max(1.0);
+        let to_image = |position: Point| {
+            Point::new((position.x - area.x) * scale, (position.y - area.y) * scale)
+        };
+        let state = tree.state.downcast_mut::<DrawState>();
+        match event {
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                let Some(cursor_position) = cursor.position_over(area) else {
+                    return event::Status::Ignored;
+                };
+                state.dragged_from = Some(to_image(cursor_position));
+                state.drawn = None;
+                event::Status::Captured
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) => {
+                let Some(start) = state.dragged_from else {
+                    return event::Status::Ignored;
+                };
+                state.drawn = Some(region_between(
+                    start,
+                    to_image(position),
+                    self.aspect_ratio.ratio(),
+                    image_size,
+                ));
+                event::Status::Captured
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                if state.dragged_from.take().is_none() {
+                    return event::Status::Ignored;
+                }
+                // A click without a drag keeps the current region
+                if let Some(drawn) = state
+                    .drawn
+                    .filter(|region| region.width > 0 && region.height > 0)
+                {
+                    shell.publish((self.on_change)(drawn));
+                }
+                event::Status::Captured
+            }
+            _ => event::Status::Ignored,
+        }
+    }
+
+    fn mouse_interaction(
+        &self,
+        _tree: &Tree,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        _viewport: &Rectangle,
+        _renderer: &Renderer,
+    ) -> mouse::Interaction {
+        match cursor.is_over(layout.bounds()) {
+            true => mouse::Interaction::Crosshair,
+            false => mouse::Interaction::Idle,
+        }
+    }
+
+    fn draw(
+        &self,
+        tree: &Tree,
+        renderer: &mut Renderer,
+        _theme: &Renderer::Theme,
+        _style: &renderer::Style,
+        layout: Layout<'_>,
+        _cursor: mouse::Cursor,
+        _viewport: &Rectangle,
+    ) {
+        let (area, image_size) = image_area(renderer, &self.handle, layout.bounds());
+        core_image::Renderer::draw(renderer, self.handle.clone(), area);
+        let state = tree.state.downcast_ref::<DrawState>();
+        // The one being drawn, then the one recorded
+        let region = match state.dragged_from {
+            Some(_) => state.drawn,
+            None => self.crop,
+        };
+        let Some(valid_region) = region else {
+            return;
+        };
+        let scale = area.width / image_size.width;
+        let selected = Rectangle {
+            x: area.x + valid_region.x as f32 * scale,
+            y: area.y + valid_region.y as f32 * scale,
+            width: valid_region.width as f32 * scale,
+            height: valid_region.height as f32 * scale,
+        };
+        // Drawn in a layer of its own to be above the image
+        renderer.with_layer(layout.bounds(), |renderer| {
+            // Shades what is cut out, above, below, left and right of the region
+            let shaded = [
+                Rectangle::new(area.position(), Size::new(area.width, selected.y - area.y)),
+                Rectangle::new(
+                    Point::new(area.x, selected.y + selected.height),
+                    Size::new(
+                        area.width,
+                        area.y + area.height - selected.y - selected.height,
+                    ),
+                ),
+                Rectangle::new(
+                    Point::new(area.x, selected.y),
+                    Size::new(selected.x - area.x, selected.height),
+                ),
+                Rectangle::new(
+                    Point::new(selected.x + selected.width, selected.y),
+                    Size::new(
+                        area.x + area.width - selected.x - selected.width,
+                        selected.height,
+                    ),
+                ),
+            ];
+            for bounds in shaded {
+                renderer.fill_quad(
+                    renderer::Quad {
+                        bounds,
+                        border_radius: 0.0.into(),
+                        border_width: 0.0,
+                        border_color: Color::TRANSPARENT,
+                    },
+                    Background::Color(Color::from_rgba(0.0, 0.0, 0.0, 0.5)),
+                );
+            }
+            renderer.fill_quad(
+                renderer::Quad {
+                    bounds: selected,
+                    border_radius: 0.0.into(),
+                    border_width: 2.0,
+                    border_color: Color::from_rgb(1.0, 0.8, 0.0),
+                },
+                Background::Color(Color::TRANSPARENT),
+            );
+        });
+    }
+}
+
+impl<'a, Message, Renderer> From<CropSelector<Message>> for Element<'a, Message, Renderer>
+where
+    Renderer: 'a + core_image::Renderer<Handle = Handle>,
+    Message: 'a,
+{
+    fn from(selector: CropSelector<Message>) -> Element<'a, Message, Renderer> {
+        Element::new(selector)
+    }
+}
+
+// Writes the cropped version of every image with a recorded crop, with its transform
+// applied first. Returns the number of images written and the errors.
+pub fn write_cropped_copies(records: &[Properties], output_dir: &Path) -> (usize, Vec<String>) {
+    let mut written = 0;
+    let mut errors = vec![];
+    for record in records {
+        let crop = match record.crop {
+            Some(valid_crop) => valid_crop,
+            None => continue,
+        };
+        let image_path = PathBuf::from(&record.image_path);
+        let output_path = corrected_path(&image_path, output_dir);
+        let transform = record.transform.unwrap_or_default();
+        match open_oriented(&image_path).and_then(|image| {
+            transform
+                .apply(image)
+                .crop_imm(crop.x, crop.y, crop.width, crop.height)
+                .save(&output_path)
+        }) {
+            Ok(_) => written += 1,
+            Err(e) => errors.push(format!("{}: {}", record.image_path, e)),
+        }
+    }
+    (written, errors)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::img_visualizer::transform::ImageTransform;
+
+    #[test]
+    fn test_region_between() {
+        let image_size = Size::new(200.0, 100.0);
+        let region = |start: (f32, f32), end: (f32, f32), ratio: AspectRatio| {
+            region_between(
+                Point::new(start.0, start.1),
+                Point::new(end.0, end.1),
+                ratio.ratio(),
+                image_size,
+            )
+        };
+        assert_eq!(
+            region((10.0, 10.0), (50.0, 30.0), AspectRatio::Free),
+            CropRegion {
+                x: 10,
+                y: 10,
+                width: 40,
+                height: 20
+            }
+        );
+        // Dragged up and to the left, past the edge of the image
+        assert_eq!(
+            region((50.0, 50.0), (-20.0, 30.0), AspectRatio::Free),
+            CropRegion {
+                x: 0,
+                y: 30,
+                width: 50,
+                height: 20
+            }
+        );
+        // The shorter side decides the size
+        assert_eq!(
+            region((10.0, 10.0), (90.0, 40.0), AspectRatio::Square),
+            CropRegion {
+                x: 10,
+                y: 10,
+                width: 30,
+                height: 30
+            }
+        );
+        assert_eq!(
+            region((100.0, 90.0), (0.0, 0.0), AspectRatio::SixteenNine),
+            CropRegion {
+                x: 0,
+                y: 34,
+                width: 100,
+                height: 56
+            }
+        );
+    }
+
+    #[test]
+    fn test_write_cropped_copies() {
+        let folder = std::env::temp_dir().join("annotator_test_crop");
+        let output_dir = folder.join("cropped");
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let image_path = folder.join("wide.png");
+        image::RgbImage::new(30, 20).save(&image_path).unwrap();
+        let records = vec![Properties {
+            image_path: image_path.to_string_lossy().to_string(),
+            // Cropped after the rotation, in a 20 x 30 image
+            transform: Some(ImageTransform::default().rotated(90)),
+            crop: Some(CropRegion {
+                x: 5,
+                y: 10,
+                width: 10,
+                height: 15,
+            }),
+            ..Default::default()
+        }];
+        assert_eq!(write_cropped_copies(&records, &output_dir), (1, vec![]));
+        let cropped = image::open(output_dir.join("wide.png")).unwrap();
+        assert_eq!((cropped.width(), cropped.height()), (10, 15));
+    }
+}
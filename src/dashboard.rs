@@ -0,0 +1,263 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDate;
+
+use super::annotators::{current_annotator, parse_timestamp};
+use super::render_image::{AnnotatedStore, Properties};
+use super::storage::{AnnotationStorage, StorageHandle};
+
+// Number of phrases listed for the incorrect images
+const TOP_PHRASES: usize = 5;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FolderStats {
+    pub folder_path: String,
+    pub total: usize,
+    pub correct: usize,
+    pub incorrect: usize,
+    pub unreviewed: usize,
+    // Files that are not images, they can't be reviewed
+    pub invalid: usize,
+    // Images reviewed per day, by their last update
+    pub reviews_per_day: Vec<(NaiveDate, usize)>,
+    // Comments of incorrect images, most common first
+    pub common_phrases: Vec<(String, usize)>,
+}
+
+impl FolderStats {
+    // Share of the valid images with a verdict
+    pub fn progress(&self) -> f32 {
+        let reviewable = self.total - self.invalid;
+        match reviewable {
+            0 => 0.0,
+            _ => (self.correct + self.incorrect) as f32 / reviewable as f32,
+        }
+    }
+
+    pub fn reviews_per_active_day(&self) -> Option<f32> {
+        if self.reviews_per_day.is_empty() {
+            return None;
+        }
+        let reviewed: usize = self.reviews_per_day.iter().map(|(_, count)| count).sum();
+        Some(reviewed as f32 / self.reviews_per_day.len() as f32)
+    }
+}
+
+// Lowercase with single spaces, so "Blurry " and "blurry" count as the same phrase
+fn normalize_phrase(comment: &str) -> String {
+    comment
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+pub fn compute_folder_stats(
+    folder_path: &str,
+    records: &[Properties],
+    is_valid: impl Fn(&Properties) -> bool,
+) -> FolderStats {
+    let mut stats = FolderStats {
+        folder_path: folder_path.to_string(),
+        total: records.len(),
+        ..Default::default()
+    };
+    let mut per_day: BTreeMap<NaiveDate, usize> = BTreeMap::new();
+    let mut phrases: HashMap<String, usize> = HashMap::new();
+    for record in records {
+        if !is_valid(record) {
+            stats.invalid += 1;
+            continue;
+        }
+        let annotation = record.effective_verdict(current_annotator());
+        let comments = record.verdict_of(current_annotator()).1;
+        match annotation {
+            Some(true) => stats.correct += 1,
+            Some(false) => stats.incorrect += 1,
+            None => {
+                stats.unreviewed += 1;
+                continue;
+            }
+        }
+        if let Some(date) = record
+            .last_updated
+            .as_deref()
+            .and_then(parse_timestamp)
+            .map(|timestamp| timestamp.date_naive())
+        {
+            *per_day.entry(date).or_default() += 1;
+        }
+        if annotation == Some(false) {
+            let phrase = normalize_phrase(&comments.unwrap_or_default());
+            if !phrase.is_empty() {
+                *phrases.entry(phrase).or_default() += 1;
+            }
+        }
+    }
+    stats.reviews_per_day = per_day.into_iter().collect();
+    let mut common_phrases: Vec<(String, usize)> = phrases.into_iter().collect();
+    common_phrases.sort_by(|first, second| second.1.cmp(&first.1).then(first.0.cmp(&second.0)));
+    common_phrases.truncate(TOP_PHRASES);
+    stats.common_phrases = common_phrases;
+    stats
+}
+
+// Stats of every stored folder, sorted by folder path. The folders in `json_obj` may have
+// changes that aren't saved yet, their records are used instead of the stored ones. Images
+// not checked yet by the quality pass count as valid.
+pub fn compute_dashboard(
+    storage: &dyn AnnotationStorage,
+    json_obj: &AnnotatedStore,
+    validity: &HashMap<PathBuf, bool>,
+) -> Vec<FolderStats> {
+    let is_valid = |record: &Properties| {
+        validity
+            .get(Path::new(&record.image_path))
+            .copied()
+            .unwrap_or(true)
+    };
+    let mut folder_paths = storage.list_folders().unwrap_or_else(|e| {
+        println!("Couldn't list the saved folders, error: {}", e);
+        vec![]
+    });
+    folder_paths.extend(json_obj.image_to_properties_map.keys().cloned());
+    folder_paths.sort();
+    folder_paths.dedup();
+    folder_paths
+        .iter()
+        .filter_map(|folder_path| {
+            let records = match json_obj.image_to_properties_map.get(folder_path) {
+                Some(records) => records.clone(),
+                None => match storage.load_folder(folder_path) {
+                    Ok(records) => records.unwrap_or_default(),
+                    Err(e) => {
+                        println!("Couldn't load {}, error: {}", folder_path, e);
+                        vec![]
+                    }
+                },
+            };
+            match records.is_empty() {
+                true => None,
+                false => Some(compute_folder_stats(folder_path, &records, is_valid)),
+            }
+        })
+        .collect()
+}
+
+pub async fn dashboard_in_background(
+    storage: StorageHandle,
+    json_obj: AnnotatedStore,
+    validity: HashMap<PathBuf, bool>,
+) -> Vec<FolderStats> {
+    tokio::task::spawn_blocking(move || compute_dashboard(&*storage, &json_obj, &validity))
+        .await
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::img_visualizer::storage::JsonStorage;
+
+    fn record(annotation: Option<bool>, comments: Option<&str>, last_updated: &str) -> Properties {
+        Properties {
+            image_path: "test/sample.jpg".to_string(),
+            annotation,
+            comments: comments.map(|comment| comment.to_string()),
+            last_updated: Some(last_updated.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compute_folder_stats() {
+        let records = vec![
+            record(Some(true), None, "2024-01-01 10:00:00.0 +00:00"),
+            record(Some(false), Some("Blurry "), "2024-01-01 11:00:00.0 +00:00"),
+            record(Some(false), Some("blurry"), "2024-01-02 10:00:00.0 +00:00"),
+            record(
+                Some(false),
+                Some("wrong label"),
+                "2024-01-02 10:30:00.0 +00:00",
+            ),
+            record(None, None, "2024-01-03 10:00:00.0 +00:00"),
+            Properties {
+                image_path: "test/notes.txt".to_string(),
+                ..Default::default()
+            },
+        ];
+        let stats = compute_folder_stats("test", &records, |record| {
+            record.image_path.ends_with(".jpg")
+        });
+        assert_eq!(
+            (
+                stats.total,
+                stats.correct,
+                stats.incorrect,
+                stats.unreviewed,
+                stats.invalid
+            ),
+            (6, 1, 3, 1, 1)
+        );
+        assert_eq!(stats.progress(), 0.8);
+        assert_eq!(
+            stats.reviews_per_day,
+            vec![
+                (NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(), 2),
+                (NaiveDate::from_ymd_opt(2024, 1, 2).unwrap(), 2),
+            ]
+        );
+        assert_eq!(stats.reviews_per_active_day(), Some(2.0));
+        assert_eq!(
+            stats.common_phrases,
+            vec![("blurry".to_string(), 2), ("wrong label".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_compute_dashboard() {
+        let json_path = std::env::temp_dir().join("annotator_test_dashboard.json");
+        let _ = std::fs::remove_file(&json_path);
+        let storage = JsonStorage::new(json_path.to_str().unwrap());
+        let saved = vec![
+            record(Some(true), None, "2024-01-01 10:00:00.0 +00:00"),
+            record(None, None, "2024-01-01 10:00:00.0 +00:00"),
+        ];
+        storage.save_folder("b", &saved).unwrap();
+        storage.save_folder("c", &saved).unwrap();
+        // Folder c is open with a verdict that isn't saved yet
+        let mut json_obj = AnnotatedStore::default();
+        let mut opened = saved.clone();
+        opened[1].annotation = Some(false);
+        json_obj
+            .image_to_properties_map
+            .insert("c".to_string(), opened.clone());
+        json_obj
+            .image_to_properties_map
+            .insert("a".to_string(), opened);
+        let validity = HashMap::from([(PathBuf::from("test/sample.jpg"), true)]);
+
+        let dashboard = compute_dashboard(&storage, &json_obj, &validity);
+        let counts: Vec<(&str, usize, usize, usize)> = dashboard
+            .iter()
+            .map(|stats| {
+                (
+                    stats.folder_path.as_str(),
+                    stats.correct,
+                    stats.incorrect,
+                    stats.unreviewed,
+                )
+            })
+            .collect();
+        assert_eq!(counts, vec![("a", 1, 1, 0), ("b", 1, 0, 1), ("c", 1, 1, 0)]);
+        let _ = std::fs::remove_file(json_path);
+    }
+
+    #[test]
+    fn test_compute_folder_stats_empty() {
+        let stats = compute_folder_stats("test", &[], |_| true);
+        assert_eq!(stats.progress(), 0.0);
+        assert_eq!(stats.reviews_per_active_day(), None);
+    }
+}
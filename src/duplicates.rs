@@ -0,0 +1,199 @@
+use std::path::{Path, PathBuf};
+
+use image::imageops::FilterType;
+use once_cell::sync::OnceCell;
+
+use super::bulk::BulkAction;
+use super::metadata::open_oriented;
+use super::render_image::validate_image;
+
+// Images hashed by each background worker
+pub const HASH_BATCH_SIZE: usize = 32;
+// Bits that can differ between the hashes of two near-duplicates, out of 64
+pub const DEFAULT_THRESHOLD: u32 = 5;
+pub const MAX_THRESHOLD: u32 = 20;
+pub const DUPLICATE_TAG: &str = "duplicate";
+
+static THRESHOLD: OnceCell<u32> = OnceCell::new();
+
+pub fn init_duplicate_threshold(threshold: u32) {
+    let _ = THRESHOLD.set(threshold.min(MAX_THRESHOLD));
+}
+
+pub fn duplicate_threshold() -> u32 {
+    THRESHOLD.get().copied().unwrap_or(DEFAULT_THRESHOLD)
+}
+
+// Difference hash: every bit tells whether a pixel is brighter than its right neighbour in a
+// 9 x 8 grayscale version of the image. Resizing, recompression and small colour changes
+// keep most bits.
+pub fn perceptual_hash(path: &Path) -> Result<u64, String> {
+    validate_image(path).map_err(|e| e.to_string())?;
+    let small = open_oriented(path)
+        .map_err(|e| e.to_string())?
+        .resize_exact(9, 8, FilterType::Triangle)
+        .to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x, y).0[0] > small.get_pixel(x + 1, y).0[0];
+            hash = (hash << 1) | brighter as u64;
+        }
+    }
+    Ok(hash)
+}
+
+pub fn hash_distance(first: u64, second: u64) -> u32 {
+    (first ^ second).count_ones()
+}
+
+// Groups of images whose hashes are at most `threshold` bits apart, directly or through
+// other images of the group. Images without a hash are left out. Every group is sorted and
+// the groups are ordered by their first image.
+pub fn group_duplicates(hashes: &[Option<u64>], threshold: u32) -> Vec<Vec<usize>> {
+    let mut group_of: Vec<usize> = (0..hashes.len()).collect();
+    fn root(group_of: &mut [usize], idx: usize) -> usize {
+        let mut current = idx;
+        while group_of[current] != current {
+            group_of[current] = group_of[group_of[current]];
+            current = group_of[current];
+        }
+        current
+    }
+    let hashed: Vec<(usize, u64)> = hashes
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, hash)| Some((idx, (*hash)?)))
+        .collect();
+    for (position, (idx, hash)) in hashed.iter().enumerate() {
+        for (other_idx, other_hash) in hashed[position + 1..].iter() {
+            if hash_distance(*hash, *other_hash) <= threshold {
+                let (first, second) = (root(&mut group_of, *idx), root(&mut group_of, *other_idx));
+                group_of[first.max(second)] = first.min(second);
+            }
+        }
+    }
+    let mut groups: Vec<Vec<usize>> = vec![vec![]; hashes.len()];
+    for (idx, _) in hashed.iter() {
+        let group = root(&mut group_of, *idx);
+        groups[group].push(*idx);
+    }
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+// For every image, the first image of its group if it is another one
+pub fn original_of(groups: &[Vec<usize>], len: usize) -> Vec<Option<usize>> {
+    let mut originals = vec![None; len];
+    for group in groups {
+        for idx in group.iter().skip(1) {
+            if let Some(original) = originals.get_mut(*idx) {
+                *original = Some(group[0]);
+            }
+        }
+    }
+    originals
+}
+
+// Applied to every image of a group but the first one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicateAction {
+    MarkAsIncorrect,
+    Tag,
+}
+
+impl DuplicateAction {
+    pub fn describe(&self) -> &str {
+        match self {
+            Self::MarkAsIncorrect => "marked as incorrect",
+            Self::Tag => "tagged as duplicates",
+        }
+    }
+
+    pub fn bulk_action(&self, original: &Path) -> BulkAction {
+        match self {
+            Self::MarkAsIncorrect => BulkAction::MarkAsIncorrect(Some(format!(
+                "Duplicate of {}",
+                original.file_name().unwrap_or_default().to_string_lossy()
+            ))),
+            Self::Tag => BulkAction::AddTag(DUPLICATE_TAG.to_string()),
+        }
+    }
+}
+
+// None for the files that can't be decoded
+pub async fn hash_in_background(image_paths: Vec<PathBuf>) -> Vec<(PathBuf, Option<u64>)> {
+    tokio::task::spawn_blocking(move || {
+        image_paths
+            .into_iter()
+            .map(|path| {
+                let hash = perceptual_hash(&path).ok();
+                (path, hash)
+            })
+            .collect()
+    })
+    .await
+    .unwrap_or_default()
+}
+
+// The images are compared pairwise, which takes a while on large folders
+pub async fn group_in_background(hashes: Vec<Option<u64>>, threshold: u32) -> Vec<Vec<usize>> {
+    tokio::task::spawn_blocking(move || group_duplicates(&hashes, threshold))
+        .await
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_group_duplicates() {
+        let hashes = vec![
+            Some(0b1111_0000),
+            None,
+            Some(0xFFFF_0000_0000),
+            // 2 bits from the first one, and 2 more from the next one
+            Some(0b1111_0011),
+            Some(0b1100_0011),
+            Some(0xFFFF_0000_0001),
+        ];
+        assert_eq!(
+            group_duplicates(&hashes, 2),
+            vec![vec![0, 3, 4], vec![2, 5]]
+        );
+        assert_eq!(group_duplicates(&hashes, 0), Vec::<Vec<usize>>::new());
+        assert_eq!(
+            original_of(&group_duplicates(&hashes, 2), hashes.len()),
+            vec![None, None, None, Some(0), Some(0), Some(2)]
+        );
+    }
+
+    #[test]
+    fn test_perceptual_hash() {
+        let folder = std::env::temp_dir().join("annotator_test_duplicates");
+        std::fs::create_dir_all(&folder).unwrap();
+        let gradient = image::RgbImage::from_fn(64, 48, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8])
+        });
+        let original_path = folder.join("original.png");
+        gradient.save(&original_path).unwrap();
+        // Smaller and saved as JPEG
+        let copy_path = folder.join("copy.jpg");
+        image::DynamicImage::ImageRgb8(gradient.clone())
+            .resize(32, 24, FilterType::Triangle)
+            .save(&copy_path)
+            .unwrap();
+        let flipped_path = folder.join("flipped.png");
+        image::imageops::flip_horizontal(&gradient)
+            .save(&flipped_path)
+            .unwrap();
+
+        let original = perceptual_hash(&original_path).unwrap();
+        let copy = perceptual_hash(&copy_path).unwrap();
+        let flipped = perceptual_hash(&flipped_path).unwrap();
+        assert!(hash_distance(original, copy) <= DEFAULT_THRESHOLD);
+        assert!(hash_distance(original, flipped) > DEFAULT_THRESHOLD);
+        assert!(perceptual_hash(&folder.join("missing.png")).is_err());
+    }
+}
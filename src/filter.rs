@@ -0,0 +1,224 @@
+use std::path::{Path, PathBuf};
+
+use super::fingerprint::FileStatus;
+use super::render_image::Properties;
+
+#[derive(PartialEq, Clone, Eq, Copy, Debug, Default)]
+pub enum StatusFilter {
+    #[default]
+    All,
+    Unreviewed,
+    Incorrect,
+    WithComments,
+    Invalid,
+    WithWarnings,
+    Changed,
+}
+
+impl StatusFilter {
+    pub const ALL: &'static [Self] = &[
+        Self::All,
+        Self::Unreviewed,
+        Self::Incorrect,
+        Self::WithComments,
+        Self::Invalid,
+        Self::WithWarnings,
+        Self::Changed,
+    ];
+
+    pub fn as_display(&self) -> &str {
+        match self {
+            Self::All => "All images",
+            Self::Unreviewed => "Not selected yet",
+            Self::Incorrect => "Incorrect",
+            Self::WithComments => "With comments",
+            Self::Invalid => "Invalid",
+            Self::WithWarnings => "With quality warnings",
+            Self::Changed => "Changed since review",
+        }
+    }
+}
+
+impl std::fmt::Display for StatusFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_display())
+    }
+}
+
+// Restricts Next/Previous to the images matching both the status and the file name search
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImageFilter {
+    pub status: StatusFilter,
+    // Case insensitive part of the file name
+    pub search: String,
+}
+
+impl ImageFilter {
+    pub fn is_active(&self) -> bool {
+        self.status != StatusFilter::All || !self.search.trim().is_empty()
+    }
+
+    // `annotation` and `comments` are the verdict shown in the viewer
+    pub fn matches(
+        &self,
+        path: &Path,
+        annotation: Option<bool>,
+        comments: Option<&str>,
+        valid: bool,
+        has_warnings: bool,
+        changed: bool,
+    ) -> bool {
+        let status_matches = match self.status {
+            StatusFilter::All => true,
+            StatusFilter::Unreviewed => valid && annotation.is_none(),
+            StatusFilter::Incorrect => annotation == Some(false),
+            StatusFilter::WithComments => comments.is_some_and(|comment| !comment.is_empty()),
+            StatusFilter::Invalid => !valid,
+            StatusFilter::WithWarnings => has_warnings,
+            StatusFilter::Changed => changed,
+        };
+        let search = self.search.trim().to_lowercase();
+        let name_matches = search.is_empty()
+            || path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().to_lowercase().contains(&search));
+        status_matches && name_matches
+    }
+
+    // Indices of the matching images, in order
+    pub fn matching_indices(
+        &self,
+        all_images: &[PathBuf],
+        records: &[Properties],
+        annotator: Option<&str>,
+        is_valid: impl Fn(usize) -> bool,
+    ) -> Vec<usize> {
+        all_images
+            .iter()
+            .enumerate()
+            .filter(|(idx, path)| {
+                let record = records.get(*idx);
+                let annotation = record.and_then(|record| record.effective_verdict(annotator));
+                let comments = record.and_then(|record| record.verdict_of(annotator).1);
+                let has_warnings = record.is_some_and(|record| !record.quality_warnings.is_empty());
+                let changed =
+                    record.is_some_and(|record| record.file_status == Some(FileStatus::Changed));
+                self.matches(
+                    path,
+                    annotation,
+                    comments.as_deref(),
+                    is_valid(*idx),
+                    has_warnings,
+                    changed,
+                )
+            })
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+}
+
+pub fn next_match(matches: &[usize], curr_idx: usize) -> Option<usize> {
+    matches.iter().copied().find(|idx| *idx > curr_idx)
+}
+
+pub fn previous_match(matches: &[usize], curr_idx: usize) -> Option<usize> {
+    matches.iter().rev().copied().find(|idx| *idx < curr_idx)
+}
+
+// Parses an image number as shown in the info bar (1-based) into an index
+pub fn parse_image_number(number: &str, len: usize) -> Result<usize, String> {
+    match number.trim().parse::<usize>() {
+        Ok(value) if (1..=len).contains(&value) => Ok(value - 1),
+        _ => Err(format!("Enter a number from 1 to {}", len)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::img_visualizer::quality::QualityWarning;
+
+    fn records() -> (Vec<PathBuf>, Vec<Properties>) {
+        let verdicts = [
+            ("cat_1.jpg", Some(true), None, vec![]),
+            (
+                "cat_2.jpg",
+                Some(false),
+                Some("blurry"),
+                vec![QualityWarning::Blurry],
+            ),
+            ("dog_1.jpg", None, None, vec![]),
+            ("notes.txt", None, None, vec![]),
+            (
+                "Dog_2.jpg",
+                Some(true),
+                Some(""),
+                vec![QualityWarning::TooSmall],
+            ),
+        ];
+        verdicts
+            .iter()
+            .enumerate()
+            .map(|(index, (name, annotation, comments, quality_warnings))| {
+                let path = PathBuf::from("test").join(name);
+                let record = Properties {
+                    index,
+                    image_path: path.to_string_lossy().to_string(),
+                    annotation: *annotation,
+                    comments: comments.map(|comment| comment.to_string()),
+                    quality_warnings: quality_warnings.clone(),
+                    // The first image was replaced after it was reviewed
+                    file_status: (index == 0).then_some(FileStatus::Changed),
+                    ..Default::default()
+                };
+                (path, record)
+            })
+            .unzip()
+    }
+
+    #[test]
+    fn test_matching_indices() {
+        let (all_images, records) = records();
+        let is_valid = |idx: usize| idx != 3;
+        let matching = |status: StatusFilter, search: &str| {
+            let filter = ImageFilter {
+                status,
+                search: search.to_string(),
+            };
+            filter.matching_indices(&all_images, &records, None, is_valid)
+        };
+        assert_eq!(matching(StatusFilter::All, ""), vec![0, 1, 2, 3, 4]);
+        assert_eq!(matching(StatusFilter::Unreviewed, ""), vec![2]);
+        assert_eq!(matching(StatusFilter::Incorrect, ""), vec![1]);
+        assert_eq!(matching(StatusFilter::WithComments, ""), vec![1]);
+        assert_eq!(matching(StatusFilter::Invalid, ""), vec![3]);
+        assert_eq!(matching(StatusFilter::WithWarnings, ""), vec![1, 4]);
+        assert_eq!(matching(StatusFilter::WithWarnings, "dog"), vec![4]);
+        assert_eq!(matching(StatusFilter::Changed, ""), vec![0]);
+        assert_eq!(matching(StatusFilter::All, " DOG "), vec![2, 4]);
+        assert_eq!(
+            matching(StatusFilter::Unreviewed, "cat"),
+            Vec::<usize>::new()
+        );
+        assert!(!ImageFilter::default().is_active());
+    }
+
+    #[test]
+    fn test_next_and_previous_match() {
+        let matches = vec![1, 4, 6];
+        assert_eq!(next_match(&matches, 0), Some(1));
+        assert_eq!(next_match(&matches, 4), Some(6));
+        assert_eq!(next_match(&matches, 6), None);
+        assert_eq!(previous_match(&matches, 5), Some(4));
+        assert_eq!(previous_match(&matches, 1), None);
+        assert_eq!(previous_match(&[], 0), None);
+    }
+
+    #[test]
+    fn test_parse_image_number() {
+        assert_eq!(parse_image_number(" 3 ", 5), Ok(2));
+        assert!(parse_image_number("0", 5).is_err());
+        assert!(parse_image_number("6", 5).is_err());
+        assert!(parse_image_number("first", 5).is_err());
+    }
+}
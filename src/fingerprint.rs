@@ -0,0 +1,234 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::formats::split_item;
+use super::render_image::Properties;
+
+// The file an image was reviewed in, recorded with every verdict
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileFingerprint {
+    pub sha256: String,
+    pub size: u64,
+    // Seconds since the Unix epoch
+    pub modified: u64,
+}
+
+impl FileFingerprint {
+    // Pages and frames share the fingerprint of their file
+    pub fn of(image_path: &Path) -> io::Result<FileFingerprint> {
+        let file = split_item(image_path).0;
+        let metadata = std::fs::metadata(&file)?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(&file)?, &mut hasher)?;
+        Ok(FileFingerprint {
+            sha256: format!("{:x}", hasher.finalize()),
+            size: metadata.len(),
+            modified: modified_secs(&metadata),
+        })
+    }
+
+    // Size and modification time only, the content is hashed later by `verify_record`
+    pub fn unhashed(image_path: &Path) -> io::Result<FileFingerprint> {
+        let metadata = std::fs::metadata(split_item(image_path).0)?;
+        Ok(FileFingerprint {
+            sha256: String::new(),
+            size: metadata.len(),
+            modified: modified_secs(&metadata),
+        })
+    }
+
+    pub fn is_hashed(&self) -> bool {
+        !self.sha256.is_empty()
+    }
+
+    pub fn same_metadata(&self, metadata: &std::fs::Metadata) -> bool {
+        metadata.len() == self.size && modified_secs(metadata) == self.modified
+    }
+}
+
+fn modified_secs(metadata: &std::fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    // The content is not the one that was reviewed
+    Changed,
+    Missing,
+}
+
+impl FileStatus {
+    pub fn as_display(&self) -> &str {
+        match self {
+            Self::Changed => "The file changed since it was reviewed, please review it again",
+            Self::Missing => "The file is missing",
+        }
+    }
+}
+
+// Compares the file with the fingerprint recorded with the verdict. The content is only
+// hashed again when the size or modification time differ; a file that was only touched
+// gets its new modification time. A fingerprint not hashed yet is hashed on the first check
+// that finds the file as it was reviewed.
+pub fn verify_record(record: &mut Properties) -> Option<FileStatus> {
+    let file = split_item(Path::new(&record.image_path)).0;
+    let Some(fingerprint) = record.fingerprint.as_mut() else {
+        // Nothing to compare with before the first verdict, but a file that came back is
+        // no longer missing
+        record.file_status = record.file_status.filter(|_| !file.exists());
+        return record.file_status;
+    };
+    let status = match std::fs::metadata(&file) {
+        Err(_) => Some(FileStatus::Missing),
+        Ok(metadata) if fingerprint.same_metadata(&metadata) => {
+            if !fingerprint.is_hashed() {
+                if let Ok(current) = FileFingerprint::of(Path::new(&record.image_path)) {
+                    *fingerprint = current;
+                }
+            }
+            None
+        }
+        // Modified before it was hashed, the content reviewed is unknown
+        Ok(_) if !fingerprint.is_hashed() => Some(FileStatus::Changed),
+        Ok(_) => match FileFingerprint::of(Path::new(&record.image_path)) {
+            Ok(current) if current.sha256 == fingerprint.sha256 => {
+                *fingerprint = current;
+                None
+            }
+            Ok(_) => Some(FileStatus::Changed),
+            Err(_) => Some(FileStatus::Missing),
+        },
+    };
+    record.file_status = status;
+    status
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FileChanges {
+    pub changed: usize,
+    pub missing: usize,
+}
+
+impl FileChanges {
+    pub fn is_empty(&self) -> bool {
+        self.changed == 0 && self.missing == 0
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} reviewed images changed on disk, {} are missing",
+            self.changed, self.missing
+        )
+    }
+}
+
+pub fn verify_records<'a>(records: impl IntoIterator<Item = &'a mut Properties>) -> FileChanges {
+    let mut changes = FileChanges::default();
+    for record in records {
+        match verify_record(record) {
+            Some(FileStatus::Changed) => changes.changed += 1,
+            Some(FileStatus::Missing) => changes.missing += 1,
+            None => {}
+        }
+    }
+    changes
+}
+
+// Saved records of images that are no longer in the folder, kept so that saving the folder
+// doesn't drop their verdicts
+pub fn missing_records(records: &[Properties], saved_records: &[Properties]) -> Vec<Properties> {
+    let listed: HashSet<&str> = records
+        .iter()
+        .map(|record| record.image_path.as_str())
+        .collect();
+    saved_records
+        .iter()
+        .filter(|saved| !listed.contains(saved.image_path.as_str()))
+        .map(|saved| Properties {
+            file_status: Some(FileStatus::Missing),
+            ..saved.clone()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_verify_record() {
+        let folder = std::env::temp_dir().join("annotator_test_fingerprint");
+        std::fs::create_dir_all(&folder).unwrap();
+        let image_path = folder.join("image.png");
+        std::fs::write(&image_path, b"first version").unwrap();
+        let mut record = Properties {
+            image_path: image_path.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        // Nothing to compare with before the first verdict
+        record.file_status = Some(FileStatus::Missing);
+        assert_eq!(verify_record(&mut record), None);
+
+        assert!(record.set_verdict(None, Some(true), None));
+        assert!(!record.fingerprint.as_ref().unwrap().is_hashed());
+        assert_eq!(verify_record(&mut record), None);
+        let fingerprint = record.fingerprint.clone().unwrap();
+        assert!(fingerprint.is_hashed());
+        assert_eq!(fingerprint.size, 13);
+
+        // Same content with another modification time
+        record.fingerprint.as_mut().unwrap().modified -= 10;
+        assert_eq!(verify_record(&mut record), None);
+        assert_eq!(record.fingerprint, Some(fingerprint));
+
+        std::fs::write(&image_path, b"second version").unwrap();
+        assert_eq!(verify_record(&mut record), Some(FileStatus::Changed));
+        assert_eq!(record.file_status, Some(FileStatus::Changed));
+        // Marking it again is the new review
+        assert!(record.set_verdict(None, Some(true), None));
+        assert_eq!(record.file_status, None);
+        // Changed again before the new review was hashed
+        std::fs::write(&image_path, b"third, longer version").unwrap();
+        assert_eq!(verify_record(&mut record), Some(FileStatus::Changed));
+        assert!(record.set_verdict(None, Some(true), None));
+        assert_eq!(verify_record(&mut record), None);
+
+        std::fs::remove_file(&image_path).unwrap();
+        let mut records = [record];
+        assert_eq!(
+            verify_records(records.iter_mut()),
+            FileChanges {
+                changed: 0,
+                missing: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_missing_records() {
+        let record = |name: &str| Properties {
+            image_path: name.to_string(),
+            annotation: Some(false),
+            ..Default::default()
+        };
+        let records = vec![record("a.png"), record("b.png")];
+        let saved_records = vec![record("b.png"), record("c.png")];
+        let missing = missing_records(&records, &saved_records);
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].image_path, "c.png");
+        assert_eq!(missing[0].annotation, Some(false));
+        assert_eq!(missing[0].file_status, Some(FileStatus::Missing));
+    }
+}
@@ -0,0 +1,450 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::error::{DecodingError, ImageFormatHint};
+use image::{AnimationDecoder, DynamicImage, ImageBuffer, ImageDecoder, ImageError, ImageResult};
+use once_cell::sync::OnceCell;
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::ColorType;
+
+// Every page of a multi-page TIFF and every frame of an animated GIF or WebP is its own item
+// in the folder. The first one keeps the path of the file, the others get a marker and
+// their index after it.
+const PAGE_MARKER: &str = "#page=";
+const FRAME_MARKER: &str = "#frame=";
+
+static FRAME_STEP: OnceCell<u32> = OnceCell::new();
+
+// Only every `step`th frame of animated images is annotated, set once at startup
+pub fn init_frame_step(step: u32) {
+    let _ = FRAME_STEP.set(step.max(1));
+}
+
+pub fn frame_step() -> u32 {
+    FRAME_STEP.get().copied().unwrap_or(1)
+}
+
+#[derive(PartialEq, Clone, Eq, Copy, Debug)]
+pub enum PartKind {
+    Page,
+    Frame,
+}
+
+impl PartKind {
+    fn marker(&self) -> &'static str {
+        match self {
+            Self::Page => PAGE_MARKER,
+            Self::Frame => FRAME_MARKER,
+        }
+    }
+
+    pub fn as_display(&self) -> &str {
+        match self {
+            Self::Page => "Page",
+            Self::Frame => "Frame",
+        }
+    }
+
+    // The kind of parts the file is split into, if any
+    pub fn of_file(file: &Path) -> Option<PartKind> {
+        match imghdr::from_file(file) {
+            Ok(Some(imghdr::Type::Tiff)) => Some(Self::Page),
+            Ok(Some(imghdr::Type::Gif | imghdr::Type::Webp)) => Some(Self::Frame),
+            _ => None,
+        }
+    }
+}
+
+pub fn part_path(file: &Path, kind: PartKind, index: u32) -> PathBuf {
+    match index {
+        0 => file.to_path_buf(),
+        _ => PathBuf::from(format!(
+            "{}{}{}",
+            file.to_string_lossy(),
+            kind.marker(),
+            index
+        )),
+    }
+}
+
+// The part an item stands for, None for the first one which keeps the path of the file
+pub fn part_of(path: &Path) -> Option<(PartKind, u32)> {
+    let path_str = path.to_string_lossy();
+    [PartKind::Page, PartKind::Frame]
+        .into_iter()
+        .find_map(|kind| {
+            let (_, index) = path_str.rsplit_once(kind.marker())?;
+            Some((kind, index.parse::<u32>().ok()?))
+        })
+}
+
+// The file an item comes from, and the index of its page or frame
+pub fn split_item(path: &Path) -> (PathBuf, u32) {
+    match part_of(path) {
+        Some((kind, index)) => {
+            let path_str = path.to_string_lossy();
+            let file = path_str.rsplit_once(kind.marker()).unwrap_or_default().0;
+            (PathBuf::from(file), index)
+        }
+        None => (path.to_path_buf(), 0),
+    }
+}
+
+// The page or frame of the item at `idx`, the first part of a file included
+pub fn item_part(all_images: &[PathBuf], idx: usize) -> Option<(PartKind, u32)> {
+    let path = all_images.get(idx)?;
+    part_of(path).or_else(|| {
+        let (kind, _) = part_of(all_images.get(idx + 1)?)?;
+        (split_item(&all_images[idx + 1]).0 == *path).then_some((kind, 0))
+    })
+}
+
+// Whether the item has to be decoded and tone-mapped here rather than by iced, which only
+// shows the first page of a file and cuts everything down to 8 bits
+pub fn needs_tone_mapping(path: &Path) -> bool {
+    let (file, index) = split_item(path);
+    if index > 0 {
+        return true;
+    }
+    match imghdr::from_file(&file) {
+        Ok(Some(imghdr::Type::Tiff | imghdr::Type::Exr | imghdr::Type::Rgbe)) => true,
+        Ok(Some(imghdr::Type::Png)) => File::open(&file)
+            .ok()
+            .and_then(|opened| PngDecoder::new(BufReader::new(opened)).ok())
+            .is_some_and(|decoder| {
+                decoder.color_type().bytes_per_pixel() > decoder.color_type().channel_count()
+            }),
+        _ => false,
+    }
+}
+
+fn count_pages(path: &Path) -> u32 {
+    let mut decoder = match File::open(path)
+        .ok()
+        .and_then(|file| Decoder::new(BufReader::new(file)).ok())
+    {
+        Some(valid_decoder) => valid_decoder,
+        None => return 1,
+    };
+    let mut pages = 1;
+    while decoder.more_images() && decoder.next_image().is_ok() {
+        pages += 1;
+    }
+    pages
+}
+
+// Walks the chunk headers of a WebP file, an animated one has an ANMF chunk per frame. The
+// WebP decoder would decode the first frame as soon as it is created, and every frame to
+// count them.
+fn count_webp_frames(mut file: impl Read + Seek) -> std::io::Result<u32> {
+    let mut header = [0; 12];
+    file.read_exact(&mut header)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WEBP" {
+        return Err(std::io::ErrorKind::InvalidData.into());
+    }
+    let mut frames = 0;
+    let mut chunk = [0; 8];
+    while file.read_exact(&mut chunk).is_ok() {
+        if &chunk[0..4] == b"ANMF" {
+            frames += 1;
+        }
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as i64;
+        // Chunks are padded to an even size
+        file.seek(SeekFrom::Current(size + size % 2))?;
+    }
+    Ok(frames)
+}
+
+fn count_frames(path: &Path) -> u32 {
+    let file = match File::open(path) {
+        Ok(valid_file) => BufReader::new(valid_file),
+        Err(_) => return 1,
+    };
+    let frames = match imghdr::from_file(path) {
+        // Only reads the frame headers
+        Ok(Some(imghdr::Type::Gif)) => gif::DecodeOptions::new()
+            .read_info(file)
+            .map(|mut decoder| {
+                let mut frames = 0;
+                while let Ok(Some(_)) = decoder.next_frame_info() {
+                    frames += 1;
+                }
+                frames
+            })
+            .unwrap_or(1),
+        Ok(Some(imghdr::Type::Webp)) => count_webp_frames(file).unwrap_or(1),
+        _ => 1,
+    };
+    frames.max(1)
+}
+
+// Number of pages or frames of the file, 1 for anything but multi-page and animated images
+pub fn count_parts(path: &Path) -> u32 {
+    match PartKind::of_file(path) {
+        Some(PartKind::Page) => count_pages(path),
+        Some(PartKind::Frame) => count_frames(path),
+        None => 1,
+    }
+}
+
+// Adds the other pages of multi-page files, and every `frame_step`th frame of animated
+// images, right after them
+pub fn expand_items(files: Vec<PathBuf>, frame_step: u32) -> Vec<PathBuf> {
+    files
+        .into_iter()
+        .flat_map(|file| {
+            let parts = count_parts(&file);
+            let (kind, step) = match PartKind::of_file(&file) {
+                Some(PartKind::Frame) => (PartKind::Frame, frame_step.max(1)),
+                _ => (PartKind::Page, 1),
+            };
+            (0..parts)
+                .step_by(step as usize)
+                .map(move |index| part_path(&file, kind, index))
+        })
+        .collect()
+}
+
+// Indices of the items of the same file as the item at `idx`, in order
+pub fn sibling_items(all_images: &[PathBuf], idx: usize) -> Vec<usize> {
+    let file = match all_images.get(idx) {
+        Some(valid_path) => split_item(valid_path).0,
+        None => return vec![],
+    };
+    let mut first = idx;
+    while first > 0 && split_item(&all_images[first - 1]).0 == file {
+        first -= 1;
+    }
+    (first..all_images.len())
+        .take_while(|sibling| split_item(&all_images[*sibling]).0 == file)
+        .collect()
+}
+
+fn decoding_error(message: String) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Exact(image::ImageFormat::Tiff),
+        message,
+    ))
+}
+
+// Decodes the page or frame an item stands for, the first one through the `image` crate
+pub fn open_item(path: &Path) -> ImageResult<DynamicImage> {
+    match part_of(path) {
+        Some((PartKind::Page, page)) => open_page(&split_item(path).0, page),
+        Some((PartKind::Frame, frame)) => open_frame(&split_item(path).0, frame),
+        None => image::open(path),
+    }
+}
+
+// Frames are composed over the previous ones, as they are shown when playing the animation
+fn open_frame(file: &Path, frame: u32) -> ImageResult<DynamicImage> {
+    let reader = BufReader::new(File::open(file).map_err(ImageError::IoError)?);
+    let mut frames = match imghdr::from_file(file).map_err(ImageError::IoError)? {
+        Some(imghdr::Type::Webp) => WebPDecoder::new(reader)?.into_frames(),
+        _ => GifDecoder::new(reader)?.into_frames(),
+    };
+    match frames.nth(frame as usize) {
+        Some(decoded) => Ok(DynamicImage::ImageRgba8(decoded?.into_buffer())),
+        None => Err(ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::PathExtension(file.to_path_buf()),
+            format!("No frame {} in the file", frame),
+        ))),
+    }
+}
+
+fn open_page(file: &Path, page: u32) -> ImageResult<DynamicImage> {
+    let mut decoder = File::open(file)
+        .map_err(ImageError::IoError)
+        .and_then(|opened| {
+            Decoder::new(BufReader::new(opened)).map_err(|e| decoding_error(e.to_string()))
+        })?;
+    decoder
+        .seek_to_image(page as usize)
+        .map_err(|e| decoding_error(e.to_string()))?;
+    let (width, height) = decoder
+        .dimensions()
+        .map_err(|e| decoding_error(e.to_string()))?;
+    let color_type = decoder
+        .colortype()
+        .map_err(|e| decoding_error(e.to_string()))?;
+    let pixels = decoder
+        .read_image()
+        .map_err(|e| decoding_error(e.to_string()))?;
+    let unsupported = || decoding_error(format!("Unsupported page format: {:?}", color_type));
+    let image = match (color_type, pixels) {
+        (ColorType::Gray(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8)
+        }
+        (ColorType::GrayA(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA8)
+        }
+        (ColorType::RGB(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8)
+        }
+        (ColorType::RGBA(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+        }
+        (ColorType::Gray(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma16)
+        }
+        (ColorType::GrayA(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA16)
+        }
+        (ColorType::RGB(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb16)
+        }
+        (ColorType::RGBA(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba16)
+        }
+        (ColorType::Gray(32), DecodingResult::F32(data)) => {
+            let rgb = data.iter().flat_map(|value| [*value; 3]).collect();
+            ImageBuffer::from_raw(width, height, rgb).map(DynamicImage::ImageRgb32F)
+        }
+        (ColorType::RGB(32), DecodingResult::F32(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb32F)
+        }
+        (ColorType::RGBA(32), DecodingResult::F32(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba32F)
+        }
+        _ => None,
+    };
+    image.ok_or_else(unsupported)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::codecs::gif::GifEncoder;
+    use image::{Frame, RgbaImage};
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    #[test]
+    fn test_item_paths() {
+        let file = PathBuf::from("scans/scan.tif");
+        assert_eq!(part_path(&file, PartKind::Page, 0), file);
+        assert_eq!(
+            split_item(&part_path(&file, PartKind::Page, 2)),
+            (file.clone(), 2)
+        );
+        let animation = PathBuf::from("clips/clip.gif");
+        let third_frame = part_path(&animation, PartKind::Frame, 3);
+        assert_eq!(split_item(&third_frame), (animation, 3));
+        assert_eq!(part_of(&third_frame), Some((PartKind::Frame, 3)));
+        assert_eq!(split_item(&file), (file, 0));
+        assert_eq!(
+            split_item(Path::new("notes#page=two.txt")),
+            (PathBuf::from("notes#page=two.txt"), 0)
+        );
+    }
+
+    #[test]
+    fn test_expand_and_open_pages() {
+        let folder = std::env::temp_dir().join("annotator_test_pages");
+        std::fs::create_dir_all(&folder).unwrap();
+        let tiff_path = folder.join("scan.tif");
+        {
+            let mut encoder = TiffEncoder::new(File::create(&tiff_path).unwrap()).unwrap();
+            encoder
+                .write_image::<colortype::Gray16>(2, 1, &[0, 1000])
+                .unwrap();
+            encoder
+                .write_image::<colortype::Gray16>(2, 1, &[4000, 65535])
+                .unwrap();
+        }
+        let png_path = folder.join("photo.png");
+        image::RgbImage::new(2, 2).save(&png_path).unwrap();
+        assert!(needs_tone_mapping(&tiff_path));
+        assert!(!needs_tone_mapping(&png_path));
+
+        let all_images = expand_items(vec![tiff_path.clone(), png_path.clone()], 1);
+        assert_eq!(
+            all_images,
+            vec![
+                tiff_path.clone(),
+                part_path(&tiff_path, PartKind::Page, 1),
+                png_path
+            ]
+        );
+        assert_eq!(sibling_items(&all_images, 1), vec![0, 1]);
+        assert_eq!(sibling_items(&all_images, 2), vec![2]);
+        assert_eq!(item_part(&all_images, 0), Some((PartKind::Page, 0)));
+        assert_eq!(item_part(&all_images, 2), None);
+
+        let second_page = open_item(&all_images[1]).unwrap();
+        assert_eq!(second_page.to_luma16().into_raw(), vec![4000, 65535]);
+        assert!(open_item(&part_path(&tiff_path, PartKind::Page, 5)).is_err());
+    }
+
+    #[test]
+    fn test_expand_and_open_frames() {
+        let folder = std::env::temp_dir().join("annotator_test_frames");
+        std::fs::create_dir_all(&folder).unwrap();
+        let gif_path = folder.join("clip.gif");
+        {
+            let mut encoder = GifEncoder::new(File::create(&gif_path).unwrap());
+            // The red channel of each frame is its index times 50
+            let frames = (0..5u8).map(|index| {
+                Frame::new(RgbaImage::from_pixel(
+                    2,
+                    2,
+                    image::Rgba([index * 50, 0, 0, 255]),
+                ))
+            });
+            encoder.encode_frames(frames).unwrap();
+        }
+        assert_eq!(count_parts(&gif_path), 5);
+
+        let all_images = expand_items(vec![gif_path.clone()], 2);
+        assert_eq!(
+            all_images,
+            vec![
+                gif_path.clone(),
+                part_path(&gif_path, PartKind::Frame, 2),
+                part_path(&gif_path, PartKind::Frame, 4)
+            ]
+        );
+        assert_eq!(item_part(&all_images, 0), Some((PartKind::Frame, 0)));
+        assert_eq!(sibling_items(&all_images, 2), vec![0, 1, 2]);
+        assert!(needs_tone_mapping(&all_images[1]));
+
+        let fifth_frame = open_item(&all_images[2]).unwrap().to_rgba8();
+        assert_eq!(fifth_frame.get_pixel(0, 0).0, [200, 0, 0, 255]);
+        assert!(open_item(&part_path(&gif_path, PartKind::Frame, 9)).is_err());
+    }
+
+    #[test]
+    fn test_count_webp_frames() {
+        let chunk = |fourcc: &[u8], size: u32| {
+            let mut bytes = [fourcc, &size.to_le_bytes()].concat();
+            bytes.resize(8 + (size + size % 2) as usize, 0);
+            bytes
+        };
+        let body = [
+            chunk(b"VP8X", 10),
+            chunk(b"ANIM", 6),
+            chunk(b"ANMF", 31),
+            chunk(b"ANMF", 31),
+            chunk(b"ANMF", 40),
+        ]
+        .concat();
+        let webp = [
+            b"RIFF".as_slice(),
+            &(body.len() as u32 + 4).to_le_bytes(),
+            b"WEBP",
+            &body,
+        ]
+        .concat();
+        assert_eq!(count_webp_frames(std::io::Cursor::new(&webp)).unwrap(), 3);
+
+        let folder = std::env::temp_dir().join("annotator_test_webp_frames");
+        std::fs::create_dir_all(&folder).unwrap();
+        let webp_path = folder.join("clip.webp");
+        std::fs::write(&webp_path, &webp).unwrap();
+        assert_eq!(count_parts(&webp_path), 3);
+        assert!(count_webp_frames(std::io::Cursor::new(b"not a webp file")).is_err());
+    }
+}
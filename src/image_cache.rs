@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+use iced_widget::image::Handle;
+use lru::LruCache;
+
+use super::adjustments::DisplayAdjustments;
+use super::metadata::open_oriented;
+use super::render_image::validate_image;
+use super::transform::ImageTransform;
+
+// Number of images decoded ahead on each side of the current one
+pub const PREFETCH_DISTANCE: usize = 3;
+// Decoded images kept in memory, the prefetched ones plus a few recently seen
+const CACHE_CAPACITY: usize = 16;
+
+// The decoded pixels, or why the file can't be shown
+pub type Decoded = Result<Handle, String>;
+
+// How an image is decoded, it is decoded again when this changes. The adjustments are the
+// same for every image, the transform is the one recorded for the image.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rendering {
+    pub adjustments: DisplayAdjustments,
+    pub transform: ImageTransform,
+}
+
+pub struct ImageCache {
+    // With how they were decoded
+    entries: LruCache<PathBuf, (Rendering, Decoded)>,
+    // Sent to a worker and not back yet
+    pending: HashMap<PathBuf, Rendering>,
+    // Applied to the images decoded from now on
+    adjustments: DisplayAdjustments,
+}
+
+impl Default for ImageCache {
+    fn default() -> Self {
+        ImageCache {
+            entries: LruCache::new(NonZeroUsize::new(CACHE_CAPACITY).unwrap()),
+            pending: HashMap::new(),
+            adjustments: DisplayAdjustments::default(),
+        }
+    }
+}
+
+impl Clone for ImageCache {
+    fn clone(&self) -> Self {
+        let mut cache = ImageCache::default();
+        // Oldest first, so the clone keeps the same order
+        for (path, decoded) in self.entries.iter().rev() {
+            cache.entries.put(path.clone(), decoded.clone());
+        }
+        cache.pending = self.pending.clone();
+        cache.adjustments = self.adjustments;
+        cache
+    }
+}
+
+impl std::fmt::Debug for ImageCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImageCache")
+            .field("cached", &self.entries.len())
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+impl ImageCache {
+    // None while the image is still being decoded. After the adjustments change, the
+    // previous version is shown until the new one is ready.
+    pub fn peek(&self, path: &Path) -> Option<&Decoded> {
+        self.entries.peek(path).map(|(_, decoded)| decoded)
+    }
+
+    pub fn adjustments(&self) -> DisplayAdjustments {
+        self.adjustments
+    }
+
+    // The cached images are decoded again with the new adjustments
+    pub fn set_adjustments(&mut self, adjustments: DisplayAdjustments) {
+        self.adjustments = adjustments;
+    }
+
+    // Paths to decode so that the image at `curr_idx` and its neighbours are ready, with the
+    // transform of each image given by `transform_of`. They are marked as pending, the
+    // current image comes first.
+    pub fn missing(
+        &mut self,
+        all_images: &[PathBuf],
+        curr_idx: usize,
+        transform_of: impl Fn(usize) -> ImageTransform,
+    ) -> Vec<(PathBuf, Rendering)> {
+        let mut wanted = vec![curr_idx];
+        for distance in 1..=PREFETCH_DISTANCE {
+            wanted.push(curr_idx + distance);
+            if let Some(idx) = curr_idx.checked_sub(distance) {
+                wanted.push(idx);
+            }
+        }
+        let mut missing = vec![];
+        // The current image is touched last so that it is the most recently used
+        for idx in wanted.iter().rev() {
+            let path = match all_images.get(*idx) {
+                Some(valid_path) => valid_path,
+                None => continue,
+            };
+            if let Some(rendering) = self.request(path, transform_of(*idx)) {
+                missing.push((path.clone(), rendering));
+            }
+        }
+        missing.reverse();
+        missing
+    }
+
+    // Marks the path as pending if it is neither decoded nor being decoded with the current
+    // adjustments and this transform. Returns how to decode it if it has to be.
+    pub fn request(&mut self, path: &Path, transform: ImageTransform) -> Option<Rendering> {
+        let wanted = Rendering {
+            adjustments: self.adjustments,
+            transform,
+        };
+        let cached = self.entries.get(path).map(|(rendering, _)| *rendering);
+        if cached == Some(wanted) || self.pending.get(path) == Some(&wanted) {
+            return None;
+        }
+        self.pending.insert(path.to_path_buf(), wanted);
+        Some(wanted)
+    }
+
+    // Outdated results are only kept if there is nothing else to show
+    pub fn insert(&mut self, path: PathBuf, rendering: Rendering, decoded: Decoded) {
+        let latest = match self.pending.get(&path) {
+            Some(wanted) => *wanted == rendering,
+            None => rendering.adjustments == self.adjustments,
+        };
+        if latest {
+            self.pending.remove(&path);
+        }
+        if latest || !self.entries.contains(&path) {
+            self.entries.put(path, (rendering, decoded));
+        }
+    }
+}
+
+pub fn decode_image(path: &Path, rendering: &Rendering) -> Decoded {
+    validate_image(path).map_err(|e| e.to_string())?;
+    let oriented = open_oriented(path).map_err(|e| e.to_string())?;
+    let pixels = rendering
+        .adjustments
+        .render(rendering.transform.apply(oriented));
+    Ok(Handle::from_pixels(
+        pixels.width(),
+        pixels.height(),
+        pixels.into_raw(),
+    ))
+}
+
+// Decodes on a blocking worker of the app's runtime, keeping the UI responsive
+pub async fn decode_in_background(path: PathBuf, rendering: Rendering) -> Decoded {
+    tokio::task::spawn_blocking(move || decode_image(&path, &rendering))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_missing_prefetches_neighbours_once() {
+        let all_images: Vec<PathBuf> = (0..10)
+            .map(|idx| PathBuf::from(format!("test/{}.jpg", idx)))
+            .collect();
+        let no_transform = |_| ImageTransform::default();
+        let mut cache = ImageCache::default();
+        let missing = cache.missing(&all_images, 1, no_transform);
+        assert_eq!(missing[0], (all_images[1].clone(), Rendering::default()));
+        assert_eq!(missing.len(), 5);
+        // Already pending
+        assert!(cache.missing(&all_images, 1, no_transform).is_empty());
+
+        let rendering = Rendering::default();
+        cache.insert(all_images[2].clone(), rendering, Err("Invalid".to_string()));
+        assert_eq!(
+            cache.peek(&all_images[2]),
+            Some(&Err("Invalid".to_string()))
+        );
+        assert_eq!(
+            cache.missing(&all_images, 2, no_transform),
+            vec![(all_images[5].clone(), rendering)]
+        );
+        assert!(cache.peek(&all_images[1]).is_none());
+
+        // Decoded again with new adjustments, the old version is shown meanwhile
+        let inverted = DisplayAdjustments {
+            invert: true,
+            ..Default::default()
+        };
+        cache.set_adjustments(inverted);
+        assert!(cache
+            .request(&all_images[2], ImageTransform::default())
+            .is_some());
+        cache.insert(all_images[2].clone(), rendering, Err("Stale".to_string()));
+        assert_eq!(
+            cache.peek(&all_images[2]),
+            Some(&Err("Invalid".to_string()))
+        );
+        assert!(cache
+            .request(&all_images[2], ImageTransform::default())
+            .is_none());
+
+        // And when the transform of the image changes
+        let rotated = ImageTransform::default().rotated(90);
+        assert_eq!(
+            cache.request(&all_images[2], rotated),
+            Some(Rendering {
+                adjustments: inverted,
+                transform: rotated,
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_image() {
+        let folder = std::env::temp_dir().join("annotator_test_decode_image");
+        std::fs::create_dir_all(&folder).unwrap();
+        let image_path = folder.join("sample.png");
+        image::RgbImage::new(4, 2).save(&image_path).unwrap();
+        let text_path = folder.join("notes.txt");
+        std::fs::write(&text_path, "not an image").unwrap();
+
+        let rendering = Rendering::default();
+        assert!(decode_image(&image_path, &rendering).is_ok());
+        assert!(decode_image(&text_path, &rendering).is_err());
+    }
+}
@@ -1,17 +1,96 @@
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
 
 use iced::{
-    theme,
+    executor, theme,
     widget::{button, column, container, horizontal_space, row, scrollable},
-    Element, Length, Renderer, Sandbox,
+    Application, Command, Element, Length, Renderer, Subscription,
 };
 
+use self::adjustments::DisplayAdjustments;
+use self::annotators::{adjudication_queue, current_annotator, is_adjudicator, merge_saved};
+use self::bulk::{apply_bulk_action, parse_index_ranges};
+use self::compare::{pair_by_name, ViewTransform};
+use self::crop::AspectRatio;
+use self::dashboard::{dashboard_in_background, FolderStats};
+use self::duplicates::{
+    duplicate_threshold, group_in_background, hash_in_background, original_of, HASH_BATCH_SIZE,
+};
+use self::filter::{next_match, parse_image_number, previous_match, ImageFilter};
+use self::fingerprint::{missing_records, verify_records, FileChanges};
+use self::formats::{expand_items, frame_step, split_item};
+use self::image_cache::{decode_in_background, ImageCache, PREFETCH_DISTANCE};
+use self::metadata::{read_in_background, ImageMetadata};
+use self::quality::{check_in_background, QualityWarning, CHECK_BATCH_SIZE};
 use self::render_image::{
-    init_json_obj, AnnotatedStore, ImageStepMessage, Message, Step, ThemeType,
+    init_json_obj, AnnotatedStore, ImageStepMessage, Message, Properties, Step, ThemeType,
+};
+use self::storage::{restore_records, StorageHandle};
+use self::sync::{
+    sync_client, sync_in_background, SyncClient, SyncConflict, SyncDirection, SyncJob, SyncOutcome,
+};
+use self::thumbnails::{build_in_background, thumbnail_cache, BUILD_BATCH_SIZE};
+use self::transform::ImageTransform;
+use self::watcher::{
+    apply_changes, folder_events, known_files, notify_changes, renamed_item, scan_in_background,
+    FolderChanges, FolderEvent, WATCH_INTERVAL,
 };
+use rfd::FileDialog;
 
+#[path = "adjustments.rs"]
+mod adjustments;
+#[path = "annotators.rs"]
+mod annotators;
+#[path = "bulk.rs"]
+mod bulk;
+#[path = "cli.rs"]
+mod cli;
+#[path = "compare.rs"]
+mod compare;
+#[path = "config.rs"]
+mod config;
+#[path = "crop.rs"]
+mod crop;
+#[path = "dashboard.rs"]
+mod dashboard;
+#[path = "duplicates.rs"]
+mod duplicates;
+#[path = "filter.rs"]
+mod filter;
+#[path = "fingerprint.rs"]
+mod fingerprint;
+#[path = "formats.rs"]
+mod formats;
+#[path = "image_cache.rs"]
+mod image_cache;
+#[path = "metadata.rs"]
+mod metadata;
+#[path = "quality.rs"]
+mod quality;
 #[path = "render_image.rs"]
 mod render_image;
+#[path = "server.rs"]
+mod server;
+#[path = "sqlite_store.rs"]
+mod sqlite_store;
+#[path = "storage.rs"]
+mod storage;
+#[path = "sync.rs"]
+mod sync;
+#[path = "thumbnails.rs"]
+mod thumbnails;
+#[path = "transform.rs"]
+mod transform;
+#[path = "watcher.rs"]
+mod watcher;
+
+pub fn run_cli(args: &[String]) -> Option<i32> {
+    cli::run(args)
+}
 
 #[derive(Default, Debug, Clone)]
 pub struct Steps {
@@ -28,6 +107,91 @@ pub struct Steps {
     incorrect_btn_clicked: bool,
     theme: iced::Theme,
     theme_changed: bool,
+    storage: StorageHandle,
+    sync: Option<Arc<SyncClient>>,
+    sync_conflicts: Vec<SyncConflict>,
+    sync_status: String,
+    // Push or pull waiting for the running one to finish
+    pending_sync: Option<SyncDirection>,
+    sync_running: bool,
+    last_pull: Option<Instant>,
+    // Indices of the images with conflicting verdicts, for the adjudicator
+    adjudication_queue: Vec<usize>,
+    queue_position: usize,
+    rationale: String,
+    dashboard: Vec<FolderStats>,
+    dashboard_requested: bool,
+    // Whether each image can be shown, by image path, from the background quality checks
+    image_validity: HashMap<PathBuf, bool>,
+    // Vertical scroll position of the page, the gallery only builds the rows around it
+    scroll_offset: f32,
+    // Indices of the images selected in the gallery
+    selected: BTreeSet<usize>,
+    bulk_range: String,
+    bulk_comment: String,
+    bulk_tag: String,
+    // Result of the last bulk action
+    bulk_status: String,
+    image_filter: ImageFilter,
+    // Indices of the images matching `image_filter`
+    filtered: Vec<usize>,
+    jump_input: String,
+    navigation_status: String,
+    // Decoded current image and its neighbours, filled by background workers
+    image_cache: ImageCache,
+    // Thumbnail files by image path, from the on-disk cache
+    thumbnails: HashMap<PathBuf, PathBuf>,
+    // Images of the folder still waiting for a thumbnail, built one batch at a time
+    thumbnail_queue: VecDeque<PathBuf>,
+    thumbnail_batch_running: bool,
+    // Folder with the counterparts of the images, shown side by side
+    comparison_folder: Option<String>,
+    // Counterpart of each image in `comparison_folder`, paired by file name
+    counterparts: Vec<Option<PathBuf>>,
+    view_transform: ViewTransform,
+    // Otherwise the display adjustments are reset when moving to another image
+    keep_adjustments: bool,
+    // Metadata of the images shown so far, by path
+    metadata: HashMap<PathBuf, Result<ImageMetadata, String>>,
+    metadata_requested: HashSet<PathBuf>,
+    show_metadata: bool,
+    // Dragging over the image draws the crop region
+    crop_mode: bool,
+    crop_aspect: AspectRatio,
+    // Perceptual hash of the images hashed so far by path, None if they can't be decoded
+    hashes: HashMap<PathBuf, Option<u64>>,
+    // Images of the folder still waiting for a hash, hashed one batch at a time
+    hash_queue: VecDeque<PathBuf>,
+    hash_batch_running: bool,
+    duplicate_threshold: u32,
+    // Groups of indices of near-duplicate images, see `group_duplicates`
+    duplicate_groups: Vec<Vec<usize>>,
+    // For every image, the first image of its group if it is another one
+    duplicate_originals: Vec<Option<usize>>,
+    // The images are grouped again in the background, once at a time
+    duplicates_requested: bool,
+    duplicates_running: bool,
+    // Result of the last action on duplicates
+    duplicate_status: String,
+    // Warnings of the images checked so far by path, also kept in their records
+    quality_warnings: HashMap<PathBuf, Vec<QualityWarning>>,
+    // Images of the folder still waiting for the quality checks, checked one batch at a time
+    quality_queue: VecDeque<PathBuf>,
+    quality_batch_running: bool,
+    // Saved records of images that are no longer in the folder, saved back along with the
+    // folder's records
+    missing_records: Vec<Properties>,
+    // Records of every folder as last loaded from or saved to the storage, to tell the changes
+    // made here from the ones saved meanwhile by others
+    last_saved: HashMap<String, Vec<Properties>>,
+    // The folder is listed again in the background when its file system events say it
+    // changed, or every `WATCH_INTERVAL` without them. One scan at a time.
+    folder_scan_requested: bool,
+    folder_scan_running: bool,
+    watching_events: bool,
+    // Changes found in the folder when they can't be shown as a desktop notification, and
+    // whether the folder is watched
+    folder_status: String,
 }
 
 #[derive(Default)]
@@ -37,6 +201,11 @@ pub struct FolderVisualizer {
 }
 
 fn get_all_images(folder_path: &String) -> Vec<PathBuf> {
+    expand_items(list_files(folder_path), frame_step())
+}
+
+// Files of the folder, without sub-directories
+fn list_files(folder_path: &String) -> Vec<PathBuf> {
     let paths = std::fs::read_dir(folder_path);
     let mut output: Vec<PathBuf> = vec![];
     match paths {
@@ -70,20 +239,26 @@ fn get_all_images(folder_path: &String) -> Vec<PathBuf> {
     output
 }
 
-impl Sandbox for FolderVisualizer {
+impl Application for FolderVisualizer {
+    type Executor = executor::Default;
     type Message = render_image::Message;
+    type Theme = iced::Theme;
+    type Flags = ();
 
-    fn new() -> FolderVisualizer {
+    fn new(_flags: ()) -> (FolderVisualizer, Command<Self::Message>) {
         let folder_path: String = "".into();
         let all_images = vec![];
         let json_obj: AnnotatedStore = init_json_obj(folder_path.clone(), all_images.clone());
         let mut steps_obj = Steps::new(folder_path, 0, all_images.clone(), vec![], json_obj);
         steps_obj.correct_items = vec![None; all_images.len()];
         steps_obj.theme = iced::Theme::Dark;
-        FolderVisualizer {
-            steps: steps_obj,
-            theme: iced::Theme::Dark,
-        }
+        (
+            FolderVisualizer {
+                steps: steps_obj,
+                theme: iced::Theme::Dark,
+            },
+            Command::none(),
+        )
     }
 
     fn title(&self) -> String {
@@ -129,15 +304,16 @@ impl Sandbox for FolderVisualizer {
         ]
         .into();
 
-        container(scrollable(
-            container(content).width(Length::Fill).center_x(),
-        ))
+        container(
+            scrollable(container(content).width(Length::Fill).center_x())
+                .on_scroll(|viewport| Message::Scrolled(viewport.absolute_offset().y)),
+        )
         .height(Length::Fill)
         .center_y()
         .into()
     }
 
-    fn update(&mut self, message: Self::Message) {
+    fn update(&mut self, message: Self::Message) -> Command<Self::Message> {
         match message {
             Message::ThemeChanged(theme) => {
                 self.theme = match theme {
@@ -159,17 +335,104 @@ impl Sandbox for FolderVisualizer {
                 self.steps.advance();
             }
             Message::ImageStepMessage(step_msg) => {
+                let previous_idx = self.steps.curr_idx;
                 let new_theme: Option<iced::Theme> = self.steps.update(step_msg);
                 if let Some(valid_theme) = new_theme {
                     self.theme = valid_theme;
                 }
+                if self.steps.curr_idx != previous_idx && !self.steps.keep_adjustments {
+                    self.steps
+                        .image_cache
+                        .set_adjustments(DisplayAdjustments::default());
+                }
+            }
+            Message::ImageDecoded(path, rendering, decoded) => {
+                self.steps.image_cache.insert(path, rendering, decoded);
+            }
+            Message::ThumbnailsBuilt(built) => {
+                self.steps.thumbnails.extend(built);
+                self.steps.thumbnail_batch_running = false;
+            }
+            Message::MetadataRead(path, metadata) => self.steps.store_metadata(path, metadata),
+            Message::HashesComputed(hashes) => {
+                self.steps.hashes.extend(hashes);
+                self.steps.hash_batch_running = false;
+                // Grouped once every image is hashed, the groups would change on every batch
+                if self.steps.hash_queue.is_empty() {
+                    self.steps.refresh_duplicates();
+                }
+            }
+            Message::DuplicatesGrouped(images, groups) => {
+                self.steps.duplicates_running = false;
+                // Ignored if the images changed meanwhile, they are grouped again
+                if images == self.steps.all_images {
+                    self.steps.duplicate_originals = original_of(&groups, images.len());
+                    self.steps.duplicate_groups = groups;
+                }
+            }
+            Message::QualityChecked(checked) => {
+                for (path, warnings) in checked {
+                    self.steps
+                        .image_validity
+                        .insert(path.clone(), warnings.is_some());
+                    self.steps
+                        .quality_warnings
+                        .insert(path, warnings.unwrap_or_default());
+                }
+                self.steps.quality_batch_running = false;
+                self.steps.store_quality_warnings();
+                self.steps.refresh_filter();
+            }
+            Message::WatchTick => {
+                self.steps.pull_if_due();
+                if !self.steps.watching_events {
+                    self.steps.folder_scan_requested = true;
+                }
+                return Command::batch([self.steps.scan_folder(), self.steps.run_sync()]);
+            }
+            Message::FolderChanged(event) => {
+                self.steps.apply_folder_event(event);
+                return self.steps.scan_folder();
+            }
+            Message::FolderScanned(folder_path, changes) => {
+                self.steps.folder_scan_running = false;
+                // Ignored if another folder was opened meanwhile
+                if folder_path == self.steps.folder_path && !changes.is_empty() {
+                    self.steps.apply_folder_changes(changes);
+                }
+            }
+            Message::DashboardComputed(dashboard) => self.steps.dashboard = dashboard,
+            Message::Scrolled(offset) => self.steps.scroll_offset = offset,
+            Message::Synced(job) => {
+                self.steps.sync_running = false;
+                self.steps.apply_sync_job(job);
             }
         }
+        Command::batch([
+            self.steps.run_sync(),
+            self.steps.refresh_dashboard(),
+            self.steps.prefetch(),
+            self.steps.build_thumbnails(),
+            self.steps.read_metadata(),
+            self.steps.compute_hashes(),
+            self.steps.group_duplicates(),
+            self.steps.check_quality(),
+        ])
     }
 
     fn theme(&self) -> iced::Theme {
         self.theme.clone()
     }
+
+    fn subscription(&self) -> Subscription<Message> {
+        match self.steps.folder_path.is_empty() {
+            true => Subscription::none(),
+            false => Subscription::batch([
+                folder_events(self.steps.folder_path.clone()).map(Message::FolderChanged),
+                iced::time::every(WATCH_INTERVAL).map(|_| Message::WatchTick),
+            ]),
+        }
+    }
 }
 
 impl Steps {
@@ -180,9 +443,23 @@ impl Steps {
         correct_items: Vec<Option<bool>>,
         json_obj: AnnotatedStore,
     ) -> Steps {
+        // FIXME: Removed Step::End temporarily
+        let steps = match is_adjudicator() {
+            true => vec![
+                Step::WelcomeWithFolderChoose,
+                Step::Adjudication,
+                Step::Dashboard,
+            ],
+            false => vec![
+                Step::WelcomeWithFolderChoose,
+                Step::Images,
+                Step::Gallery,
+                Step::Dashboard,
+                Step::Duplicates,
+            ],
+        };
         Steps {
-            // FIXME: Removed Step::End temporarily
-            steps: vec![Step::WelcomeWithFolderChoose, Step::Images],
+            steps,
             folder_path,
             curr_idx,
             all_images,
@@ -195,10 +472,148 @@ impl Steps {
             incorrect_btn_clicked: false,
             theme: iced::Theme::Dark,
             theme_changed: false,
+            storage: StorageHandle::default(),
+            sync: sync_client(),
+            sync_conflicts: vec![],
+            sync_status: "".to_string(),
+            pending_sync: None,
+            sync_running: false,
+            last_pull: None,
+            adjudication_queue: vec![],
+            queue_position: 0,
+            rationale: "".to_string(),
+            dashboard: vec![],
+            dashboard_requested: false,
+            image_validity: HashMap::new(),
+            scroll_offset: 0.0,
+            selected: BTreeSet::new(),
+            bulk_range: "".to_string(),
+            bulk_comment: "".to_string(),
+            bulk_tag: "".to_string(),
+            bulk_status: "".to_string(),
+            image_filter: ImageFilter::default(),
+            filtered: vec![],
+            jump_input: "".to_string(),
+            navigation_status: "".to_string(),
+            image_cache: ImageCache::default(),
+            thumbnails: HashMap::new(),
+            thumbnail_queue: VecDeque::new(),
+            thumbnail_batch_running: false,
+            comparison_folder: None,
+            counterparts: vec![],
+            view_transform: ViewTransform::default(),
+            keep_adjustments: false,
+            metadata: HashMap::new(),
+            metadata_requested: HashSet::new(),
+            show_metadata: false,
+            crop_mode: false,
+            crop_aspect: AspectRatio::default(),
+            hashes: HashMap::new(),
+            hash_queue: VecDeque::new(),
+            hash_batch_running: false,
+            duplicate_threshold: duplicate_threshold(),
+            duplicate_groups: vec![],
+            duplicate_originals: vec![],
+            duplicates_requested: false,
+            duplicates_running: false,
+            duplicate_status: "".to_string(),
+            quality_warnings: HashMap::new(),
+            quality_queue: VecDeque::new(),
+            quality_batch_running: false,
+            missing_records: vec![],
+            last_saved: HashMap::new(),
+            folder_scan_requested: false,
+            folder_scan_running: false,
+            watching_events: true,
+            folder_status: String::new(),
         }
     }
 
     pub fn update(&mut self, msg: ImageStepMessage) -> Option<iced::Theme> {
+        if let ImageStepMessage::ResolveSyncConflict(position, keep_local) = msg {
+            self.resolve_sync_conflict(position, keep_local);
+            return Some(self.theme.clone());
+        }
+        if let ImageStepMessage::RefreshDashboard() = msg {
+            self.dashboard_requested = true;
+            return Some(self.theme.clone());
+        }
+        if matches!(
+            msg,
+            ImageStepMessage::OpenFromGallery(_)
+                | ImageStepMessage::ToggleSelection(..)
+                | ImageStepMessage::SelectAll()
+                | ImageStepMessage::ClearSelection()
+                | ImageStepMessage::BulkRangeType(_)
+                | ImageStepMessage::SelectRange()
+                | ImageStepMessage::SelectByStatus(_)
+                | ImageStepMessage::BulkCommentType(_)
+                | ImageStepMessage::BulkTagType(_)
+                | ImageStepMessage::ApplyBulkAction(_)
+        ) {
+            self.update_gallery(msg);
+            return Some(self.theme.clone());
+        }
+        if matches!(
+            msg,
+            ImageStepMessage::AdjudicationPrevious()
+                | ImageStepMessage::AdjudicationNext()
+                | ImageStepMessage::Resolve(_)
+                | ImageStepMessage::RationaleType(_)
+        ) {
+            self.update_adjudication(msg);
+            return Some(self.theme.clone());
+        }
+        if matches!(
+            msg,
+            ImageStepMessage::ChooseComparisonFolder()
+                | ImageStepMessage::StopComparison()
+                | ImageStepMessage::ViewChanged(_)
+                | ImageStepMessage::ResetView()
+                | ImageStepMessage::AdjustmentsChanged(_)
+                | ImageStepMessage::ResetAdjustments()
+                | ImageStepMessage::KeepAdjustments(_)
+                | ImageStepMessage::ToggleMetadata()
+        ) {
+            self.update_display(msg);
+            return Some(self.theme.clone());
+        }
+        if matches!(
+            msg,
+            ImageStepMessage::Rotate(_)
+                | ImageStepMessage::Flip(_)
+                | ImageStepMessage::ResetTransform()
+                | ImageStepMessage::ToggleCropMode()
+                | ImageStepMessage::CropChanged(_)
+                | ImageStepMessage::CropAspectChanged(_)
+                | ImageStepMessage::ClearCrop()
+        ) {
+            self.update_corrections(msg);
+            return Some(self.theme.clone());
+        }
+        if matches!(
+            msg,
+            ImageStepMessage::DuplicateThresholdChanged(_)
+                | ImageStepMessage::ApplyToDuplicates(..)
+        ) {
+            self.update_duplicates(msg);
+            return Some(self.theme.clone());
+        }
+        let msg = match self.resolve_navigation(msg) {
+            Some(valid_msg) => valid_msg,
+            None => return Some(self.theme.clone()),
+        };
+        let export_requested = matches!(msg, ImageStepMessage::Export());
+        // Navigating keeps the verdict as it is, a changed file stays flagged
+        let verdict_edited = matches!(
+            msg,
+            ImageStepMessage::MarkAsCorrect()
+                | ImageStepMessage::MarkAsIncorrect()
+                | ImageStepMessage::ResetSelection()
+                | ImageStepMessage::CommentType(_)
+                | ImageStepMessage::CommentAdded(_)
+        );
+        let edited_idx = self.curr_idx;
         let (
             new_idx,
             new_image_prop_map,
@@ -226,33 +641,881 @@ impl Steps {
             self.folder_path = new_steps_obj.folder_path;
             self.json_obj.image_to_properties_map = new_image_prop_map;
             self.all_images = new_steps_obj.all_images;
+            self.selected.clear();
+            self.metadata.clear();
+            self.metadata_requested.clear();
+            self.watching_events = true;
+            self.folder_status.clear();
+            self.queue_background_work();
+            self.clear_duplicates();
+            self.refresh_counterparts();
+            self.restore_from_storage();
+            self.store_quality_warnings();
         } else {
             self.curr_idx = new_idx;
             self.correct_items = new_correct_items;
-            self.json_obj
+            // The annotation and comment belong to the image shown when the message was sent
+            if let Some(record) = self
+                .json_obj
                 .image_to_properties_map
                 .get_mut(&self.folder_path)
-                .unwrap()
-                .get_mut(self.curr_idx)
-                .unwrap()
-                .annotation = new_annotation;
-            self.json_obj
-                .image_to_properties_map
-                .get_mut(&self.folder_path)
-                .unwrap()
-                .get_mut(self.curr_idx)
-                .unwrap()
-                .comments = new_comment.clone();
+                .and_then(|records| records.get_mut(edited_idx))
+                .filter(|_| verdict_edited)
+            {
+                record.set_verdict(current_annotator(), new_annotation, new_comment.clone());
+            }
         }
         if let Some(msg_valid) = new_comment {
             self.new_message = msg_valid;
         } else {
             self.new_message = String::from("");
         }
+        if export_requested {
+            self.export();
+        }
+        self.refresh_filter();
 
         Some(self.theme.clone())
     }
 
+    // Flags the reviewed images of the folder whose file changed or went missing
+    fn verify_files(&mut self) {
+        let mut changes = match self
+            .json_obj
+            .image_to_properties_map
+            .get_mut(&self.folder_path)
+        {
+            Some(records) => verify_records(records.iter_mut()),
+            None => FileChanges::default(),
+        };
+        changes.missing += self.missing_records.len();
+        if !changes.is_empty() {
+            println!("{}: {}", self.folder_path, changes.summary());
+        }
+    }
+
+    fn export(&mut self) {
+        self.verify_files();
+        for (folder_path, records) in self.json_obj.image_to_properties_map.iter_mut() {
+            // Keeps what other users saved since the folder was opened
+            match self.storage.load_folder(folder_path) {
+                Ok(saved_records) => {
+                    let stored: HashMap<&str, &Properties> = saved_records
+                        .iter()
+                        .flatten()
+                        .map(|record| (record.image_path.as_str(), record))
+                        .collect();
+                    let base: HashMap<&str, &Properties> = self
+                        .last_saved
+                        .get(folder_path)
+                        .into_iter()
+                        .flatten()
+                        .map(|record| (record.image_path.as_str(), record))
+                        .collect();
+                    for record in records.iter_mut() {
+                        if let Some(stored_record) = stored.get(record.image_path.as_str()) {
+                            let base_record = base.get(record.image_path.as_str()).copied();
+                            merge_saved(record, base_record, stored_record);
+                        }
+                    }
+                }
+                Err(e) => println!("Error: {}", e),
+            }
+            let records = match *folder_path == self.folder_path {
+                true => [records.as_slice(), &self.missing_records].concat(),
+                false => records.clone(),
+            };
+            match self.storage.save_folder(folder_path, &records) {
+                Ok(_) => {
+                    println!("Done");
+                    self.last_saved.insert(folder_path.clone(), records);
+                }
+                Err(e) => println!("Error: {}", e),
+            }
+        }
+        self.refresh_correct_items();
+        self.refresh_adjudication_queue();
+        if self.sync.is_some() {
+            // A push pulls too
+            self.pending_sync = Some(SyncDirection::Push);
+        }
+    }
+
+    // Brings back the verdicts saved earlier for images that are still in the folder
+    fn restore_from_storage(&mut self) {
+        let saved_records = match self.storage.load_folder(&self.folder_path) {
+            Ok(records) => records.unwrap_or_default(),
+            Err(e) => {
+                println!(
+                    "Couldn't load saved annotations for {}, error: {}",
+                    self.folder_path, e
+                );
+                vec![]
+            }
+        };
+        self.missing_records.clear();
+        if let Some(records) = self
+            .json_obj
+            .image_to_properties_map
+            .get_mut(&self.folder_path)
+        {
+            restore_records(records, &saved_records);
+            self.missing_records = missing_records(records, &saved_records);
+        }
+        self.last_saved
+            .insert(self.folder_path.clone(), saved_records);
+        self.verify_files();
+        self.refresh_correct_items();
+        self.refresh_adjudication_queue();
+
+        if self.sync.is_some() {
+            self.pending_sync = self.pending_sync.or(Some(SyncDirection::Pull));
+            self.last_pull = Some(Instant::now());
+        }
+    }
+
+    fn refresh_correct_items(&mut self) {
+        if let Some(records) = self.json_obj.image_to_properties_map.get(&self.folder_path) {
+            for record in records.iter() {
+                if let Some(item) = self.correct_items.get_mut(record.index) {
+                    *item = record.effective_verdict(current_annotator());
+                }
+            }
+        }
+        self.refresh_filter();
+    }
+
+    // Turns the navigation messages into a `JumpTo` the step can apply, None if they were
+    // fully handled here
+    fn resolve_navigation(&mut self, msg: ImageStepMessage) -> Option<ImageStepMessage> {
+        let target = match msg {
+            ImageStepMessage::Next() if self.image_filter.is_active() => {
+                next_match(&self.filtered, self.curr_idx)
+            }
+            ImageStepMessage::Previous() if self.image_filter.is_active() => {
+                previous_match(&self.filtered, self.curr_idx)
+            }
+            ImageStepMessage::GoToImage() => {
+                match parse_image_number(&self.jump_input, self.all_images.len()) {
+                    Ok(idx) => {
+                        self.navigation_status.clear();
+                        Some(idx)
+                    }
+                    Err(e) => {
+                        self.navigation_status = e;
+                        None
+                    }
+                }
+            }
+            ImageStepMessage::SearchSubmit() => {
+                let first_match = self
+                    .filtered
+                    .iter()
+                    .copied()
+                    .find(|idx| *idx >= self.curr_idx)
+                    .or(self.filtered.first().copied());
+                if first_match.is_none() {
+                    self.navigation_status = "No matching image".to_string();
+                }
+                first_match
+            }
+            ImageStepMessage::JumpInputType(input) => {
+                self.jump_input = input;
+                None
+            }
+            ImageStepMessage::SearchType(search) => {
+                self.image_filter.search = search;
+                self.refresh_filter();
+                None
+            }
+            ImageStepMessage::FilterChanged(status) => {
+                self.image_filter.status = status;
+                self.refresh_filter();
+                None
+            }
+            _ => return Some(msg),
+        };
+        target.map(ImageStepMessage::JumpTo)
+    }
+
+    fn refresh_filter(&mut self) {
+        if !self.image_filter.is_active() {
+            self.filtered.clear();
+            return;
+        }
+        let records = self
+            .json_obj
+            .image_to_properties_map
+            .get(&self.folder_path)
+            .map(|records| records.as_slice())
+            .unwrap_or_default();
+        self.filtered = self.image_filter.matching_indices(
+            &self.all_images,
+            records,
+            current_annotator(),
+            // Images not checked yet are taken as valid until they are
+            |idx| {
+                self.image_validity
+                    .get(&self.all_images[idx])
+                    .copied()
+                    .unwrap_or(true)
+            },
+        );
+    }
+
+    // Position of the current image among the filtered ones, for the info bar
+    pub fn filter_position(&self) -> Option<String> {
+        if !self.image_filter.is_active() {
+            return None;
+        }
+        Some(
+            match self.filtered.iter().position(|idx| *idx == self.curr_idx) {
+                Some(position) => format!("Filtered: {} of {}", position + 1, self.filtered.len()),
+                None => format!("Filtered: not matching ({} matches)", self.filtered.len()),
+            },
+        )
+    }
+
+    fn update_gallery(&mut self, msg: ImageStepMessage) {
+        match msg {
+            ImageStepMessage::OpenFromGallery(idx) if idx < self.all_images.len() => {
+                self.curr_idx = idx;
+                self.new_message = self
+                    .json_obj
+                    .image_to_properties_map
+                    .get(&self.folder_path)
+                    .and_then(|records| records.get(idx)?.verdict_of(current_annotator()).1)
+                    .unwrap_or_default();
+                self.incorrect_btn_clicked = false;
+                if let Some(position) = self
+                    .steps
+                    .iter()
+                    .position(|step| matches!(step, Step::Images))
+                {
+                    self.current = position;
+                }
+            }
+            ImageStepMessage::ToggleSelection(idx, true) => {
+                self.selected.insert(idx);
+            }
+            ImageStepMessage::ToggleSelection(idx, false) => {
+                self.selected.remove(&idx);
+            }
+            ImageStepMessage::SelectAll() => self.selected = (0..self.all_images.len()).collect(),
+            ImageStepMessage::ClearSelection() => self.selected.clear(),
+            ImageStepMessage::BulkRangeType(range) => self.bulk_range = range,
+            ImageStepMessage::SelectRange() => {
+                match parse_index_ranges(&self.bulk_range, self.all_images.len()) {
+                    Ok(indices) => self.selected = indices,
+                    Err(e) => self.bulk_status = e,
+                }
+            }
+            ImageStepMessage::SelectByStatus(status) => {
+                self.selected = self
+                    .correct_items
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, annotation)| **annotation == status)
+                    .map(|(idx, _)| idx)
+                    .collect();
+            }
+            ImageStepMessage::BulkCommentType(comment) => self.bulk_comment = comment,
+            ImageStepMessage::BulkTagType(tag) => self.bulk_tag = tag,
+            ImageStepMessage::ApplyBulkAction(action) => {
+                if let Some(records) = self
+                    .json_obj
+                    .image_to_properties_map
+                    .get_mut(&self.folder_path)
+                {
+                    let changed =
+                        apply_bulk_action(records, &self.selected, &action, current_annotator());
+                    self.bulk_status = format!("{} images {}", changed, action.describe());
+                }
+                self.refresh_correct_items();
+            }
+            _ => {}
+        }
+    }
+
+    // Comparison, zoom and display adjustments, which only change how the images are shown
+    fn update_display(&mut self, msg: ImageStepMessage) {
+        match msg {
+            ImageStepMessage::ChooseComparisonFolder() => {
+                if let Some(valid_path) = FileDialog::new().set_directory(".").pick_folder() {
+                    self.comparison_folder = Some(valid_path.to_string_lossy().to_string());
+                    self.view_transform = ViewTransform::default();
+                    self.refresh_counterparts();
+                }
+            }
+            ImageStepMessage::StopComparison() => {
+                self.comparison_folder = None;
+                self.counterparts.clear();
+            }
+            ImageStepMessage::ViewChanged(transform) => self.view_transform = transform,
+            ImageStepMessage::ResetView() => self.view_transform = ViewTransform::default(),
+            ImageStepMessage::AdjustmentsChanged(adjustments) => {
+                self.image_cache.set_adjustments(adjustments)
+            }
+            ImageStepMessage::ResetAdjustments() => self
+                .image_cache
+                .set_adjustments(DisplayAdjustments::default()),
+            ImageStepMessage::KeepAdjustments(keep) => self.keep_adjustments = keep,
+            ImageStepMessage::ToggleMetadata() => self.show_metadata = !self.show_metadata,
+            _ => {}
+        }
+    }
+
+    // Records the rotation, flip or crop that fixes the current image. The image is shown
+    // with the rotation and flips applied.
+    fn update_corrections(&mut self, msg: ImageStepMessage) {
+        let transform = self.transform_of(self.curr_idx);
+        let record = match self
+            .json_obj
+            .image_to_properties_map
+            .get_mut(&self.folder_path)
+            .and_then(|records| records.get_mut(self.curr_idx))
+        {
+            Some(valid_record) => valid_record,
+            None => return,
+        };
+        match msg {
+            ImageStepMessage::Rotate(degrees) => {
+                record.set_transform(transform.rotated(degrees));
+            }
+            ImageStepMessage::Flip(horizontal) => {
+                record.set_transform(transform.flipped(horizontal));
+            }
+            ImageStepMessage::ResetTransform() => {
+                record.set_transform(ImageTransform::default());
+            }
+            ImageStepMessage::ToggleCropMode() => self.crop_mode = !self.crop_mode,
+            ImageStepMessage::CropChanged(crop) => {
+                record.set_crop(Some(crop));
+            }
+            ImageStepMessage::CropAspectChanged(aspect) => self.crop_aspect = aspect,
+            ImageStepMessage::ClearCrop() => {
+                record.set_crop(None);
+            }
+            _ => {}
+        }
+    }
+
+    fn update_duplicates(&mut self, msg: ImageStepMessage) {
+        match msg {
+            ImageStepMessage::DuplicateThresholdChanged(threshold) => {
+                self.duplicate_threshold = threshold;
+                self.refresh_duplicates();
+            }
+            ImageStepMessage::ApplyToDuplicates(group, action) => {
+                let groups: Vec<Vec<usize>> = match group {
+                    Some(valid_group) => self
+                        .duplicate_groups
+                        .get(valid_group)
+                        .cloned()
+                        .into_iter()
+                        .collect(),
+                    None => self.duplicate_groups.clone(),
+                };
+                let records = match self
+                    .json_obj
+                    .image_to_properties_map
+                    .get_mut(&self.folder_path)
+                {
+                    Some(valid_records) => valid_records,
+                    None => return,
+                };
+                let mut changed = 0;
+                // The first image of each group is kept as the original
+                for group in groups {
+                    let duplicates: BTreeSet<usize> = group.iter().skip(1).copied().collect();
+                    let bulk_action = action.bulk_action(&self.all_images[group[0]]);
+                    changed +=
+                        apply_bulk_action(records, &duplicates, &bulk_action, current_annotator());
+                }
+                self.duplicate_status = format!("{} images {}", changed, action.describe());
+                self.refresh_correct_items();
+            }
+            _ => {}
+        }
+    }
+
+    fn transform_of(&self, idx: usize) -> ImageTransform {
+        self.json_obj
+            .image_to_properties_map
+            .get(&self.folder_path)
+            .and_then(|records| records.get(idx)?.transform)
+            .unwrap_or_default()
+    }
+
+    fn refresh_counterparts(&mut self) {
+        self.counterparts = match &self.comparison_folder {
+            Some(comparison_folder) => {
+                pair_by_name(&self.all_images, &get_all_images(comparison_folder))
+            }
+            None => vec![],
+        };
+    }
+
+    fn update_adjudication(&mut self, msg: ImageStepMessage) {
+        match msg {
+            ImageStepMessage::AdjudicationPrevious() => {
+                self.queue_position = self.queue_position.saturating_sub(1);
+            }
+            ImageStepMessage::AdjudicationNext()
+                if self.queue_position + 1 < self.adjudication_queue.len() =>
+            {
+                self.queue_position += 1;
+            }
+            ImageStepMessage::Resolve(annotation) => {
+                let adjudicator = current_annotator().unwrap_or_default();
+                let rationale = Some(self.rationale.clone()).filter(|text| !text.is_empty());
+                if let Some(record) = self
+                    .json_obj
+                    .image_to_properties_map
+                    .get_mut(&self.folder_path)
+                    .and_then(|records| records.get_mut(self.curr_idx))
+                {
+                    record.resolve(adjudicator, annotation, rationale);
+                }
+            }
+            ImageStepMessage::RationaleType(rationale) => {
+                self.rationale = rationale;
+                return;
+            }
+            _ => {}
+        }
+        self.show_queue_item();
+    }
+
+    fn refresh_adjudication_queue(&mut self) {
+        if !is_adjudicator() {
+            return;
+        }
+        self.adjudication_queue = self
+            .json_obj
+            .image_to_properties_map
+            .get(&self.folder_path)
+            .map(|records| adjudication_queue(records))
+            .unwrap_or_default();
+        // Stay on the same image if it is still conflicting
+        self.queue_position = self
+            .adjudication_queue
+            .iter()
+            .position(|idx| *idx == self.curr_idx)
+            .unwrap_or(
+                self.queue_position
+                    .min(self.adjudication_queue.len().saturating_sub(1)),
+            );
+        self.show_queue_item();
+    }
+
+    fn show_queue_item(&mut self) {
+        if let Some(idx) = self.adjudication_queue.get(self.queue_position) {
+            self.curr_idx = *idx;
+            self.rationale = self
+                .json_obj
+                .image_to_properties_map
+                .get(&self.folder_path)
+                .and_then(|records| records.get(*idx)?.resolution.as_ref()?.rationale.clone())
+                .unwrap_or_default();
+        }
+    }
+
+    fn apply_sync_outcome(&mut self, outcome: SyncOutcome) {
+        if outcome.pulled > 0 {
+            self.refresh_correct_items();
+            self.refresh_adjudication_queue();
+        }
+        self.sync_status = outcome.summary();
+        for conflict in outcome.conflicts {
+            self.sync_conflicts
+                .retain(|known| known.local.image_path != conflict.local.image_path);
+            self.sync_conflicts.push(conflict);
+        }
+    }
+
+    // Pulls the folder again once `--sync-interval` has passed since the last pull
+    fn pull_if_due(&mut self) {
+        if let Some(sync) = self.sync.as_ref() {
+            let due = self
+                .last_pull
+                .is_none_or(|last_pull| last_pull.elapsed() >= sync.pull_interval());
+            if due {
+                self.pending_sync = self.pending_sync.or(Some(SyncDirection::Pull));
+                self.last_pull = Some(Instant::now());
+            }
+        }
+    }
+
+    // Starts the pending push or pull in the background, one at a time
+    fn run_sync(&mut self) -> Command<Message> {
+        let sync = match (&self.sync, self.sync_running) {
+            (Some(sync), false) => sync.clone(),
+            _ => return Command::none(),
+        };
+        let Some(direction) = self.pending_sync.take() else {
+            return Command::none();
+        };
+        let records = self
+            .json_obj
+            .image_to_properties_map
+            .get(&self.folder_path)
+            .cloned()
+            .unwrap_or_default();
+        self.sync_running = true;
+        self.sync_status = "Syncing...".to_string();
+        Command::perform(
+            sync_in_background(
+                sync,
+                self.storage.clone(),
+                self.folder_path.clone(),
+                direction,
+                records,
+            ),
+            Message::Synced,
+        )
+    }
+
+    fn apply_sync_job(&mut self, job: SyncJob) {
+        // The records of another folder were saved by the push already
+        if job.folder_path == self.folder_path {
+            if let Some(records) = self
+                .json_obj
+                .image_to_properties_map
+                .get_mut(&self.folder_path)
+            {
+                job.apply_to(records);
+            }
+        }
+        self.apply_sync_outcome(job.outcome);
+    }
+
+    // Computes the stats of every folder in the background, the stored ones are loaded again
+    fn refresh_dashboard(&mut self) -> Command<Message> {
+        if !self.dashboard_requested {
+            return Command::none();
+        }
+        self.dashboard_requested = false;
+        Command::perform(
+            dashboard_in_background(
+                self.storage.clone(),
+                self.json_obj.clone(),
+                self.image_validity.clone(),
+            ),
+            Message::DashboardComputed,
+        )
+    }
+
+    fn resolve_sync_conflict(&mut self, position: usize, keep_local: bool) {
+        let (sync, conflict) = match (self.sync.clone(), self.sync_conflicts.get(position)) {
+            (Some(sync), Some(conflict)) => (sync, conflict.clone()),
+            _ => return,
+        };
+        match sync.resolve(&conflict, keep_local) {
+            Ok(kept) => {
+                if let Err(e) = self.storage.save_record(&conflict.folder_path, &kept) {
+                    println!("Error: {}", e);
+                }
+                if let Some(record) = self
+                    .json_obj
+                    .image_to_properties_map
+                    .get_mut(&conflict.folder_path)
+                    .and_then(|records| {
+                        records
+                            .iter_mut()
+                            .find(|record| record.image_path == kept.image_path)
+                    })
+                {
+                    *record = kept;
+                }
+                self.sync_conflicts.remove(position);
+                self.refresh_correct_items();
+            }
+            Err(e) => self.sync_status = format!("Sync failed: {}", e),
+        }
+    }
+
+    // Decodes the current image and its neighbours in the background if they aren't yet.
+    // The adjudicator's current image is the one of the queue.
+    fn prefetch(&mut self) -> Command<Message> {
+        if !matches!(self.steps[self.current], Step::Images | Step::Adjudication)
+            || self.all_images.is_empty()
+        {
+            return Command::none();
+        }
+        let records = self.json_obj.image_to_properties_map.get(&self.folder_path);
+        let mut missing = self
+            .image_cache
+            .missing(&self.all_images, self.curr_idx, |idx| {
+                records
+                    .and_then(|records| records.get(idx)?.transform)
+                    .unwrap_or_default()
+            });
+        let first = self.curr_idx.saturating_sub(PREFETCH_DISTANCE);
+        let last = (self.curr_idx + PREFETCH_DISTANCE + 1).min(self.counterparts.len());
+        for idx in first..last {
+            if let Some(Some(counterpart)) = self.counterparts.get(idx) {
+                // The transform is only recorded for the images of the opened folder
+                if let Some(rendering) = self
+                    .image_cache
+                    .request(counterpart, ImageTransform::default())
+                {
+                    missing.push((counterpart.clone(), rendering));
+                }
+            }
+        }
+        Command::batch(missing.into_iter().map(|(path, rendering)| {
+            Command::perform(
+                decode_in_background(path.clone(), rendering),
+                move |decoded| Message::ImageDecoded(path, rendering, decoded),
+            )
+        }))
+    }
+
+    // Reads the metadata of the current image in the background, once per image
+    fn read_metadata(&mut self) -> Command<Message> {
+        if !matches!(self.steps[self.current], Step::Images) || self.all_images.is_empty() {
+            return Command::none();
+        }
+        let path = self.all_images[self.curr_idx].clone();
+        if !self.metadata_requested.insert(path.clone()) {
+            return Command::none();
+        }
+        Command::perform(read_in_background(path.clone()), move |metadata| {
+            Message::MetadataRead(path, metadata)
+        })
+    }
+
+    // Keeps the metadata for the panel, and the dimensions and format in the image's record
+    fn store_metadata(&mut self, path: PathBuf, metadata: Result<ImageMetadata, String>) {
+        if let Ok(valid_metadata) = &metadata {
+            let image_path = path.to_string_lossy();
+            if let Some(record) = self
+                .json_obj
+                .image_to_properties_map
+                .get_mut(&self.folder_path)
+                .and_then(|records| {
+                    records
+                        .iter_mut()
+                        .find(|record| record.image_path == image_path)
+                })
+            {
+                record.image_info = Some(valid_metadata.info.clone());
+            }
+        }
+        self.metadata.insert(path, metadata);
+    }
+
+    // Sends the next batch of images without a perceptual hash to a worker, one batch at a
+    // time. The duplicates are grouped once the queue is empty.
+    fn compute_hashes(&mut self) -> Command<Message> {
+        if self.hash_batch_running || self.hash_queue.is_empty() {
+            return Command::none();
+        }
+        let batch_size = HASH_BATCH_SIZE.min(self.hash_queue.len());
+        let batch: Vec<PathBuf> = self.hash_queue.drain(..batch_size).collect();
+        self.hash_batch_running = true;
+        Command::perform(hash_in_background(batch), Message::HashesComputed)
+    }
+
+    // Thumbnails, hashes and quality checks still missing for the images of the folder
+    fn queue_background_work(&mut self) {
+        self.thumbnail_queue = self
+            .all_images
+            .iter()
+            .filter(|path| !self.thumbnails.contains_key(*path))
+            .cloned()
+            .collect();
+        self.hash_queue = self
+            .all_images
+            .iter()
+            .filter(|path| !self.hashes.contains_key(*path))
+            .cloned()
+            .collect();
+        self.quality_queue = self
+            .all_images
+            .iter()
+            .filter(|path| !self.quality_warnings.contains_key(*path))
+            .cloned()
+            .collect();
+    }
+
+    // A file renamed within the folder is applied as is, anything else needs a new listing
+    fn apply_folder_event(&mut self, event: FolderEvent) {
+        match event {
+            FolderEvent::Renamed(old_file, new_file) => {
+                let listed = |file: &Path| {
+                    self.all_images
+                        .iter()
+                        .any(|path| split_item(path).0 == file)
+                };
+                if listed(&old_file)
+                    && !listed(&new_file)
+                    && new_file.parent() == Some(Path::new(&self.folder_path))
+                    && new_file.is_file()
+                {
+                    self.apply_folder_changes(FolderChanges {
+                        renamed: vec![(old_file, new_file)],
+                        ..Default::default()
+                    });
+                } else {
+                    self.folder_scan_requested = true;
+                }
+            }
+            FolderEvent::Changed => self.folder_scan_requested = true,
+            FolderEvent::Unavailable(e) => {
+                self.watching_events = false;
+                self.folder_status = format!(
+                    "Couldn't watch the folder ({}), it is listed every {} seconds instead",
+                    e,
+                    WATCH_INTERVAL.as_secs()
+                );
+            }
+        }
+    }
+
+    // Lists the folder again in the background, comparing it with the files already shown
+    fn scan_folder(&mut self) -> Command<Message> {
+        if self.folder_scan_running || !self.folder_scan_requested || self.folder_path.is_empty() {
+            return Command::none();
+        }
+        self.folder_scan_requested = false;
+        let records = self
+            .json_obj
+            .image_to_properties_map
+            .get(&self.folder_path)
+            .map(|records| records.as_slice())
+            .unwrap_or_default();
+        let known = known_files(&self.all_images, records);
+        self.folder_scan_running = true;
+        let folder_path = self.folder_path.clone();
+        Command::perform(
+            scan_in_background(folder_path.clone(), known, frame_step()),
+            move |changes| Message::FolderScanned(folder_path, changes),
+        )
+    }
+
+    // Keeps the verdicts and the current image through files added, removed or renamed
+    fn apply_folder_changes(&mut self, changes: FolderChanges) {
+        let current_image = self.all_images.get(self.curr_idx).map(|path| {
+            let file = split_item(path).0;
+            match changes.renamed.iter().find(|(old, _)| *old == file) {
+                Some((_, new_file)) => renamed_item(path, new_file),
+                None => path.clone(),
+            }
+        });
+        let records = self
+            .json_obj
+            .image_to_properties_map
+            .entry(self.folder_path.clone())
+            .or_default();
+        apply_changes(
+            &self.folder_path,
+            &mut self.all_images,
+            records,
+            &mut self.missing_records,
+            &changes,
+        );
+        self.curr_idx = current_image
+            .and_then(|current| self.all_images.iter().position(|path| *path == current))
+            .unwrap_or(self.curr_idx.min(self.all_images.len().saturating_sub(1)));
+        self.correct_items = vec![None; self.all_images.len()];
+        self.refresh_correct_items();
+        // Positions of the images after a removed one changed
+        if !changes.removed.is_empty() {
+            self.selected.clear();
+            self.clear_duplicates();
+        }
+        self.queue_background_work();
+        self.store_quality_warnings();
+        self.refresh_duplicates();
+        self.refresh_counterparts();
+        self.refresh_adjudication_queue();
+        self.refresh_filter();
+        if notify_changes(&self.folder_path, &changes).is_err() {
+            self.folder_status = changes.summary();
+        }
+    }
+
+    // Checks the next batch of images of the folder for quality problems in the background
+    fn check_quality(&mut self) -> Command<Message> {
+        if self.quality_batch_running || self.quality_queue.is_empty() {
+            return Command::none();
+        }
+        let batch_size = CHECK_BATCH_SIZE.min(self.quality_queue.len());
+        let batch: Vec<PathBuf> = self.quality_queue.drain(..batch_size).collect();
+        self.quality_batch_running = true;
+        Command::perform(check_in_background(batch), Message::QualityChecked)
+    }
+
+    // Copies the warnings found so far into the records of the folder, without touching
+    // `last_updated` since they come from the file and not from a review
+    fn store_quality_warnings(&mut self) {
+        let Some(records) = self
+            .json_obj
+            .image_to_properties_map
+            .get_mut(&self.folder_path)
+        else {
+            return;
+        };
+        for record in records.iter_mut() {
+            if let Some(warnings) = self
+                .quality_warnings
+                .get(&PathBuf::from(&record.image_path))
+            {
+                record.quality_warnings = warnings.clone();
+            }
+        }
+    }
+
+    // The groups found so far stay until the new ones are ready
+    fn refresh_duplicates(&mut self) {
+        self.duplicates_requested = true;
+    }
+
+    // The groups refer to images by position, they don't apply once positions change
+    fn clear_duplicates(&mut self) {
+        self.duplicate_groups.clear();
+        self.duplicate_originals.clear();
+        self.refresh_duplicates();
+    }
+
+    fn group_duplicates(&mut self) -> Command<Message> {
+        if self.duplicates_running || !self.duplicates_requested {
+            return Command::none();
+        }
+        self.duplicates_requested = false;
+        self.duplicates_running = true;
+        let hashes: Vec<Option<u64>> = self
+            .all_images
+            .iter()
+            .map(|path| self.hashes.get(path).copied().flatten())
+            .collect();
+        let images = self.all_images.clone();
+        Command::perform(
+            group_in_background(hashes, self.duplicate_threshold),
+            move |groups| Message::DuplicatesGrouped(images, groups),
+        )
+    }
+
+    // Sends the next batch of missing thumbnails to a worker, one batch at a time
+    fn build_thumbnails(&mut self) -> Command<Message> {
+        if self.thumbnail_batch_running || self.thumbnail_queue.is_empty() {
+            return Command::none();
+        }
+        let cache = match thumbnail_cache() {
+            Some(valid_cache) => valid_cache,
+            None => {
+                self.thumbnail_queue.clear();
+                return Command::none();
+            }
+        };
+        let batch_size = BUILD_BATCH_SIZE.min(self.thumbnail_queue.len());
+        let batch: Vec<PathBuf> = self.thumbnail_queue.drain(..batch_size).collect();
+        self.thumbnail_batch_running = true;
+        Command::perform(build_in_background(cache, batch), Message::ThumbnailsBuilt)
+    }
+
     pub fn view(&self) -> Element<ImageStepMessage> {
         self.steps[self.current].view(self)
     }
@@ -260,12 +1523,21 @@ impl Steps {
     pub fn advance(&mut self) {
         if self.can_continue() {
             self.current += 1;
+            self.refresh_step();
         }
     }
 
     pub fn go_back(&mut self) {
         if self.has_previous() {
             self.current -= 1;
+            self.refresh_step();
+        }
+    }
+
+    // Computes what the newly shown step needs, rather than on every redraw
+    fn refresh_step(&mut self) {
+        if matches!(self.steps[self.current], Step::Dashboard) {
+            self.dashboard_requested = true;
         }
     }
 
@@ -278,11 +1550,17 @@ impl Steps {
     }
 
     pub fn is_next_image_available(&self) -> bool {
-        self.curr_idx + 1 < self.all_images.len()
+        match self.image_filter.is_active() {
+            true => next_match(&self.filtered, self.curr_idx).is_some(),
+            false => self.curr_idx + 1 < self.all_images.len(),
+        }
     }
 
     pub fn is_previous_image_available(&self) -> bool {
-        self.curr_idx != 0
+        match self.image_filter.is_active() {
+            true => previous_match(&self.filtered, self.curr_idx).is_some(),
+            false => self.curr_idx != 0,
+        }
     }
 
     pub fn title(&self) -> String {
@@ -1,7 +1,11 @@
-use iced::{Sandbox, Settings};
+use iced::{Application, Settings};
 
 mod img_visualizer;
 
 fn main() -> iced::Result {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(exit_code) = img_visualizer::run_cli(&args) {
+        std::process::exit(exit_code);
+    }
     img_visualizer::FolderVisualizer::run(Settings::default())
 }
@@ -0,0 +1,349 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use exif::{In, Tag};
+use image::codecs::bmp::BmpDecoder;
+use image::codecs::gif::GifDecoder;
+use image::codecs::hdr::HdrAdapter;
+use image::codecs::ico::IcoDecoder;
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::openexr::OpenExrDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::pnm::PnmDecoder;
+use image::codecs::tiff::TiffDecoder;
+use image::{ColorType, DynamicImage, ImageDecoder, ImageFormat, ImageResult};
+use serde::{Deserialize, Serialize};
+
+use super::formats::{open_item, part_of, split_item};
+use super::render_image::validate_image;
+
+// EXIF fields shown in the metadata panel, in this order
+const EXIF_FIELDS: &[(Tag, &str)] = &[
+    (Tag::Make, "Camera make"),
+    (Tag::Model, "Camera model"),
+    (Tag::LensModel, "Lens"),
+    (Tag::DateTimeOriginal, "Captured"),
+    (Tag::ExposureTime, "Exposure"),
+    (Tag::FNumber, "Aperture"),
+    (Tag::PhotographicSensitivity, "ISO"),
+    (Tag::Orientation, "Orientation"),
+    (Tag::GPSLatitude, "Latitude"),
+    (Tag::GPSLongitude, "Longitude"),
+];
+
+// Kept in the exported records. The size is the one of the image as displayed, after the
+// EXIF orientation is applied, which is also the frame of any region stored for the image.
+// Regions in the raw pixel frame of the file are found by undoing `orientation`.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Eq, PartialEq)]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+    pub format: String,
+    // EXIF orientation of the file, only kept when the image had to be rotated or flipped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<u32>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct ImageMetadata {
+    pub info: ImageInfo,
+    pub color_type: String,
+    // Bits per channel
+    pub bit_depth: u16,
+    pub file_size: u64,
+    // Label and value of the EXIF fields found in the file
+    pub exif: Vec<(String, String)>,
+}
+
+impl ImageMetadata {
+    // Label and value of every field, for the metadata panel
+    pub fn rows(&self) -> Vec<(String, String)> {
+        let mut rows = vec![
+            (
+                "Dimensions".to_string(),
+                format!("{} x {}", self.info.width, self.info.height),
+            ),
+            ("File size".to_string(), format_file_size(self.file_size)),
+            ("Format".to_string(), self.info.format.clone()),
+            ("Colour type".to_string(), self.color_type.clone()),
+            ("Bit depth".to_string(), self.bit_depth.to_string()),
+        ];
+        rows.extend(self.exif.iter().cloned());
+        rows
+    }
+}
+
+pub fn format_file_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1048575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1048576.0),
+    }
+}
+
+// The EXIF orientation tag, 1 (as stored) if the file has none
+pub fn read_orientation(path: &Path) -> u32 {
+    File::open(path)
+        .ok()
+        .and_then(|file| {
+            exif::Reader::new()
+                .read_from_container(&mut BufReader::new(file))
+                .ok()
+        })
+        .and_then(|exif| {
+            exif.get_field(Tag::Orientation, In::PRIMARY)?
+                .value
+                .get_uint(0)
+        })
+        .filter(|orientation| (1..=8).contains(orientation))
+        .unwrap_or(1)
+}
+
+// Rotates and flips the decoded pixels so that the image is shown upright
+pub fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+// What was done to show the image upright, None if nothing
+pub fn describe_orientation(orientation: u32) -> Option<&'static str> {
+    match orientation {
+        2 => Some("flipped horizontally"),
+        3 => Some("rotated 180°"),
+        4 => Some("flipped vertically"),
+        5 => Some("rotated 90° clockwise and flipped horizontally"),
+        6 => Some("rotated 90° clockwise"),
+        7 => Some("rotated 90° counter-clockwise and flipped horizontally"),
+        8 => Some("rotated 90° counter-clockwise"),
+        _ => None,
+    }
+}
+
+// Opens the image the way it is displayed, with the EXIF orientation applied
+pub fn open_oriented(path: &Path) -> image::ImageResult<DynamicImage> {
+    Ok(apply_orientation(
+        open_item(path)?,
+        read_orientation(&split_item(path).0),
+    ))
+}
+
+fn header_of<'a>(decoder: ImageResult<impl ImageDecoder<'a>>) -> Option<(u32, u32, ColorType)> {
+    let decoder = decoder.ok()?;
+    let (width, height) = decoder.dimensions();
+    Some((width, height, decoder.color_type()))
+}
+
+// Size and colour type from the VP8, VP8L or VP8X chunk at the start of a WebP file. The
+// WebP decoder of the image crate decodes the whole image as soon as it is created.
+fn webp_header(file: &Path) -> Option<(u32, u32, ColorType)> {
+    let mut header = [0; 30];
+    File::open(file).ok()?.read_exact(&mut header).ok()?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WEBP" {
+        return None;
+    }
+    let payload = &header[20..];
+    let u24 = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+    let u14 = |bytes: &[u8]| u16::from_le_bytes([bytes[0], bytes[1]]) as u32 & 0x3fff;
+    match &header[12..16] {
+        b"VP8 " if payload[3..6] == [0x9d, 0x01, 0x2a] => {
+            Some((u14(&payload[6..8]), u14(&payload[8..10]), ColorType::Rgb8))
+        }
+        b"VP8L" if payload[0] == 0x2f => {
+            let bits = u32::from_le_bytes([payload[1], payload[2], payload[3], payload[4]]);
+            let color_type = match bits >> 28 & 1 {
+                1 => ColorType::Rgba8,
+                _ => ColorType::Rgb8,
+            };
+            Some(((bits & 0x3fff) + 1, (bits >> 14 & 0x3fff) + 1, color_type))
+        }
+        b"VP8X" => {
+            let color_type = match payload[0] & 0x10 {
+                0 => ColorType::Rgb8,
+                _ => ColorType::Rgba8,
+            };
+            Some((
+                u24(&payload[4..7]) + 1,
+                u24(&payload[7..10]) + 1,
+                color_type,
+            ))
+        }
+        _ => None,
+    }
+}
+
+// Size and colour type read from the header of the file, None for the formats without a
+// header reader here
+fn read_header(file: &Path, format: ImageFormat) -> Option<(u32, u32, ColorType)> {
+    let reader = BufReader::new(File::open(file).ok()?);
+    match format {
+        ImageFormat::Png => header_of(PngDecoder::new(reader)),
+        ImageFormat::Jpeg => header_of(JpegDecoder::new(reader)),
+        ImageFormat::Gif => header_of(GifDecoder::new(reader)),
+        ImageFormat::Tiff => header_of(TiffDecoder::new(reader)),
+        ImageFormat::Bmp => header_of(BmpDecoder::new(reader)),
+        ImageFormat::OpenExr => header_of(OpenExrDecoder::new(reader)),
+        ImageFormat::Hdr => header_of(HdrAdapter::new(reader)),
+        ImageFormat::Ico => header_of(IcoDecoder::new(reader)),
+        ImageFormat::Pnm => header_of(PnmDecoder::new(reader)),
+        ImageFormat::WebP => webp_header(file),
+        _ => None,
+    }
+}
+
+// Only the headers are read. Pages and frames other than the first are decoded on their
+// own since their size can differ from the file's, as are the rare formats without a header
+// reader.
+pub fn read_metadata(path: &Path) -> Result<ImageMetadata, String> {
+    validate_image(path).map_err(|e| e.to_string())?;
+    let (file, _) = split_item(path);
+    let file_size = std::fs::metadata(&file).map_err(|e| e.to_string())?.len();
+    let format = image::io::Reader::open(&file)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| e.to_string())?
+        .format();
+    let header = match part_of(path) {
+        Some(_) => None,
+        None => format.and_then(|valid_format| read_header(&file, valid_format)),
+    };
+    let (width, height, color) = match header {
+        Some(valid_header) => valid_header,
+        None => {
+            let decoded = open_item(path).map_err(|e| e.to_string())?;
+            (decoded.width(), decoded.height(), decoded.color())
+        }
+    };
+    let orientation = read_orientation(&file);
+    // Shown upright, see `apply_orientation`
+    let (width, height) = match orientation {
+        5..=8 => (height, width),
+        _ => (width, height),
+    };
+    Ok(ImageMetadata {
+        info: ImageInfo {
+            width,
+            height,
+            format: format
+                .map(|format| format!("{:?}", format).to_uppercase())
+                .unwrap_or_default(),
+            orientation: Some(orientation).filter(|orientation| *orientation != 1),
+        },
+        color_type: format!("{:?}", color),
+        bit_depth: color.bits_per_pixel() / color.channel_count() as u16,
+        file_size,
+        exif: read_exif(&file),
+    })
+}
+
+// Empty if the file has no EXIF data
+fn read_exif(path: &Path) -> Vec<(String, String)> {
+    let exif = match File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| {
+            exif::Reader::new()
+                .read_from_container(&mut BufReader::new(file))
+                .map_err(|e| e.to_string())
+        }) {
+        Ok(valid_exif) => valid_exif,
+        Err(_) => return vec![],
+    };
+    let value_of = |tag: Tag| {
+        let field = exif.get_field(tag, In::PRIMARY)?;
+        Some(field.display_value().with_unit(&exif).to_string())
+    };
+    EXIF_FIELDS
+        .iter()
+        .filter_map(|(tag, label)| {
+            let mut value = value_of(*tag)?;
+            // N/S and E/W are separate fields
+            let reference = match *tag {
+                Tag::GPSLatitude => value_of(Tag::GPSLatitudeRef),
+                Tag::GPSLongitude => value_of(Tag::GPSLongitudeRef),
+                _ => None,
+            };
+            if let Some(valid_reference) = reference {
+                value = format!("{} {}", value, valid_reference);
+            }
+            Some((label.to_string(), value))
+        })
+        .collect()
+}
+
+pub async fn read_in_background(path: PathBuf) -> Result<ImageMetadata, String> {
+    tokio::task::spawn_blocking(move || read_metadata(&path))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_metadata() {
+        let folder = std::env::temp_dir().join("annotator_test_metadata");
+        std::fs::create_dir_all(&folder).unwrap();
+        let image_path = folder.join("sample.png");
+        image::RgbImage::new(30, 20).save(&image_path).unwrap();
+
+        let metadata = read_metadata(&image_path).unwrap();
+        assert_eq!(
+            metadata.info,
+            ImageInfo {
+                width: 30,
+                height: 20,
+                format: "PNG".to_string(),
+                orientation: None,
+            }
+        );
+        assert_eq!(metadata.color_type, "Rgb8");
+        assert_eq!(metadata.bit_depth, 8);
+        assert!(metadata.exif.is_empty());
+        assert_eq!(metadata.rows()[0].1, "30 x 20");
+        assert_eq!(format_file_size(2560), "2.5 KB");
+
+        let text_path = folder.join("notes.txt");
+        std::fs::write(&text_path, "not an image").unwrap();
+        assert!(read_metadata(&text_path).is_err());
+
+        // Lossless WebP header of a 300 x 200 image with alpha
+        let webp_path = folder.join("sample.webp");
+        let bits: u32 = 299 | 199 << 14 | 1 << 28;
+        let mut webp = [b"RIFF".as_slice(), &[0; 4], b"WEBPVP8L", &[0; 4], &[0x2f]].concat();
+        webp.extend(bits.to_le_bytes());
+        webp.resize(30, 0);
+        std::fs::write(&webp_path, webp).unwrap();
+        assert_eq!(webp_header(&webp_path), Some((300, 200, ColorType::Rgba8)));
+        assert_eq!(read_orientation(&image_path), 1);
+    }
+
+    #[test]
+    fn test_apply_orientation() {
+        // 3 x 2, the top left pixel is the only white one
+        let mut pixels = image::GrayImage::new(3, 2);
+        pixels.put_pixel(0, 0, image::Luma([255]));
+        let image = DynamicImage::ImageLuma8(pixels);
+        let white_pixel = |orientation: u32| {
+            let oriented = apply_orientation(image.clone(), orientation).to_luma8();
+            let (x, y, _) = oriented
+                .enumerate_pixels()
+                .find(|(_, _, pixel)| pixel.0[0] == 255)
+                .unwrap();
+            (oriented.dimensions(), (x, y))
+        };
+        assert_eq!(white_pixel(1), ((3, 2), (0, 0)));
+        assert_eq!(white_pixel(3), ((3, 2), (2, 1)));
+        assert_eq!(white_pixel(5), ((2, 3), (0, 0)));
+        assert_eq!(white_pixel(6), ((2, 3), (1, 0)));
+        assert_eq!(white_pixel(8), ((2, 3), (0, 2)));
+        assert_eq!(describe_orientation(1), None);
+    }
+}
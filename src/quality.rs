@@ -0,0 +1,243 @@
+use std::path::{Path, PathBuf};
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage};
+use serde::{Deserialize, Serialize};
+
+use super::formats::{open_item, part_of, split_item};
+use super::render_image::validate_image;
+
+// Images checked by each background worker
+pub const CHECK_BATCH_SIZE: usize = 16;
+// Smallest width or height accepted
+pub const MIN_SIDE: u32 = 64;
+// Standard deviation of the brightness, out of 255, below which an image is blank
+const BLANK_STD_DEV: f64 = 2.0;
+// Variance of the Laplacian below which an image is too blurry to be useful
+const BLUR_VARIANCE: f64 = 15.0;
+// Blur is measured on a copy this size at most, so it doesn't depend on the resolution much
+const BLUR_SIDE: u32 = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityWarning {
+    Blank,
+    Blurry,
+    TooSmall,
+    Corrupt,
+    WrongExtension,
+}
+
+impl QualityWarning {
+    pub fn as_display(&self) -> &str {
+        match self {
+            Self::Blank => "Blank or uniform",
+            Self::Blurry => "Very blurry",
+            Self::TooSmall => "Resolution too small",
+            Self::Corrupt => "Truncated or corrupt file",
+            Self::WrongExtension => "Extension doesn't match the content",
+        }
+    }
+}
+
+impl std::fmt::Display for QualityWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_display())
+    }
+}
+
+// Extensions used for the sniffed type, None for the types that aren't checked
+fn expected_extensions(image_type: imghdr::Type) -> Option<&'static [&'static str]> {
+    match image_type {
+        imghdr::Type::Jpeg => Some(&["jpg", "jpeg", "jpe", "jfif"]),
+        imghdr::Type::Png => Some(&["png"]),
+        imghdr::Type::Gif => Some(&["gif"]),
+        imghdr::Type::Tiff => Some(&["tif", "tiff"]),
+        imghdr::Type::Bmp => Some(&["bmp", "dib"]),
+        imghdr::Type::Webp => Some(&["webp"]),
+        imghdr::Type::Exr => Some(&["exr"]),
+        imghdr::Type::Ico => Some(&["ico"]),
+        imghdr::Type::Rgbe => Some(&["hdr", "pic"]),
+        imghdr::Type::Pbm | imghdr::Type::Pgm | imghdr::Type::Ppm => {
+            Some(&["pbm", "pgm", "ppm", "pnm"])
+        }
+        _ => None,
+    }
+}
+
+fn has_wrong_extension(file: &Path, image_type: imghdr::Type) -> bool {
+    let extension = file
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    expected_extensions(image_type).is_some_and(|expected| !expected.contains(&extension.as_str()))
+}
+
+// Decoded with the sniffed format, so a wrong extension alone doesn't make the file corrupt
+fn decode(path: &Path) -> image::ImageResult<DynamicImage> {
+    match part_of(path) {
+        Some(_) => open_item(path),
+        None => image::io::Reader::open(path)?
+            .with_guessed_format()?
+            .decode(),
+    }
+}
+
+fn brightness_std_dev(gray: &GrayImage) -> f64 {
+    let count = (gray.width() as f64) * (gray.height() as f64);
+    let mean = gray.pixels().map(|pixel| pixel.0[0] as f64).sum::<f64>() / count;
+    let variance = gray
+        .pixels()
+        .map(|pixel| (pixel.0[0] as f64 - mean).powi(2))
+        .sum::<f64>()
+        / count;
+    variance.sqrt()
+}
+
+// Variance of the 4-neighbour Laplacian: sharp edges give large values, blur flattens them
+pub fn laplacian_variance(gray: &GrayImage) -> f64 {
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let at = |x: u32, y: u32| gray.get_pixel(x, y).0[0] as f64;
+    let responses: Vec<f64> = (1..height - 1)
+        .flat_map(|y| (1..width - 1).map(move |x| (x, y)))
+        .map(|(x, y)| at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y))
+        .collect();
+    let count = responses.len() as f64;
+    let mean = responses.iter().sum::<f64>() / count;
+    responses
+        .iter()
+        .map(|response| (response - mean).powi(2))
+        .sum::<f64>()
+        / count
+}
+
+// Warnings for an image of the folder, None for files that aren't images at all since
+// those are shown as invalid instead
+pub fn check_quality(path: &Path) -> Option<Vec<QualityWarning>> {
+    let image_type = validate_image(path).ok()?;
+    let mut warnings = vec![];
+    if has_wrong_extension(&split_item(path).0, image_type) {
+        warnings.push(QualityWarning::WrongExtension);
+    }
+    let image = match decode(path) {
+        Ok(image) => image,
+        Err(_) => {
+            warnings.push(QualityWarning::Corrupt);
+            return Some(warnings);
+        }
+    };
+    if image.width().min(image.height()) < MIN_SIDE {
+        warnings.push(QualityWarning::TooSmall);
+    }
+    let gray = image.to_luma8();
+    if brightness_std_dev(&gray) < BLANK_STD_DEV {
+        // A uniform image has no edges, it would always be reported as blurry too
+        warnings.push(QualityWarning::Blank);
+    } else {
+        let small = match gray.width().max(gray.height()) > BLUR_SIDE {
+            true => image
+                .resize(BLUR_SIDE, BLUR_SIDE, FilterType::Triangle)
+                .to_luma8(),
+            false => gray,
+        };
+        if laplacian_variance(&small) < BLUR_VARIANCE {
+            warnings.push(QualityWarning::Blurry);
+        }
+    }
+    Some(warnings)
+}
+
+pub async fn check_in_background(
+    image_paths: Vec<PathBuf>,
+) -> Vec<(PathBuf, Option<Vec<QualityWarning>>)> {
+    tokio::task::spawn_blocking(move || {
+        image_paths
+            .into_iter()
+            .map(|path| {
+                let warnings = check_quality(&path);
+                (path, warnings)
+            })
+            .collect()
+    })
+    .await
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn checkerboard(size: u32) -> image::RgbImage {
+        image::RgbImage::from_fn(size, size, |x, y| match (x / 8 + y / 8) % 2 {
+            0 => image::Rgb([20, 20, 20]),
+            _ => image::Rgb([230, 230, 230]),
+        })
+    }
+
+    #[test]
+    fn test_check_quality() {
+        let folder = std::env::temp_dir().join("annotator_test_quality");
+        std::fs::create_dir_all(&folder).unwrap();
+        let sharp_path = folder.join("sharp.png");
+        checkerboard(128).save(&sharp_path).unwrap();
+        assert_eq!(check_quality(&sharp_path), Some(vec![]));
+
+        let blank_path = folder.join("blank.png");
+        image::RgbImage::from_pixel(128, 128, image::Rgb([40, 40, 40]))
+            .save(&blank_path)
+            .unwrap();
+        assert_eq!(
+            check_quality(&blank_path),
+            Some(vec![QualityWarning::Blank])
+        );
+
+        let blurry_path = folder.join("blurry.png");
+        image::imageops::blur(&checkerboard(128), 6.0)
+            .save(&blurry_path)
+            .unwrap();
+        assert_eq!(
+            check_quality(&blurry_path),
+            Some(vec![QualityWarning::Blurry])
+        );
+
+        let small_path = folder.join("small.png");
+        checkerboard(32).save(&small_path).unwrap();
+        assert_eq!(
+            check_quality(&small_path),
+            Some(vec![QualityWarning::TooSmall])
+        );
+
+        // A PNG saved as .jpg, then cut in half
+        let misnamed_path = folder.join("misnamed.jpg");
+        std::fs::copy(&sharp_path, &misnamed_path).unwrap();
+        assert_eq!(
+            check_quality(&misnamed_path),
+            Some(vec![QualityWarning::WrongExtension])
+        );
+        let bytes = std::fs::read(&misnamed_path).unwrap();
+        std::fs::write(&misnamed_path, &bytes[..bytes.len() / 2]).unwrap();
+        assert_eq!(
+            check_quality(&misnamed_path),
+            Some(vec![
+                QualityWarning::WrongExtension,
+                QualityWarning::Corrupt
+            ])
+        );
+
+        let text_path = folder.join("notes.txt");
+        std::fs::write(&text_path, "not an image").unwrap();
+        assert_eq!(check_quality(&text_path), None);
+    }
+
+    #[test]
+    fn test_laplacian_variance() {
+        let flat = GrayImage::from_pixel(16, 16, image::Luma([128]));
+        assert_eq!(laplacian_variance(&flat), 0.0);
+        let stripes = GrayImage::from_fn(16, 16, |x, _| image::Luma([(x % 2 * 255) as u8]));
+        assert!(laplacian_variance(&stripes) > BLUR_VARIANCE);
+        assert_eq!(laplacian_variance(&GrayImage::new(2, 2)), 0.0);
+    }
+}
@@ -1,6 +1,10 @@
 use chrono::Local;
 use iced::widget::pick_list;
-use std::{collections::HashMap, panic, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    panic,
+    path::{Path, PathBuf},
+};
 
 use once_cell::sync::Lazy;
 use rfd::FileDialog;
@@ -8,11 +12,27 @@ use rfd::FileDialog;
 use iced::{theme, Element, Length, Renderer};
 use iced_widget::image::Handle;
 use iced_widget::{
-    button, column, container, horizontal_space, image, row, text, text_input, Button, Column,
-    Container,
+    button, checkbox, column, container, horizontal_space, image, progress_bar, row, slider, text,
+    text_input, Button, Column, Container, Row, Space,
 };
 use serde::{Deserialize, Serialize};
 
+use super::adjustments::{ChannelView, DisplayAdjustments, ToneMapping};
+use super::annotators::{compute_agreement, current_annotator, review_settings};
+use super::bulk::BulkAction;
+use super::compare::{SyncedViewer, ViewTransform};
+use super::crop::{AspectRatio, CropRegion, CropSelector};
+use super::dashboard::FolderStats;
+use super::duplicates::{DuplicateAction, MAX_THRESHOLD};
+use super::filter::StatusFilter;
+use super::fingerprint::{FileFingerprint, FileStatus};
+use super::formats::{item_part, needs_tone_mapping, sibling_items, split_item, PartKind};
+use super::image_cache::{Decoded, Rendering};
+use super::metadata::{describe_orientation, open_oriented, ImageInfo, ImageMetadata};
+use super::quality::QualityWarning;
+use super::sync::SyncJob;
+use super::transform::ImageTransform;
+use super::watcher::{FolderChanges, FolderEvent};
 use super::{get_all_images, Steps};
 
 #[derive(PartialEq, Clone, Eq, Copy, Debug)]
@@ -47,6 +67,30 @@ pub enum Message {
     NextPressed,
     ImageStepMessage(ImageStepMessage),
     ThemeChanged(ThemeType),
+    // Sent by the background decoder, with the adjustments and transform applied
+    ImageDecoded(PathBuf, Rendering, Decoded),
+    // Image and thumbnail paths, sent by the thumbnail builder
+    ThumbnailsBuilt(Vec<(PathBuf, PathBuf)>),
+    MetadataRead(PathBuf, Result<ImageMetadata, String>),
+    // Image paths and their perceptual hash, sent by the hashing worker
+    HashesComputed(Vec<(PathBuf, Option<u64>)>),
+    // Images of the folder and their groups of near-duplicates, sent by the grouping worker
+    DuplicatesGrouped(Vec<PathBuf>, Vec<Vec<usize>>),
+    // Image paths and the problems found in them, None for files that aren't images, sent
+    // by the quality checker
+    QualityChecked(Vec<(PathBuf, Option<Vec<QualityWarning>>)>),
+    // Stats of every folder, computed in the background
+    DashboardComputed(Vec<FolderStats>),
+    // Vertical offset of the page, sent when it is scrolled
+    Scrolled(f32),
+    // Sent every `WATCH_INTERVAL` while a folder is open
+    WatchTick,
+    // Sent by the watcher of the open folder
+    FolderChanged(FolderEvent),
+    // Folder and the changes found in it, sent by the folder scan
+    FolderScanned(String, FolderChanges),
+    // Sent by the background push or pull
+    Synced(SyncJob),
 }
 
 pub static mut FOLDER_FOUND: bool = false;
@@ -74,20 +118,146 @@ pub enum ImageStepMessage {
     CommentAdded(String),
     CommentType(String),
     ThemeChanged(ThemeType),
+    // Position in the list of sync conflicts, and whether to keep the local verdict
+    ResolveSyncConflict(usize, bool),
+    AdjudicationPrevious(),
+    AdjudicationNext(),
+    // Final verdict of the adjudicator, `None` clears it
+    Resolve(Option<bool>),
+    RationaleType(String),
+    RefreshDashboard(),
+    OpenFromGallery(usize),
+    ToggleSelection(usize, bool),
+    SelectAll(),
+    ClearSelection(),
+    BulkRangeType(String),
+    SelectRange(),
+    // Selects the images with this status in `correct_items`
+    SelectByStatus(Option<bool>),
+    BulkCommentType(String),
+    BulkTagType(String),
+    ApplyBulkAction(BulkAction),
+    JumpTo(usize),
+    JumpInputType(String),
+    GoToImage(),
+    SearchType(String),
+    SearchSubmit(),
+    FilterChanged(StatusFilter),
+    ChooseComparisonFolder(),
+    StopComparison(),
+    // Zoom and pan of the compared images
+    ViewChanged(ViewTransform),
+    ResetView(),
+    AdjustmentsChanged(DisplayAdjustments),
+    ResetAdjustments(),
+    // Whether the adjustments stay when moving to another image
+    KeepAdjustments(bool),
+    ToggleMetadata(),
+    // Clockwise, in degrees
+    Rotate(u32),
+    // True to flip horizontally
+    Flip(bool),
+    ResetTransform(),
+    ToggleCropMode(),
+    CropChanged(CropRegion),
+    CropAspectChanged(AspectRatio),
+    ClearCrop(),
+    DuplicateThresholdChanged(u32),
+    // To the duplicates of one group, or of every group if None
+    ApplyToDuplicates(Option<usize>, DuplicateAction),
 }
 
 #[derive(Clone, Debug)]
 pub enum Step {
     WelcomeWithFolderChoose,
     Images,
+    Adjudication,
+    Gallery,
+    Dashboard,
+    Duplicates,
     // End,
 }
 
+// Number of thumbnails per row in the gallery
+const GALLERY_COLUMNS: usize = 6;
+// Height of a gallery cell: the thumbnail, the badge, the file name and the tags
+const GALLERY_CELL_HEIGHT: f32 = THUMBNAIL_SIZE as f32 + 90.0;
+const GALLERY_ROW_SPACING: f32 = 20.0;
+// Rows built above and below the scroll position, enough to fill a tall window
+const GALLERY_ROWS_AROUND: usize = 8;
+const THUMBNAIL_SIZE: u16 = super::thumbnails::THUMBNAIL_SIZE as u16;
+// Images shown on each side of the current one in the filmstrip, and their size
+const FILMSTRIP_RADIUS: isize = 4;
+const FILMSTRIP_SIZE: u16 = 64;
+
 struct ContainerCustomStyle {
     curr_theme: theme::Theme,
     bg_color: iced::Background,
 }
 
+// Coloured background behind the status of a thumbnail
+struct BadgeStyle {
+    color: iced::Color,
+}
+
+impl container::StyleSheet for BadgeStyle {
+    type Style = theme::Theme;
+    fn appearance(&self, _: &iced::Theme) -> container::Appearance {
+        container::Appearance {
+            text_color: Some(iced::Color::WHITE),
+            background: Some(iced::Background::Color(self.color)),
+            border_radius: 4.0.into(),
+            ..Default::default()
+        }
+    }
+}
+
+// Outline around the current image in the filmstrip
+struct FrameStyle {
+    highlighted: bool,
+}
+
+impl container::StyleSheet for FrameStyle {
+    type Style = theme::Theme;
+    fn appearance(&self, theme: &iced::Theme) -> container::Appearance {
+        container::Appearance {
+            border_radius: 4.0.into(),
+            border_width: if self.highlighted { 3.0 } else { 0.0 },
+            border_color: theme.palette().primary,
+            ..Default::default()
+        }
+    }
+}
+
+fn verdict_color(annotation: Option<bool>, valid: bool) -> (&'static str, iced::Color) {
+    match (valid, annotation) {
+        (false, _) => ("Invalid", iced::Color::from_rgb(0.4, 0.4, 0.4)),
+        (true, Some(true)) => ("Correct", iced::Color::from_rgb(0.1, 0.6, 0.2)),
+        (true, Some(false)) => ("Incorrect", iced::Color::from_rgb(0.8, 0.1, 0.1)),
+        (true, None) => ("Not selected", iced::Color::from_rgb(0.6, 0.5, 0.1)),
+    }
+}
+
+// Rows of the gallery to build for the scroll position of the page
+fn visible_rows(scroll_offset: f32, image_count: usize) -> std::ops::Range<usize> {
+    let row_count = image_count.div_ceil(GALLERY_COLUMNS);
+    let row_pitch = GALLERY_CELL_HEIGHT + GALLERY_ROW_SPACING;
+    // The offset can be past the end for a moment after images are removed
+    let top_row = ((scroll_offset.max(0.0) / row_pitch) as usize).min(row_count.saturating_sub(1));
+    let first = top_row.saturating_sub(GALLERY_ROWS_AROUND);
+    let last = (top_row + GALLERY_ROWS_AROUND + 1).min(row_count);
+    first..last
+}
+
+fn status_badge<'a>(annotation: Option<bool>, valid: bool) -> Container<'a, ImageStepMessage> {
+    let (label, color) = verdict_color(annotation, valid);
+    container(text(label).size(14))
+        .padding([2, 6])
+        .style(iced::theme::Container::Custom(Box::new(BadgeStyle {
+            color,
+        })))
+}
+
 impl container::StyleSheet for ContainerCustomStyle {
     type Style = theme::Theme;
     fn appearance(&self, _: &iced::Theme) -> container::Appearance {
@@ -137,7 +307,7 @@ impl<'a> Step {
         let mut new_annotation: Option<bool> = match image_properties_map_vec.get(&folder_path) {
             Some(vec_prop_map) => {
                 if let Some(prop_map) = vec_prop_map.get(*curr_idx) {
-                    prop_map.annotation
+                    prop_map.verdict_of(current_annotator()).0
                 } else {
                     None
                 }
@@ -157,10 +327,7 @@ impl<'a> Step {
         let mut new_comment = match image_properties_map_vec.get(&folder_path) {
             Some(vec_prop_map) => {
                 if let Some(prop_map) = vec_prop_map.get(*curr_idx) {
-                    prop_map
-                        .comments
-                        .as_ref()
-                        .map(|comment| comment.to_string())
+                    prop_map.verdict_of(current_annotator()).1
                 } else {
                     None
                 }
@@ -170,11 +337,19 @@ impl<'a> Step {
 
         match msg {
             ImageStepMessage::Next() => {
-                *curr_idx += 1;
+                if *curr_idx + 1 < correct_items.len() {
+                    *curr_idx += 1;
+                }
                 new_steps_obj.incorrect_btn_clicked = false;
             }
             ImageStepMessage::Previous() => {
-                *curr_idx -= 1;
+                *curr_idx = curr_idx.saturating_sub(1);
+                new_steps_obj.incorrect_btn_clicked = false;
+            }
+            ImageStepMessage::JumpTo(idx) => {
+                if idx < correct_items.len() {
+                    *curr_idx = idx;
+                }
                 new_steps_obj.incorrect_btn_clicked = false;
             }
             ImageStepMessage::MarkAsCorrect() => {
@@ -200,7 +375,6 @@ impl<'a> Step {
                 }
             }
             ImageStepMessage::Export() => {
-                write_json(&json_obj);
                 new_steps_obj.incorrect_btn_clicked = false;
             }
             ImageStepMessage::CommentAdded(entered_comment) => {
@@ -266,6 +440,45 @@ impl<'a> Step {
                 new_steps_obj.theme = new_theme;
                 new_steps_obj.theme_changed = true;
             }
+            // Handled by Steps, which owns the sync client and the adjudication queue
+            ImageStepMessage::ResolveSyncConflict(..)
+            | ImageStepMessage::AdjudicationPrevious()
+            | ImageStepMessage::AdjudicationNext()
+            | ImageStepMessage::Resolve(_)
+            | ImageStepMessage::RationaleType(_)
+            | ImageStepMessage::RefreshDashboard()
+            | ImageStepMessage::OpenFromGallery(_)
+            | ImageStepMessage::ToggleSelection(..)
+            | ImageStepMessage::SelectAll()
+            | ImageStepMessage::ClearSelection()
+            | ImageStepMessage::BulkRangeType(_)
+            | ImageStepMessage::SelectRange()
+            | ImageStepMessage::SelectByStatus(_)
+            | ImageStepMessage::BulkCommentType(_)
+            | ImageStepMessage::BulkTagType(_)
+            | ImageStepMessage::ApplyBulkAction(_)
+            | ImageStepMessage::JumpInputType(_)
+            | ImageStepMessage::GoToImage()
+            | ImageStepMessage::SearchType(_)
+            | ImageStepMessage::SearchSubmit()
+            | ImageStepMessage::FilterChanged(_)
+            | ImageStepMessage::ChooseComparisonFolder()
+            | ImageStepMessage::StopComparison()
+            | ImageStepMessage::ViewChanged(_)
+            | ImageStepMessage::ResetView()
+            | ImageStepMessage::AdjustmentsChanged(_)
+            | ImageStepMessage::ResetAdjustments()
+            | ImageStepMessage::KeepAdjustments(_)
+            | ImageStepMessage::ToggleMetadata()
+            | ImageStepMessage::Rotate(_)
+            | ImageStepMessage::Flip(_)
+            | ImageStepMessage::ResetTransform()
+            | ImageStepMessage::ToggleCropMode()
+            | ImageStepMessage::CropChanged(_)
+            | ImageStepMessage::CropAspectChanged(_)
+            | ImageStepMessage::ClearCrop()
+            | ImageStepMessage::DuplicateThresholdChanged(_)
+            | ImageStepMessage::ApplyToDuplicates(..) => {}
         };
 
         (
@@ -282,6 +495,10 @@ impl<'a> Step {
         match self {
             Step::WelcomeWithFolderChoose => true,
             Step::Images => true,
+            Step::Adjudication => true,
+            Step::Gallery => true,
+            Step::Dashboard => true,
+            Step::Duplicates => true,
             // Step::End => false,
         }
     }
@@ -290,6 +507,10 @@ impl<'a> Step {
         match self {
             Step::WelcomeWithFolderChoose => Self::welcome(obj),
             Step::Images => Self::images(obj, &obj.theme),
+            Step::Adjudication => Self::adjudication(obj, &obj.theme),
+            Step::Gallery => Self::gallery(obj),
+            Step::Dashboard => Self::dashboard(obj),
+            Step::Duplicates => Self::duplicates(obj),
             // Step::End => Self::end(),
         }
     }
@@ -355,6 +576,7 @@ impl<'a> Step {
         folder_path: &str,
         correct_items: &[Option<bool>],
         image_file_name: String,
+        filter_position: Option<String>,
         theme: &theme::Theme,
     ) -> Container<'a, ImageStepMessage, Renderer> {
         let curr_idx_text = text(format!("Current Item: {}", curr_idx + 1)).size(20);
@@ -386,6 +608,7 @@ impl<'a> Step {
                 horizontal_space(Length::Fill),
                 folder_path_text,
                 horizontal_space(Length::Fill),
+                text(filter_position.unwrap_or_default()).size(20),
             ]
             .padding(5),
         ])
@@ -400,6 +623,16 @@ impl<'a> Step {
 
     pub fn images(obj: &Steps, theme: &theme::Theme) -> Element<'a, ImageStepMessage> {
         let export_btn = button(text("Export").size(20)).on_press(ImageStepMessage::Export());
+        // Every file can be removed while the folder is open, the verdicts can still be saved
+        if obj.all_images.is_empty() {
+            return column![
+                text("The folder is empty, images added to it will show up here").size(20),
+                row![horizontal_space(Length::Fill), export_btn],
+            ]
+            .spacing(20)
+            .padding(10)
+            .into();
+        }
         let correct_btn =
             button(text("Mark as Correct").size(20)).on_press(ImageStepMessage::MarkAsCorrect());
         let incorrect_btn = button(text("Mark as Incorrect").size(20))
@@ -452,15 +685,33 @@ impl<'a> Step {
             row![input, button].spacing(10)
             /*                 .align_items(iced::Alignment::End) */
         };
-        let img_handle = fetch_image(obj.all_images.clone(), &obj.curr_idx);
+        // Decoded in the background, see `Steps::prefetch`
+        let img_handle = obj.image_cache.peek(&obj.all_images[obj.curr_idx]);
 
         let mut error_msg: Option<String> = None;
         let img_viewer = match img_handle {
-            Ok(valid_img_handle) => Some(image::viewer(valid_img_handle)),
-            Err(e) => {
+            // Dragging draws the crop region instead of panning
+            Some(Ok(valid_img_handle)) if obj.crop_mode => Some(Element::from(
+                CropSelector::new(
+                    valid_img_handle.clone(),
+                    Self::current_record(obj).and_then(|record| record.crop),
+                    obj.crop_aspect,
+                    ImageStepMessage::CropChanged,
+                )
+                .width(Length::Shrink)
+                .height(Length::Shrink),
+            )),
+            Some(Ok(valid_img_handle)) => {
+                Some(Element::from(image::viewer(valid_img_handle.clone())))
+            }
+            Some(Err(e)) => {
                 error_msg = Some(e.to_string());
                 None
             }
+            None => {
+                error_msg = Some("Loading...".to_string());
+                None
+            }
         };
 
         let file_name = obj.all_images[obj.curr_idx]
@@ -474,8 +725,19 @@ impl<'a> Step {
             &obj.folder_path,
             &obj.correct_items,
             file_name.to_string(),
+            obj.filter_position(),
             theme,
         );
+        // Warnings about the image go right under the info bar
+        let info_row = [
+            Self::create_quality_warnings(obj),
+            Self::create_file_check_info(obj),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(column![info_row], |info, warnings| info.push(warnings));
+        let navigation_row = Self::create_navigation(obj);
+        let filmstrip = Self::create_filmstrip(obj);
 
         // container(
         // border
@@ -558,45 +820,1085 @@ impl<'a> Step {
             .max_width(600)
             .width(Length::Fill);
 
-        match img_viewer {
-            Some(valid_img_viewer) => column![
+        let comparison_controls = Self::create_comparison_controls(obj);
+        let content = match obj.comparison_folder {
+            Some(_) => column![
                 container(row![choose_theme_content
                     .width(Length::Fill)
                     .align_items(iced::Alignment::Start)]),
-                container(row![
-                    horizontal_space(Length::Fill),
-                    valid_img_viewer,
-                    horizontal_space(Length::Fill)
-                ]),
+                Self::create_comparison(obj, theme),
+                comparison_controls,
+                Self::create_adjustments(obj),
+                filmstrip,
                 image_option_buttons,
+                Self::create_transform_controls(obj),
+                Self::create_crop_controls(obj),
+                navigation_row,
                 info_row,
+                Self::create_metadata_panel(obj),
                 next_prev_buttons_row.spacing(20).padding(10)
-            ]
-            .into(),
-            None => column![
-                container(row![choose_theme_content
+            ],
+            None => match img_viewer {
+                Some(valid_img_viewer) => column![
+                    container(row![choose_theme_content
+                        .width(Length::Fill)
+                        .align_items(iced::Alignment::Start)]),
+                    container(row![
+                        horizontal_space(Length::Fill),
+                        valid_img_viewer,
+                        horizontal_space(Length::Fill)
+                    ]),
+                    comparison_controls,
+                    Self::create_adjustments(obj),
+                    filmstrip,
+                    image_option_buttons,
+                    Self::create_transform_controls(obj),
+                    Self::create_crop_controls(obj),
+                    navigation_row,
+                    info_row,
+                    Self::create_metadata_panel(obj),
+                    next_prev_buttons_row.spacing(20).padding(10)
+                ],
+                None => column![
+                    container(row![choose_theme_content
+                        .width(Length::Fill)
+                        .align_items(iced::Alignment::Start)]),
+                    container(row![
+                        horizontal_space(Length::Fill),
+                        text(error_msg.unwrap_or_default()),
+                        horizontal_space(Length::Fill)
+                    ])
+                    .style(iced::theme::Container::Custom(Box::new(
+                        ContainerCustomStyle {
+                            curr_theme: theme.clone(),
+                            bg_color: iced::Background::Color(iced::Color::WHITE),
+                        },
+                    )))
+                    // .height((400)) // TOOD: Instead of hard-coding this year, find current windows' height - and make this 40% of that height.
+                    .height(400)
+                    .center_y(),
+                    comparison_controls,
+                    Self::create_adjustments(obj),
+                    filmstrip,
+                    image_option_buttons,
+                    Self::create_transform_controls(obj),
+                    Self::create_crop_controls(obj),
+                    navigation_row,
+                    info_row,
+                    Self::create_metadata_panel(obj),
+                    next_prev_buttons_row.spacing(20).padding(10)
+                ],
+            },
+        };
+
+        let content = match Self::create_duplicate_info(obj) {
+            Some(duplicate_info) => content.push(duplicate_info),
+            None => content,
+        };
+        let content = match Self::create_review_info(obj) {
+            Some(review_info) => content.push(review_info),
+            None => content,
+        };
+        match Self::create_sync_info(obj) {
+            Some(sync_info) => content.push(sync_info).into(),
+            None => content.into(),
+        }
+    }
+
+    // Problems found by the automatic quality checks, if any
+    pub fn create_quality_warnings(obj: &Steps) -> Option<Row<'a, ImageStepMessage, Renderer>> {
+        let record = Self::current_record(obj)?;
+        if record.quality_warnings.is_empty() {
+            return None;
+        }
+        let warnings: Vec<&str> = record
+            .quality_warnings
+            .iter()
+            .map(|warning| warning.as_display())
+            .collect();
+        Some(
+            row![text(format!("Warnings: {}", warnings.join(", ")))
+                .size(20)
+                .style(iced::Color::from_rgb(0.8, 0.3, 0.0))]
+            .padding(5),
+        )
+    }
+
+    // Shown when the image changed since it was reviewed, or when reviewed images of the
+    // folder are gone
+    pub fn create_file_check_info(obj: &Steps) -> Option<Row<'a, ImageStepMessage, Renderer>> {
+        let mut notices = vec![];
+        if let Some(status) = Self::current_record(obj).and_then(|record| record.file_status) {
+            notices.push(status.as_display().to_string());
+        }
+        if !obj.folder_status.is_empty() {
+            notices.push(obj.folder_status.clone());
+        }
+        if !obj.missing_records.is_empty() {
+            notices.push(format!(
+                "{} images with saved records are no longer in the folder",
+                obj.missing_records.len()
+            ));
+        }
+        if notices.is_empty() {
+            return None;
+        }
+        Some(
+            row![text(notices.join(". "))
+                .size(20)
+                .style(iced::Color::from_rgb(0.8, 0.1, 0.1))]
+            .padding(5),
+        )
+    }
+
+    // Shown when the perceptual hash of the image is close to the one of another image
+    pub fn create_duplicate_info(obj: &Steps) -> Option<Row<'a, ImageStepMessage, Renderer>> {
+        let group = obj
+            .duplicate_groups
+            .iter()
+            .find(|group| group.contains(&obj.curr_idx))?;
+        let file_name = |idx: usize| {
+            obj.all_images[idx]
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        };
+        let (description, others) =
+            match obj.duplicate_originals.get(obj.curr_idx).copied().flatten() {
+                Some(original) => (
+                    format!(
+                        "Possible duplicate of {} (image {})",
+                        file_name(original),
+                        original + 1
+                    ),
+                    vec![original],
+                ),
+                None => (
+                    format!("{} possible duplicates of this image", group.len() - 1),
+                    group[1..].to_vec(),
+                ),
+            };
+        let duplicate_info = row![text(description).size(16)]
+            .spacing(10)
+            .padding(10)
+            .align_items(iced::Alignment::Center);
+        Some(
+            others
+                .into_iter()
+                .fold(duplicate_info, |duplicate_info, idx| {
+                    duplicate_info.push(
+                        button(text(format!("Go to {}", file_name(idx))).size(16))
+                            .on_press(ImageStepMessage::JumpTo(idx)),
+                    )
+                }),
+        )
+    }
+
+    // Verdicts of the other annotators and the agreement so far, hidden in blind review
+    pub fn create_review_info(obj: &Steps) -> Option<Column<'a, ImageStepMessage, Renderer>> {
+        let settings = review_settings()?;
+        let mut review_info =
+            column![text(format!("Reviewing as {}", settings.annotator)).size(16)]
+                .spacing(10)
+                .padding(10);
+        if settings.blind {
+            return Some(
+                review_info.push(text("Blind review: other verdicts are hidden").size(16)),
+            );
+        }
+        let records = obj.json_obj.image_to_properties_map.get(&obj.folder_path)?;
+        if let Some(record) = records.get(obj.curr_idx) {
+            for (annotator, verdict) in record.verdicts.iter() {
+                if *annotator == settings.annotator {
+                    continue;
+                }
+                let described = describe_verdict(verdict.annotation, &verdict.comments);
+                review_info =
+                    review_info.push(text(format!("{}: {}", annotator, described)).size(16));
+            }
+        }
+        let stats = compute_agreement(records);
+        review_info = review_info.push(text(stats.summary()).size(16));
+        for line in stats.cohen_summary() {
+            review_info = review_info.push(text(line).size(16));
+        }
+        Some(review_info)
+    }
+
+    // The image and its counterpart in the comparison folder, zoomed and panned together
+    pub fn create_comparison(
+        obj: &Steps,
+        theme: &theme::Theme,
+    ) -> Row<'a, ImageStepMessage, Renderer> {
+        let primary = Some(obj.all_images[obj.curr_idx].clone());
+        let counterpart = obj.counterparts.get(obj.curr_idx).cloned().flatten();
+        let panes = [primary, counterpart].into_iter().map(|path| {
+            let (label, pane): (String, Element<'a, ImageStepMessage>) = match path {
+                Some(valid_path) => {
+                    let label = valid_path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string();
+                    let pane = match obj.image_cache.peek(&valid_path) {
+                        Some(Ok(handle)) => SyncedViewer::new(
+                            handle.clone(),
+                            obj.view_transform,
+                            ImageStepMessage::ViewChanged,
+                        )
+                        .into(),
+                        Some(Err(e)) => text(e).into(),
+                        None => text("Loading...").into(),
+                    };
+                    (label, pane)
+                }
+                None => (
+                    "No matching file".to_string(),
+                    text("No file with this name in the comparison folder").into(),
+                ),
+            };
+            column![
+                text(label).size(16),
+                container(pane)
                     .width(Length::Fill)
-                    .align_items(iced::Alignment::Start)]),
-                container(row![
+                    .height(400)
+                    .center_x()
+                    .center_y()
+                    .style(iced::theme::Container::Custom(Box::new(
+                        ContainerCustomStyle {
+                            curr_theme: theme.clone(),
+                            bg_color: iced::Background::Color(iced::Color::WHITE),
+                        },
+                    )))
+            ]
+            .spacing(5)
+            .width(Length::FillPortion(1))
+            .into()
+        });
+        Row::with_children(panes.collect()).spacing(10)
+    }
+
+    pub fn create_comparison_controls(obj: &Steps) -> Row<'a, ImageStepMessage, Renderer> {
+        let choose_btn = button(text("Compare with folder...").size(16))
+            .on_press(ImageStepMessage::ChooseComparisonFolder());
+        match &obj.comparison_folder {
+            Some(comparison_folder) => row![
+                choose_btn,
+                text(format!(
+                    "Comparing with: {} (the verdict applies to the left image)",
+                    comparison_folder
+                ))
+                .size(16),
+                horizontal_space(Length::Fill),
+                button(text("Reset zoom").size(16)).on_press(ImageStepMessage::ResetView()),
+                button(text("Stop comparing").size(16))
+                    .on_press(ImageStepMessage::StopComparison()),
+            ],
+            None => row![choose_btn],
+        }
+        .spacing(20)
+        .padding(10)
+        .align_items(iced::Alignment::Center)
+    }
+
+    // Corrections recorded for an image shown the wrong way round, the image is shown with
+    // them applied
+    pub fn create_transform_controls(obj: &Steps) -> Row<'a, ImageStepMessage, Renderer> {
+        let transform = Self::current_record(obj)
+            .and_then(|record| record.transform)
+            .unwrap_or_default();
+        let mut reset_btn = button(text("Reset orientation").size(16));
+        if !transform.is_identity() {
+            reset_btn = reset_btn.on_press(ImageStepMessage::ResetTransform());
+        }
+        row![
+            text("Fix orientation:").size(16),
+            button(text("Rotate 90°").size(16)).on_press(ImageStepMessage::Rotate(90)),
+            button(text("Rotate 180°").size(16)).on_press(ImageStepMessage::Rotate(180)),
+            button(text("Rotate 270°").size(16)).on_press(ImageStepMessage::Rotate(270)),
+            button(text("Flip H").size(16)).on_press(ImageStepMessage::Flip(true)),
+            button(text("Flip V").size(16)).on_press(ImageStepMessage::Flip(false)),
+            reset_btn,
+            text(format!("Recorded: {}", transform.describe())).size(16),
+        ]
+        .spacing(10)
+        .padding(10)
+        .align_items(iced::Alignment::Center)
+    }
+
+    pub fn create_crop_controls(obj: &Steps) -> Row<'a, ImageStepMessage, Renderer> {
+        let crop = Self::current_record(obj).and_then(|record| record.crop);
+        let toggle_label = match obj.crop_mode {
+            true => "Done cropping",
+            false => "Crop...",
+        };
+        let mut clear_btn = button(text("Clear crop").size(16));
+        if crop.is_some() {
+            clear_btn = clear_btn.on_press(ImageStepMessage::ClearCrop());
+        }
+        let mut controls = row![
+            button(text(toggle_label).size(16)).on_press(ImageStepMessage::ToggleCropMode()),
+            clear_btn,
+            text(match crop {
+                Some(valid_crop) => format!("Crop: {}", valid_crop.describe()),
+                None => "No crop".to_string(),
+            })
+            .size(16),
+        ];
+        if obj.crop_mode {
+            controls = controls
+                .push(horizontal_space(Length::Fill))
+                .push(text("Aspect ratio:").size(16))
+                .push(pick_list(
+                    AspectRatio::ALL,
+                    Some(obj.crop_aspect),
+                    ImageStepMessage::CropAspectChanged,
+                ))
+                .push(text("Drag over the image to draw the region").size(16));
+        }
+        controls
+            .spacing(10)
+            .padding(10)
+            .align_items(iced::Alignment::Center)
+    }
+
+    fn current_record(obj: &Steps) -> Option<&Properties> {
+        obj.json_obj
+            .image_to_properties_map
+            .get(&obj.folder_path)?
+            .get(obj.curr_idx)
+    }
+
+    // Collapsed by default, read in the background for every image shown
+    pub fn create_metadata_panel(obj: &Steps) -> Column<'a, ImageStepMessage, Renderer> {
+        let toggle_label = match obj.show_metadata {
+            true => "Hide metadata",
+            false => "Show metadata",
+        };
+        let panel = column![button(text(toggle_label).size(16))
+            .on_press(ImageStepMessage::ToggleMetadata())
+            .style(theme::Button::Secondary)]
+        .spacing(5)
+        .padding(10);
+        let orientation = obj
+            .metadata
+            .get(&obj.all_images[obj.curr_idx])
+            .and_then(|metadata| metadata.as_ref().ok()?.info.orientation);
+        let panel = match orientation.and_then(describe_orientation) {
+            Some(description) => panel.push(
+                text(format!(
+                    "Shown {} following the EXIF orientation ({})",
+                    description,
+                    orientation.unwrap_or_default()
+                ))
+                .size(16),
+            ),
+            None => panel,
+        };
+        if !obj.show_metadata {
+            return panel;
+        }
+        match obj.metadata.get(&obj.all_images[obj.curr_idx]) {
+            Some(Ok(metadata)) => {
+                metadata
+                    .rows()
+                    .into_iter()
+                    .fold(panel, |panel, (label, value)| {
+                        panel.push(
+                            row![text(label).size(16).width(150), text(value).size(16)].spacing(10),
+                        )
+                    })
+            }
+            Some(Err(e)) => panel.push(text(format!("Couldn't read the metadata: {}", e)).size(16)),
+            None => panel.push(text("Loading...").size(16)),
+        }
+    }
+
+    // Display only adjustments, applied when decoding the image
+    pub fn create_adjustments(obj: &Steps) -> Column<'a, ImageStepMessage, Renderer> {
+        let adjustments = obj.image_cache.adjustments();
+        let labelled = |label: String, control: Element<'a, ImageStepMessage>| {
+            column![text(label).size(14), control].spacing(5).width(150)
+        };
+        let adjustments_row = row![
+            labelled(
+                format!("Brightness: {}", adjustments.brightness),
+                slider(-100..=100, adjustments.brightness, move |brightness| {
+                    ImageStepMessage::AdjustmentsChanged(DisplayAdjustments {
+                        brightness,
+                        ..adjustments
+                    })
+                })
+                .into(),
+            ),
+            labelled(
+                format!("Contrast: {:.0}", adjustments.contrast),
+                slider(-100.0..=100.0, adjustments.contrast, move |contrast| {
+                    ImageStepMessage::AdjustmentsChanged(DisplayAdjustments {
+                        contrast,
+                        ..adjustments
+                    })
+                })
+                .into(),
+            ),
+            labelled(
+                format!("Gamma: {:.1}", adjustments.gamma),
+                slider(0.2..=5.0, adjustments.gamma, move |gamma| {
+                    ImageStepMessage::AdjustmentsChanged(DisplayAdjustments {
+                        gamma,
+                        ..adjustments
+                    })
+                })
+                .step(0.1)
+                .into(),
+            ),
+            pick_list(
+                ChannelView::ALL,
+                Some(adjustments.channel),
+                move |channel| {
+                    ImageStepMessage::AdjustmentsChanged(DisplayAdjustments {
+                        channel,
+                        ..adjustments
+                    })
+                }
+            ),
+            checkbox("Invert", adjustments.invert, move |invert| {
+                ImageStepMessage::AdjustmentsChanged(DisplayAdjustments {
+                    invert,
+                    ..adjustments
+                })
+            }),
+            horizontal_space(Length::Fill),
+            checkbox(
+                "Keep for other images",
+                obj.keep_adjustments,
+                ImageStepMessage::KeepAdjustments
+            ),
+            button(text("Reset").size(16)).on_press(ImageStepMessage::ResetAdjustments()),
+        ]
+        .spacing(20)
+        .padding(10)
+        .align_items(iced::Alignment::Center);
+        let high_depth = obj
+            .metadata
+            .get(&obj.all_images[obj.curr_idx])
+            .is_some_and(|metadata| metadata.as_ref().is_ok_and(|valid| valid.bit_depth > 8));
+        if !high_depth {
+            return column![adjustments_row];
+        }
+        // How 16 bit and float images are brought down to 8 bits
+        let tone_mapping_row = row![
+            text("Tone mapping:").size(16),
+            pick_list(
+                ToneMapping::ALL,
+                Some(adjustments.tone_mapping),
+                move |tone_mapping| {
+                    ImageStepMessage::AdjustmentsChanged(DisplayAdjustments {
+                        tone_mapping,
+                        ..adjustments
+                    })
+                }
+            ),
+            labelled(
+                format!("Black at: {:.1}%", adjustments.window_low),
+                slider(0.0..=20.0, adjustments.window_low, move |window_low| {
+                    ImageStepMessage::AdjustmentsChanged(DisplayAdjustments {
+                        window_low,
+                        ..adjustments
+                    })
+                })
+                .step(0.1)
+                .into(),
+            ),
+            labelled(
+                format!("White at: {:.1}%", adjustments.window_high),
+                slider(80.0..=100.0, adjustments.window_high, move |window_high| {
+                    ImageStepMessage::AdjustmentsChanged(DisplayAdjustments {
+                        window_high,
+                        ..adjustments
+                    })
+                })
+                .step(0.1)
+                .into(),
+            ),
+        ]
+        .spacing(20)
+        .padding(10)
+        .align_items(iced::Alignment::Center);
+        column![adjustments_row, tone_mapping_row]
+    }
+
+    // Previews of the neighbouring images, the current one stays in the middle
+    pub fn create_filmstrip(obj: &Steps) -> Row<'a, ImageStepMessage, Renderer> {
+        let mut filmstrip = row![].spacing(8).align_items(iced::Alignment::Center);
+        for offset in -FILMSTRIP_RADIUS..=FILMSTRIP_RADIUS {
+            let idx = match obj.curr_idx.checked_add_signed(offset) {
+                Some(valid_idx) if valid_idx < obj.all_images.len() => valid_idx,
+                // Keeps the current image centred at the ends of the folder
+                _ => {
+                    filmstrip = filmstrip.push(Space::new(FILMSTRIP_SIZE + 6, FILMSTRIP_SIZE));
+                    continue;
+                }
+            };
+            let preview: Element<'a, ImageStepMessage> =
+                match obj.thumbnails.get(&obj.all_images[idx]) {
+                    Some(thumbnail_path) => image(Handle::from_path(thumbnail_path))
+                        .width(FILMSTRIP_SIZE)
+                        .height(FILMSTRIP_SIZE)
+                        .into(),
+                    None => container(text(idx + 1).size(14))
+                        .width(FILMSTRIP_SIZE)
+                        .height(FILMSTRIP_SIZE)
+                        .center_x()
+                        .center_y()
+                        .into(),
+                };
+            let valid = obj
+                .image_validity
+                .get(&obj.all_images[idx])
+                .copied()
+                .unwrap_or(true);
+            let (_, color) = verdict_color(obj.correct_items.get(idx).copied().flatten(), valid);
+            let verdict_bar = container(Space::new(FILMSTRIP_SIZE, 4)).style(
+                iced::theme::Container::Custom(Box::new(BadgeStyle { color })),
+            );
+            let frame = button(column![preview, verdict_bar].spacing(2))
+                .on_press(ImageStepMessage::JumpTo(idx))
+                .padding(0)
+                .style(theme::Button::Text);
+            filmstrip = filmstrip.push(container(frame).padding(3).style(
+                iced::theme::Container::Custom(Box::new(FrameStyle {
+                    highlighted: idx == obj.curr_idx,
+                })),
+            ));
+        }
+        row![
+            horizontal_space(Length::Fill),
+            filmstrip,
+            horizontal_space(Length::Fill)
+        ]
+        .padding(10)
+    }
+
+    // Jumping to an image, and restricting Next/Previous to the matching images
+    pub fn create_navigation(obj: &Steps) -> Row<'a, ImageStepMessage, Renderer> {
+        let navigation = row![
+            text_input("Go to image number", &obj.jump_input)
+                .on_input(ImageStepMessage::JumpInputType)
+                .on_submit(ImageStepMessage::GoToImage())
+                .padding(10)
+                .width(200),
+            button(text("Go").size(20)).on_press(ImageStepMessage::GoToImage()),
+            text_input("Search file name", &obj.image_filter.search)
+                .on_input(ImageStepMessage::SearchType)
+                .on_submit(ImageStepMessage::SearchSubmit())
+                .padding(10),
+            pick_list(
+                StatusFilter::ALL,
+                Some(obj.image_filter.status),
+                ImageStepMessage::FilterChanged
+            ),
+            text(&obj.navigation_status).size(16),
+        ]
+        .spacing(10)
+        .padding(10)
+        .align_items(iced::Alignment::Center);
+        // Each page of a multi-page file and each frame of an animation is annotated on its own
+        let siblings = sibling_items(&obj.all_images, obj.curr_idx);
+        let Some((kind, index)) = item_part(&obj.all_images, obj.curr_idx) else {
+            return navigation;
+        };
+        if siblings.len() < 2 {
+            return navigation;
+        }
+        let position = siblings
+            .iter()
+            .position(|idx| *idx == obj.curr_idx)
+            .unwrap_or_default();
+        let label = kind.as_display();
+        let mut previous_part = button(text(format!("Previous {}", label.to_lowercase())).size(16));
+        if position > 0 {
+            previous_part =
+                previous_part.on_press(ImageStepMessage::JumpTo(siblings[position - 1]));
+        }
+        let mut next_part = button(text(format!("Next {}", label.to_lowercase())).size(16));
+        if let Some(next_idx) = siblings.get(position + 1) {
+            next_part = next_part.on_press(ImageStepMessage::JumpTo(*next_idx));
+        }
+        // With a frame step, the frame index differs from the position among the siblings
+        let position_text = match kind {
+            PartKind::Frame => format!("Frame {} ({} of {})", index, position + 1, siblings.len()),
+            PartKind::Page => format!("Page {} of {}", position + 1, siblings.len()),
+        };
+        navigation
+            .push(text(position_text).size(16))
+            .push(previous_part)
+            .push(next_part)
+    }
+
+    pub fn create_sync_info(obj: &Steps) -> Option<Column<'a, ImageStepMessage, Renderer>> {
+        obj.sync.as_ref()?;
+        let mut sync_info = column![text(&obj.sync_status).size(16)]
+            .spacing(10)
+            .padding(10);
+        for (position, conflict) in obj.sync_conflicts.iter().enumerate() {
+            let describe =
+                |record: &Properties| describe_verdict(record.annotation, &record.comments);
+            sync_info = sync_info.push(
+                row![
+                    text(format!(
+                        "Conflict on {}: mine is {}, server has {}",
+                        conflict.local.image_path,
+                        describe(&conflict.local),
+                        describe(&conflict.remote)
+                    ))
+                    .size(16),
                     horizontal_space(Length::Fill),
-                    text(error_msg.unwrap_or_default()),
-                    horizontal_space(Length::Fill)
-                ])
+                    button(text("Keep mine").size(16))
+                        .on_press(ImageStepMessage::ResolveSyncConflict(position, true)),
+                    button(text("Take theirs").size(16))
+                        .on_press(ImageStepMessage::ResolveSyncConflict(position, false)),
+                ]
+                .spacing(10),
+            );
+        }
+        Some(sync_info)
+    }
+
+    // Images the annotators disagree on, with every verdict side by side
+    pub fn adjudication(obj: &Steps, theme: &theme::Theme) -> Element<'a, ImageStepMessage> {
+        let export_btn = button(text("Export").size(20)).on_press(ImageStepMessage::Export());
+        let record = obj
+            .adjudication_queue
+            .get(obj.queue_position)
+            .and_then(|idx| {
+                obj.json_obj
+                    .image_to_properties_map
+                    .get(&obj.folder_path)?
+                    .get(*idx)
+            });
+        let record = match record {
+            Some(valid_record) => valid_record,
+            None => {
+                return column![
+                    text("No conflicting verdicts in this folder").size(20),
+                    row![horizontal_space(Length::Fill), export_btn],
+                ]
+                .spacing(20)
+                .padding(10)
+                .into()
+            }
+        };
+
+        // Decoded in the background like in the viewer, see `Steps::prefetch`
+        let img_handle = obj
+            .all_images
+            .get(record.index)
+            .and_then(|path| obj.image_cache.peek(path))
+            .cloned()
+            .unwrap_or_else(|| Err("Loading...".to_string()));
+        let img_view: Element<'a, ImageStepMessage> = match img_handle {
+            Ok(valid_img_handle) => image::viewer(valid_img_handle).into(),
+            Err(e) => container(text(e))
                 .style(iced::theme::Container::Custom(Box::new(
                     ContainerCustomStyle {
                         curr_theme: theme.clone(),
                         bg_color: iced::Background::Color(iced::Color::WHITE),
                     },
                 )))
-                // .height((400)) // TOOD: Instead of hard-coding this year, find current windows' height - and make this 40% of that height.
                 .height(400)
-                .center_y(),
-                image_option_buttons,
-                info_row,
-                next_prev_buttons_row.spacing(20).padding(10)
+                .center_y()
+                .into(),
+        };
+
+        let mut verdicts_row = row![].spacing(20).padding(10);
+        for (annotator, verdict) in record.verdicts.iter() {
+            verdicts_row = verdicts_row.push(
+                column![
+                    text(annotator).size(20),
+                    text(describe_verdict(verdict.annotation, &verdict.comments)).size(16),
+                ]
+                .spacing(5)
+                .width(Length::Fill),
+            );
+        }
+        let resolution_text = match &record.resolution {
+            Some(resolution) => format!(
+                "Resolved by {} as {}",
+                resolution.adjudicator,
+                describe_verdict(resolution.annotation, &resolution.rationale)
+            ),
+            None => "Not resolved yet".to_string(),
+        };
+
+        let rationale_input = text_input(
+            "(Optional) Rationale for the final verdict...",
+            &obj.rationale,
+        )
+        .on_input(ImageStepMessage::RationaleType)
+        .padding(10);
+        let resolve_buttons = row![
+            button(text("Resolve as Correct").size(20))
+                .on_press(ImageStepMessage::Resolve(Some(true))),
+            horizontal_space(Length::Fill),
+            button(text("Clear Resolution").size(20)).on_press(ImageStepMessage::Resolve(None)),
+            horizontal_space(Length::Fill),
+            button(text("Resolve as Incorrect").size(20))
+                .on_press(ImageStepMessage::Resolve(Some(false))),
+        ]
+        .spacing(20)
+        .padding(10);
+
+        let mut previous_btn = button(text("Previous Conflict").size(20));
+        if obj.queue_position > 0 {
+            previous_btn = previous_btn
+                .on_press(ImageStepMessage::AdjudicationPrevious())
+                .style(iced::theme::Button::Primary);
+        }
+        let mut next_btn = button(text("Next Conflict").size(20));
+        if obj.queue_position + 1 < obj.adjudication_queue.len() {
+            next_btn = next_btn
+                .on_press(ImageStepMessage::AdjudicationNext())
+                .style(iced::theme::Button::Primary);
+        }
+        let file_name = obj.all_images[record.index]
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        column![
+            text(format!(
+                "Conflict {} of {}: {}",
+                obj.queue_position + 1,
+                obj.adjudication_queue.len(),
+                file_name
+            ))
+            .size(20),
+            container(row![
+                horizontal_space(Length::Fill),
+                img_view,
+                horizontal_space(Length::Fill)
+            ]),
+            verdicts_row,
+            text(resolution_text).size(20),
+            rationale_input,
+            resolve_buttons,
+            row![
+                previous_btn,
+                horizontal_space(Length::Fill),
+                export_btn,
+                horizontal_space(Length::Fill),
+                next_btn,
+            ]
+            .spacing(20)
+            .padding(10),
+        ]
+        .spacing(10)
+        .into()
+    }
+
+    // Thumbnails of the folder, click one to review it
+    pub fn gallery(obj: &Steps) -> Element<'a, ImageStepMessage> {
+        let mut content = column![row![
+            text(format!("Selected: {}", obj.selected.len())).size(20),
+            horizontal_space(Length::Fill),
+            button(text("Select All").size(20)).on_press(ImageStepMessage::SelectAll()),
+            button(text("Clear Selection").size(20)).on_press(ImageStepMessage::ClearSelection()),
+        ]
+        .spacing(20)]
+        .spacing(20)
+        .padding(10);
+        content = content.push(Self::create_bulk_actions(obj));
+
+        // The rows out of sight are left empty, with the same height, so a large folder
+        // doesn't build thousands of widgets on every redraw
+        let rows = visible_rows(obj.scroll_offset, obj.all_images.len());
+        let row_pitch = GALLERY_CELL_HEIGHT + GALLERY_ROW_SPACING;
+        if rows.start > 0 {
+            content = content.push(Space::with_height(
+                rows.start as f32 * row_pitch - GALLERY_ROW_SPACING,
+            ));
+        }
+        let first = rows.start * GALLERY_COLUMNS;
+        let last = (rows.end * GALLERY_COLUMNS).min(obj.all_images.len());
+        let mut thumbnails: Vec<Element<'a, ImageStepMessage>> = vec![];
+        for (idx, path) in obj.all_images.iter().enumerate().take(last).skip(first) {
+            // Shown as loading until the quality check of the image is done
+            let valid = obj.image_validity.get(path).copied().unwrap_or(true);
+            let preview: Element<'a, ImageStepMessage> = match obj.thumbnails.get(path) {
+                Some(thumbnail_path) => image(Handle::from_path(thumbnail_path))
+                    .width(THUMBNAIL_SIZE)
+                    .height(THUMBNAIL_SIZE)
+                    .into(),
+                None => container(text(if valid { "Loading..." } else { "No preview" }).size(14))
+                    .width(THUMBNAIL_SIZE)
+                    .height(THUMBNAIL_SIZE)
+                    .center_x()
+                    .center_y()
+                    .into(),
+            };
+            let file_name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            let annotation = obj.correct_items.get(idx).copied().flatten();
+            let selected = obj.selected.contains(&idx);
+            let thumbnail = column![
+                button(preview)
+                    .on_press(ImageStepMessage::OpenFromGallery(idx))
+                    .style(theme::Button::Text),
+                row![
+                    checkbox("", selected, move |checked| {
+                        ImageStepMessage::ToggleSelection(idx, checked)
+                    }),
+                    status_badge(annotation, valid),
+                ]
+                .spacing(5),
+                text(file_name).size(14),
+            ]
+            .spacing(5)
+            .width(THUMBNAIL_SIZE + 20)
+            .height(GALLERY_CELL_HEIGHT);
+            let tags = obj
+                .json_obj
+                .image_to_properties_map
+                .get(&obj.folder_path)
+                .and_then(|records| records.get(idx))
+                .map(|record| Vec::from_iter(record.tags.iter().cloned()).join(", "))
+                .unwrap_or_default();
+            let thumbnail = match tags.is_empty() {
+                true => thumbnail,
+                false => thumbnail.push(text(tags).size(14)),
+            };
+            thumbnails.push(thumbnail.into());
+        }
+        let mut thumbnails = thumbnails.into_iter().peekable();
+        while thumbnails.peek().is_some() {
+            let grid_row = Row::with_children(thumbnails.by_ref().take(GALLERY_COLUMNS).collect());
+            content = content.push(grid_row.spacing(10));
+        }
+        let row_count = obj.all_images.len().div_ceil(GALLERY_COLUMNS);
+        if rows.end < row_count {
+            content = content.push(Space::with_height(
+                (row_count - rows.end) as f32 * row_pitch - GALLERY_ROW_SPACING,
+            ));
+        }
+        content.into()
+    }
+
+    // Selecting images by number or status, and changing all the selected ones at once
+    pub fn create_bulk_actions(obj: &Steps) -> Column<'a, ImageStepMessage, Renderer> {
+        let select_row = row![
+            text_input("Image numbers, e.g. 1-20, 25", &obj.bulk_range)
+                .on_input(ImageStepMessage::BulkRangeType)
+                .on_submit(ImageStepMessage::SelectRange())
+                .padding(10),
+            button(text("Select").size(20)).on_press(ImageStepMessage::SelectRange()),
+            button(text("Select Correct").size(20))
+                .on_press(ImageStepMessage::SelectByStatus(Some(true))),
+            button(text("Select Incorrect").size(20))
+                .on_press(ImageStepMessage::SelectByStatus(Some(false))),
+            button(text("Select Not Selected").size(20))
+                .on_press(ImageStepMessage::SelectByStatus(None)),
+        ]
+        .spacing(10);
+
+        let comment = Some(obj.bulk_comment.clone()).filter(|comment| !comment.is_empty());
+        let verdict_row = row![
+            button(text("Mark as Correct").size(20))
+                .on_press(ImageStepMessage::ApplyBulkAction(BulkAction::MarkAsCorrect)),
+            button(text("Reset Selection").size(20)).on_press(ImageStepMessage::ApplyBulkAction(
+                BulkAction::ResetSelection
+            )),
+            text_input("(Optional) Shared reason...", &obj.bulk_comment)
+                .on_input(ImageStepMessage::BulkCommentType)
+                .padding(10),
+            button(text("Mark as Incorrect").size(20)).on_press(ImageStepMessage::ApplyBulkAction(
+                BulkAction::MarkAsIncorrect(comment)
+            )),
+        ]
+        .spacing(10);
+
+        let mut add_tag_btn = button(text("Add Tag").size(20));
+        let mut remove_tag_btn = button(text("Remove Tag").size(20));
+        let tag = obj.bulk_tag.trim().to_string();
+        if !tag.is_empty() {
+            add_tag_btn = add_tag_btn.on_press(ImageStepMessage::ApplyBulkAction(
+                BulkAction::AddTag(tag.clone()),
+            ));
+            remove_tag_btn = remove_tag_btn.on_press(ImageStepMessage::ApplyBulkAction(
+                BulkAction::RemoveTag(tag),
+            ));
+        }
+        let tag_row = row![
+            text_input("Tag", &obj.bulk_tag)
+                .on_input(ImageStepMessage::BulkTagType)
+                .padding(10),
+            add_tag_btn,
+            remove_tag_btn,
+        ]
+        .spacing(10);
+
+        column![
+            select_row,
+            verdict_row,
+            tag_row,
+            text(&obj.bulk_status).size(16)
+        ]
+        .spacing(10)
+    }
+
+    // Progress of every folder opened so far
+    pub fn dashboard(obj: &Steps) -> Element<'a, ImageStepMessage> {
+        let mut content = column![row![
+            text("Progress").size(30),
+            horizontal_space(Length::Fill),
+            button(text("Refresh").size(20)).on_press(ImageStepMessage::RefreshDashboard()),
+        ]]
+        .spacing(20)
+        .padding(10);
+        if obj.dashboard.is_empty() {
+            content = content.push(text("No folder opened yet").size(20));
+        }
+        for stats in obj.dashboard.iter() {
+            let mut folder_info = column![
+                text(format!("Folder Path: {}", stats.folder_path)).size(20),
+                row![
+                    text(format!("Correct: {}", stats.correct)).size(20),
+                    horizontal_space(Length::Fill),
+                    text(format!("Incorrect: {}", stats.incorrect)).size(20),
+                    horizontal_space(Length::Fill),
+                    text(format!("Not selected yet: {}", stats.unreviewed)).size(20),
+                    horizontal_space(Length::Fill),
+                    text(format!("Invalid: {}", stats.invalid)).size(20),
+                ],
+                row![
+                    progress_bar(0.0..=1.0, stats.progress()).height(20),
+                    text(format!("{:.0}%", stats.progress() * 100.0)).size(20),
+                ]
+                .spacing(10),
+            ]
+            .spacing(10);
+
+            if let Some(rate) = stats.reviews_per_active_day() {
+                folder_info = folder_info
+                    .push(text(format!("Review rate: {:.1} images per day", rate)).size(20));
+                // Most recent days first
+                for (date, count) in stats.reviews_per_day.iter().rev().take(7) {
+                    folder_info = folder_info.push(text(format!("{}: {}", date, count)).size(16));
+                }
+            }
+            if !stats.common_phrases.is_empty() {
+                folder_info =
+                    folder_info.push(text("Common reasons for incorrect images:").size(20));
+                for (phrase, count) in stats.common_phrases.iter() {
+                    folder_info =
+                        folder_info.push(text(format!("{} ({})", phrase, count)).size(16));
+                }
+            }
+            content = content.push(folder_info);
+        }
+        content.into()
+    }
+
+    // Groups of near-duplicate images, the first image of each group is kept as the original
+    pub fn duplicates(obj: &Steps) -> Element<'a, ImageStepMessage> {
+        let pending = obj.hash_queue.len() + obj.hash_batch_running as usize;
+        let summary = match pending {
+            0 => format!(
+                "{} groups of possible duplicates",
+                obj.duplicate_groups.len()
+            ),
+            _ => format!("Hashing images, {} left...", pending),
+        };
+        let mut content = column![
+            row![
+                text("Duplicates").size(30),
+                horizontal_space(Length::Fill),
+                text(summary).size(20),
             ]
-            .into(),
+            .align_items(iced::Alignment::Center),
+            row![
+                text(format!(
+                    "Threshold: {} differing bits out of 64",
+                    obj.duplicate_threshold
+                ))
+                .size(16),
+                slider(
+                    0..=MAX_THRESHOLD,
+                    obj.duplicate_threshold,
+                    ImageStepMessage::DuplicateThresholdChanged
+                )
+                .width(300),
+                horizontal_space(Length::Fill),
+                button(text("Mark all duplicates as incorrect").size(16)).on_press(
+                    ImageStepMessage::ApplyToDuplicates(None, DuplicateAction::MarkAsIncorrect)
+                ),
+                button(text("Tag all duplicates").size(16)).on_press(
+                    ImageStepMessage::ApplyToDuplicates(None, DuplicateAction::Tag)
+                ),
+            ]
+            .spacing(10)
+            .align_items(iced::Alignment::Center),
+            text(&obj.duplicate_status).size(16),
+        ]
+        .spacing(20)
+        .padding(10);
+        for (position, group) in obj.duplicate_groups.iter().enumerate() {
+            let mut members = group
+                .iter()
+                .map(|idx| {
+                    let path = &obj.all_images[*idx];
+                    let preview: Element<'a, ImageStepMessage> = match obj.thumbnails.get(path) {
+                        Some(thumbnail_path) => image(Handle::from_path(thumbnail_path))
+                            .width(THUMBNAIL_SIZE)
+                            .height(THUMBNAIL_SIZE)
+                            .into(),
+                        None => container(text("Loading...").size(14))
+                            .width(THUMBNAIL_SIZE)
+                            .height(THUMBNAIL_SIZE)
+                            .center_x()
+                            .center_y()
+                            .into(),
+                    };
+                    let annotation = obj.correct_items.get(*idx).copied().flatten();
+                    column![
+                        button(preview)
+                            .on_press(ImageStepMessage::OpenFromGallery(*idx))
+                            .style(theme::Button::Text),
+                        status_badge(annotation, true),
+                        text(path.file_name().unwrap_or_default().to_string_lossy()).size(14),
+                    ]
+                    .spacing(5)
+                    .width(THUMBNAIL_SIZE + 20)
+                    .into()
+                })
+                .peekable();
+            let mut group_info = column![row![
+                text(format!("Group {}", position + 1)).size(20),
+                button(text("Mark duplicates as incorrect").size(16)).on_press(
+                    ImageStepMessage::ApplyToDuplicates(
+                        Some(position),
+                        DuplicateAction::MarkAsIncorrect
+                    )
+                ),
+                button(text("Tag duplicates").size(16)).on_press(
+                    ImageStepMessage::ApplyToDuplicates(Some(position), DuplicateAction::Tag)
+                ),
+            ]
+            .spacing(10)
+            .align_items(iced::Alignment::Center)]
+            .spacing(10);
+            while members.peek().is_some() {
+                let grid_row = Row::with_children(members.by_ref().take(GALLERY_COLUMNS).collect());
+                group_info = group_info.push(grid_row.spacing(10));
+            }
+            content = content.push(group_info);
         }
+        content.into()
     }
 
     pub fn end() -> Element<'a, ImageStepMessage> {
@@ -629,6 +1931,10 @@ impl<'a> Step {
         match self {
             Step::WelcomeWithFolderChoose => "Welcome".to_string(),
             Step::Images => "Images".to_string(),
+            Step::Adjudication => "Adjudication".to_string(),
+            Step::Gallery => "Gallery".to_string(),
+            Step::Dashboard => "Dashboard".to_string(),
+            Step::Duplicates => "Duplicates".to_string(),
             // Step::End => "End".to_string(),
         }
     }
@@ -638,6 +1944,14 @@ impl<'a> Step {
     // }
 }
 
+pub fn describe_verdict(annotation: Option<bool>, comments: &Option<String>) -> String {
+    match annotation {
+        Some(true) => "Correct".to_string(),
+        Some(false) => format!("Incorrect ({})", comments.clone().unwrap_or_default()),
+        None => "Not selected yet".to_string(),
+    }
+}
+
 pub fn fetch_image(all_images: Vec<PathBuf>, curr_idx: &usize) -> Result<Handle, std::io::Error> {
     // TODO: Set a default image to show that we are waiting for an image...// folder is empty
     let formatted_string = format!("Invalid index: {}", curr_idx);
@@ -645,53 +1959,52 @@ pub fn fetch_image(all_images: Vec<PathBuf>, curr_idx: &usize) -> Result<Handle,
         .get(*curr_idx)
         .unwrap_or_else(|| panic!("{}", formatted_string.to_string()))
         .to_owned();
-    let img_validation_result = imghdr::from_file(path.clone());
-    match img_validation_result {
-        Ok(if_none) => match if_none {
-            Some(_) => Ok(Handle::from_path(path)),
-            None => Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Invalid file, please check if it is a valid image file!",
-            )),
-        },
-        Err(e) => Err(e),
+    validate_image(&path)?;
+    // iced applies the EXIF orientation of the files it can show on its own
+    if !needs_tone_mapping(&path) {
+        return Ok(Handle::from_path(path));
     }
+    let pixels = DisplayAdjustments::default().render(
+        open_oriented(&path)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+    );
+    Ok(Handle::from_pixels(
+        pixels.width(),
+        pixels.height(),
+        pixels.into_raw(),
+    ))
 }
 
-pub fn load_json_and_update(path_str: &String, json_obj: &AnnotatedStore) {
-    if std::path::Path::new(path_str).exists() {
-        let content = std::fs::read_to_string(path_str).expect("Unable to read file output. Please check the file if anything was changed manually or delete and start afresh.");
-        let mut v: AnnotatedStore =
-            serde_json::from_str(&content).expect("Found invalid JSON file");
-        // println!("Prop: {:?}", json_obj.image_to_properties_map);
-        for (folder_path, val) in json_obj.image_to_properties_map.iter() {
-            v.image_to_properties_map
-                .insert(folder_path.to_string(), val.to_vec());
-        }
-        let res = std::fs::write(
-            path_str,
-            serde_json::to_string_pretty(&v).unwrap_or_default(),
-        );
-        match res {
-            Ok(_) => println!("Done"),
-            Err(_) => println!("Error"),
-        }
-    } else {
-        let res = std::fs::write(
-            path_str,
-            serde_json::to_string_pretty(json_obj).unwrap_or_default(),
-        );
-        match res {
-            Ok(_) => println!("Done"),
-            Err(_) => println!("Error"),
-        }
+// Sniffs the file content, the extension is not trusted
+pub fn validate_image(path: &Path) -> Result<imghdr::Type, std::io::Error> {
+    match imghdr::from_file(split_item(path).0)? {
+        Some(image_type) => Ok(image_type),
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Invalid file, please check if it is a valid image file!",
+        )),
     }
 }
 
-fn write_json(json_obj: &AnnotatedStore) {
-    unsafe {
-        load_json_and_update(&OUTPUT_PATH, json_obj);
+pub fn load_json_and_update(path_str: &String, json_obj: &AnnotatedStore) -> std::io::Result<()> {
+    let mut v: AnnotatedStore = match std::path::Path::new(path_str).exists() {
+        true => {
+            let content = std::fs::read_to_string(path_str)?;
+            serde_json::from_str(&content)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+        }
+        false => AnnotatedStore::default(),
+    };
+    for (folder_path, val) in json_obj.image_to_properties_map.iter() {
+        v.image_to_properties_map
+            .insert(folder_path.to_string(), val.to_vec());
     }
+    let content = serde_json::to_string_pretty(&v).map_err(std::io::Error::other)?;
+    std::fs::write(path_str, content)
+}
+
+pub fn write_json(json_obj: &AnnotatedStore) -> std::io::Result<()> {
+    unsafe { load_json_and_update(&OUTPUT_PATH, json_obj) }
 }
 
 #[derive(Deserialize, Serialize, Default, Debug, Clone, Eq, PartialEq)]
@@ -701,6 +2014,190 @@ pub struct Properties {
     pub annotation: Option<bool>,
     pub comments: Option<String>,
     pub last_updated: Option<String>,
+    // Verdicts by annotator name, only filled in multi-annotator mode
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub verdicts: BTreeMap<String, Verdict>,
+    // Final verdict of an adjudicator when the annotators disagree
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<Resolution>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    // Dimensions and format, filled when the image is first shown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_info: Option<ImageInfo>,
+    // Index of the page, from 0, for the pages of a multi-page file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+    // Index of the frame, from 0, for the frames of an animated image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame: Option<u32>,
+    // Rotation and flips needed to show the image the right way round
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<ImageTransform>,
+    // Part of the image to keep, drawn on the image shown with `transform` applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop: Option<CropRegion>,
+    // Problems found in the file by the automatic checks
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quality_warnings: Vec<QualityWarning>,
+    // Content of the file when the verdict was last set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<FileFingerprint>,
+    // Set when the file no longer matches `fingerprint`, see `verify_record`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_status: Option<FileStatus>,
+}
+
+impl Properties {
+    // The verdict edited in the viewer: the annotator's own in multi-annotator mode,
+    // where `annotation` and `comments` are left for the resolved verdict
+    pub fn verdict_of(&self, annotator: Option<&str>) -> (Option<bool>, Option<String>) {
+        match annotator {
+            Some(name) => self
+                .verdicts
+                .get(name)
+                .map(|verdict| (verdict.annotation, verdict.comments.clone()))
+                .unwrap_or_default(),
+            None => (self.annotation, self.comments.clone()),
+        }
+    }
+
+    // The verdict the image counts as in the viewer, the filters and the CLI. With several
+    // annotators, the resolution of an adjudicator comes before the annotator's own verdict.
+    pub fn effective_verdict(&self, annotator: Option<&str>) -> Option<bool> {
+        match (annotator, &self.resolution) {
+            (Some(_), Some(resolution)) => resolution.annotation,
+            _ => self.verdict_of(annotator).0,
+        }
+    }
+
+    // Returns true if the verdict changed, in which case `last_updated` is bumped and the
+    // file is fingerprinted again, its content is hashed on the next `verify_record`. Setting the same verdict on a changed file counts as a
+    // new review.
+    pub fn set_verdict(
+        &mut self,
+        annotator: Option<&str>,
+        annotation: Option<bool>,
+        comments: Option<String>,
+    ) -> bool {
+        if self.verdict_of(annotator) == (annotation, comments.clone())
+            && self.file_status != Some(FileStatus::Changed)
+        {
+            return false;
+        }
+        if let Ok(fingerprint) = FileFingerprint::unhashed(Path::new(&self.image_path)) {
+            self.fingerprint = Some(fingerprint);
+            self.file_status = None;
+        }
+        let now = Some(Local::now().to_string());
+        match annotator {
+            Some(name) => {
+                self.verdicts.insert(
+                    name.to_string(),
+                    Verdict {
+                        annotation,
+                        comments,
+                        last_updated: now.clone(),
+                    },
+                );
+            }
+            None => {
+                self.annotation = annotation;
+                self.comments = comments;
+            }
+        }
+        self.last_updated = now;
+        true
+    }
+
+    // Returns true if the tag was not there yet
+    pub fn add_tag(&mut self, tag: &str) -> bool {
+        let added = self.tags.insert(tag.to_string());
+        if added {
+            self.last_updated = Some(Local::now().to_string());
+        }
+        added
+    }
+
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        let removed = self.tags.remove(tag);
+        if removed {
+            self.last_updated = Some(Local::now().to_string());
+        }
+        removed
+    }
+
+    // Returns true if the corrective transform changed, the identity clears it
+    pub fn set_transform(&mut self, transform: ImageTransform) -> bool {
+        let transform = Some(transform).filter(|valid| !valid.is_identity());
+        if self.transform == transform {
+            return false;
+        }
+        self.transform = transform;
+        // Drawn on the image as it was shown, it doesn't match the new one
+        self.crop = None;
+        self.last_updated = Some(Local::now().to_string());
+        true
+    }
+
+    // Returns true if the crop changed
+    pub fn set_crop(&mut self, crop: Option<CropRegion>) -> bool {
+        if self.crop == crop {
+            return false;
+        }
+        self.crop = crop;
+        self.last_updated = Some(Local::now().to_string());
+        true
+    }
+
+    // Records the adjudicator's verdict, `None` clears it. Returns true if it changed.
+    pub fn resolve(
+        &mut self,
+        adjudicator: &str,
+        annotation: Option<bool>,
+        rationale: Option<String>,
+    ) -> bool {
+        let resolution = annotation.map(|verdict| Resolution {
+            adjudicator: adjudicator.to_string(),
+            annotation: Some(verdict),
+            rationale,
+            last_updated: None,
+        });
+        let unchanged = match (&self.resolution, &resolution) {
+            (Some(current), Some(new)) => {
+                Resolution {
+                    last_updated: None,
+                    ..current.clone()
+                } == *new
+            }
+            (current, new) => current.is_none() && new.is_none(),
+        };
+        if unchanged {
+            return false;
+        }
+        let now = Some(Local::now().to_string());
+        self.resolution = resolution.map(|resolved| Resolution {
+            last_updated: now.clone(),
+            ..resolved
+        });
+        self.last_updated = now;
+        true
+    }
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Eq, PartialEq)]
+pub struct Verdict {
+    pub annotation: Option<bool>,
+    pub comments: Option<String>,
+    pub last_updated: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Eq, PartialEq)]
+pub struct Resolution {
+    pub adjudicator: String,
+    pub annotation: Option<bool>,
+    pub rationale: Option<String>,
+    pub last_updated: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Default, Debug, Clone, Eq, PartialEq)]
@@ -714,12 +2211,20 @@ pub fn init_json_obj(folder_path: String, all_paths: Vec<PathBuf>) -> AnnotatedS
     for (idx, path) in all_paths.iter().enumerate() {
         let path_str = path.to_str().unwrap().to_string();
         let selected_option = None;
+        let part = item_part(&all_paths, idx);
+        let index_of = |kind: PartKind| {
+            part.filter(|(part_kind, _)| *part_kind == kind)
+                .map(|(_, index)| index)
+        };
         let properties = Properties {
             index: idx,
             image_path: path_str,
             annotation: selected_option,
             comments: None,
             last_updated: Some(Local::now().to_string()),
+            page: index_of(PartKind::Page),
+            frame: index_of(PartKind::Frame),
+            ..Default::default()
         };
         vec_maps.push(properties);
     }
@@ -828,6 +2333,7 @@ mod test {
                     annotation: None,
                     comments: None,
                     last_updated: last_updated_time.cloned(),
+                    ..Default::default()
                 }],
             )]),
         };
@@ -868,6 +2374,7 @@ mod test {
                     annotation: None,
                     comments: None,
                     last_updated: None,
+                    ..Default::default()
                 }],
             )]),
         };
@@ -902,6 +2409,7 @@ mod test {
                     annotation: None,
                     comments: None,
                     last_updated: None,
+                    ..Default::default()
                 }],
             )]),
         };
@@ -920,10 +2428,11 @@ mod test {
                     annotation: None,
                     comments: None,
                     last_updated: None,
+                    ..Default::default()
                 }],
             )]),
         };
-        write_json(&store);
+        write_json(&store).unwrap();
         unsafe {
             let content =
                 std::fs::read_to_string(OUTPUT_PATH.as_str()).expect("Couldn't read file");
@@ -946,12 +2455,13 @@ mod test {
                     annotation: None,
                     comments: None,
                     last_updated: None,
+                    ..Default::default()
                 }],
             )]),
         };
 
         unsafe {
-            load_json_and_update(&OUTPUT_PATH, &first_store);
+            load_json_and_update(&OUTPUT_PATH, &first_store).unwrap();
             let json_file = std::fs::File::open(OUTPUT_PATH.to_string())
                 .expect("Couldn't read the file properly");
             println!("{:?}", json_file.metadata());
@@ -969,12 +2479,13 @@ mod test {
                     annotation: None,
                     comments: None,
                     last_updated: None,
+                    ..Default::default()
                 }],
             )]),
         };
 
         unsafe {
-            load_json_and_update(&OUTPUT_PATH, &second_store);
+            load_json_and_update(&OUTPUT_PATH, &second_store).unwrap();
         }
 
         // try reading JSON file now
@@ -987,4 +2498,17 @@ mod test {
         }
         exit();
     }
+
+    #[test]
+    fn test_visible_rows() {
+        let row_pitch = GALLERY_CELL_HEIGHT + GALLERY_ROW_SPACING;
+        assert_eq!(visible_rows(0.0, 0), 0..0);
+        assert_eq!(visible_rows(0.0, 20), 0..4);
+        assert_eq!(
+            visible_rows(100.0 * row_pitch, 6000),
+            100 - GALLERY_ROWS_AROUND..101 + GALLERY_ROWS_AROUND
+        );
+        // Scrolled past the end after images were removed
+        assert_eq!(visible_rows(1000.0 * row_pitch, 60), 1..10);
+    }
 }
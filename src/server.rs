@@ -0,0 +1,460 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+use super::adjustments::DisplayAdjustments;
+use super::formats::{frame_step, item_part, needs_tone_mapping, split_item, PartKind};
+use super::get_all_images;
+use super::metadata::open_oriented;
+use super::render_image::{init_json_obj, validate_image, AnnotatedStore, Properties};
+use super::storage::{restore_records, AnnotationStorage};
+use super::watcher::{apply_changes, known_files, scan_folder, WATCH_INTERVAL};
+
+// Routes served for the folder given on startup:
+//   GET  /images                  -> list of `ImageEntry`
+//   GET  /images/{index}          -> raw image bytes (only for files sniffed as images), or a
+//                                    PNG for pages, frames and images the viewer tone-maps
+//   GET  /annotations/{index}     -> `Properties` of the image
+//   PUT  /annotations/{index}     <- `AnnotationUpdate`, returns the updated `Properties`
+//   GET  /export                  -> `AnnotatedStore` of the folder, same schema as output.json
+//   POST /export                  -> saves the folder to the storage, returns the `AnnotatedStore`
+// The folder is listed again every `WATCH_INTERVAL`, like in the GUI.
+pub struct AnnotationServer {
+    http: Server,
+    folder_path: String,
+    all_images: Vec<PathBuf>,
+    records: Vec<Properties>,
+    // Records of the files removed since startup, restored if the file comes back
+    missing_records: Vec<Properties>,
+    // Sniffed once and again only when the folder changes
+    entries: Vec<ImageEntry>,
+    last_scan: Instant,
+    storage: Arc<dyn AnnotationStorage>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImageEntry {
+    pub index: usize,
+    pub image_path: String,
+    pub valid: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct AnnotationUpdate {
+    pub annotation: Option<bool>,
+    pub comments: Option<String>,
+    // Stores the verdict under this annotator's name instead of the single shared verdict
+    #[serde(default)]
+    pub annotator: Option<String>,
+}
+
+type HttpResponse = Response<io::Cursor<Vec<u8>>>;
+
+fn json_response<T: Serialize>(status: u16, value: &T) -> HttpResponse {
+    Response::from_string(serde_json::to_string_pretty(value).unwrap_or_default())
+        .with_status_code(StatusCode(status))
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+fn error_response(status: u16, reason: &str) -> HttpResponse {
+    json_response(status, &serde_json::json!({ "error": reason }))
+}
+
+fn mime_type(image_type: imghdr::Type) -> &'static str {
+    match image_type {
+        imghdr::Type::Gif => "image/gif",
+        imghdr::Type::Tiff => "image/tiff",
+        imghdr::Type::Jpeg => "image/jpeg",
+        imghdr::Type::Bmp => "image/bmp",
+        imghdr::Type::Png => "image/png",
+        imghdr::Type::Webp => "image/webp",
+        imghdr::Type::Exr => "image/x-exr",
+        imghdr::Type::Ico => "image/x-icon",
+        imghdr::Type::Xbm => "image/x-xbitmap",
+        imghdr::Type::Pbm => "image/x-portable-bitmap",
+        imghdr::Type::Pgm => "image/x-portable-graymap",
+        imghdr::Type::Ppm => "image/x-portable-pixmap",
+        imghdr::Type::Rgbe => "image/vnd.radiance",
+        _ => "application/octet-stream",
+    }
+}
+
+fn list_entries(all_images: &[PathBuf]) -> Vec<ImageEntry> {
+    all_images
+        .iter()
+        .enumerate()
+        .map(|(index, path)| ImageEntry {
+            index,
+            image_path: path.to_string_lossy().to_string(),
+            valid: validate_image(path).is_ok(),
+        })
+        .collect()
+}
+
+// The image as the viewer shows it, see `decode_image`
+fn render_png(path: &Path) -> Result<Vec<u8>, String> {
+    let pixels =
+        DisplayAdjustments::default().render(open_oriented(path).map_err(|e| e.to_string())?);
+    let mut bytes = vec![];
+    pixels
+        .write_to(
+            &mut io::Cursor::new(&mut bytes),
+            image::ImageOutputFormat::Png,
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
+
+impl AnnotationServer {
+    pub fn bind(
+        addr: &str,
+        folder_path: &str,
+        storage: Arc<dyn AnnotationStorage>,
+    ) -> io::Result<AnnotationServer> {
+        let http = Server::http(addr).map_err(io::Error::other)?;
+        let all_images = get_all_images(&folder_path.to_string());
+        let mut records = init_json_obj(folder_path.to_string(), all_images.clone())
+            .image_to_properties_map
+            .remove(folder_path)
+            .unwrap_or_default();
+        if let Some(saved_records) = storage.load_folder(folder_path)? {
+            restore_records(&mut records, &saved_records);
+        }
+        Ok(AnnotationServer {
+            http,
+            folder_path: folder_path.to_string(),
+            entries: list_entries(&all_images),
+            all_images,
+            records,
+            missing_records: vec![],
+            last_scan: Instant::now(),
+            storage,
+        })
+    }
+
+    pub fn local_addr(&self) -> Option<std::net::SocketAddr> {
+        self.http.server_addr().to_ip()
+    }
+
+    // Serves requests one at a time until the process is stopped
+    pub fn run(mut self) {
+        loop {
+            match self.http.recv_timeout(WATCH_INTERVAL) {
+                Ok(Some(request)) => self.handle(request),
+                Ok(None) => {}
+                Err(e) => println!("Failed to receive a request, error: {}", e),
+            }
+            if self.last_scan.elapsed() >= WATCH_INTERVAL {
+                self.refresh_folder();
+            }
+        }
+    }
+
+    // Lists the folder again, the records follow their file as in the GUI
+    fn refresh_folder(&mut self) {
+        self.last_scan = Instant::now();
+        let changes = scan_folder(
+            &self.folder_path,
+            &known_files(&self.all_images, &self.records),
+            frame_step(),
+        );
+        if changes.is_empty() {
+            return;
+        }
+        apply_changes(
+            &self.folder_path,
+            &mut self.all_images,
+            &mut self.records,
+            &mut self.missing_records,
+            &changes,
+        );
+        self.entries = list_entries(&self.all_images);
+    }
+
+    fn handle(&mut self, mut request: Request) {
+        let mut body = String::new();
+        let response = match request.as_reader().read_to_string(&mut body) {
+            Ok(_) => self.route(request.method(), request.url(), &body),
+            Err(e) => error_response(400, &e.to_string()),
+        };
+        if let Err(e) = request.respond(response) {
+            println!("Failed to send the response, error: {}", e);
+        }
+    }
+
+    fn route(&mut self, method: &Method, url: &str, body: &str) -> HttpResponse {
+        let path = url.split('?').next().unwrap_or_default();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match (method, segments.as_slice()) {
+            (Method::Get, ["images"]) => json_response(200, &self.entries),
+            (Method::Get, ["images", index]) => self.with_index(index, Self::get_image),
+            (Method::Get, ["annotations", index]) => self.with_index(index, |server, idx| {
+                json_response(200, &server.records[idx])
+            }),
+            (Method::Put, ["annotations", index]) => {
+                match serde_json::from_str::<AnnotationUpdate>(body) {
+                    Ok(update) => {
+                        self.with_index(index, |server, idx| server.put_annotation(idx, &update))
+                    }
+                    Err(e) => error_response(400, &e.to_string()),
+                }
+            }
+            (Method::Get, ["export"]) => json_response(200, &self.store()),
+            (Method::Post, ["export"]) => {
+                match self.storage.save_folder(&self.folder_path, &self.records) {
+                    Ok(_) => json_response(200, &self.store()),
+                    Err(e) => error_response(500, &e.to_string()),
+                }
+            }
+            _ => error_response(404, "Not found"),
+        }
+    }
+
+    fn with_index<F>(&mut self, index: &str, handler: F) -> HttpResponse
+    where
+        F: FnOnce(&mut Self, usize) -> HttpResponse,
+    {
+        match index.parse::<usize>() {
+            Ok(idx) if idx < self.records.len() => handler(self, idx),
+            _ => error_response(404, &format!("Invalid index: {}", index)),
+        }
+    }
+
+    fn store(&self) -> AnnotatedStore {
+        let mut store = AnnotatedStore::default();
+        store
+            .image_to_properties_map
+            .insert(self.folder_path.clone(), self.records.clone());
+        store
+    }
+
+    fn get_image(&mut self, idx: usize) -> HttpResponse {
+        let path = &self.all_images[idx];
+        let content_type = match validate_image(path) {
+            Ok(image_type) => mime_type(image_type),
+            Err(e) => return error_response(415, &e.to_string()),
+        };
+        // The type sniffed is the one of the whole file, pages and frames are extracted from
+        // it. The first frame too, the file itself would play the whole animation.
+        let is_frame = matches!(item_part(&self.all_images, idx), Some((PartKind::Frame, _)));
+        if is_frame || needs_tone_mapping(path) {
+            return match render_png(path) {
+                Ok(bytes) => Response::from_data(bytes)
+                    .with_header(Header::from_bytes("Content-Type", "image/png").unwrap()),
+                Err(e) => error_response(500, &e),
+            };
+        }
+        match std::fs::read(split_item(path).0) {
+            Ok(bytes) => Response::from_data(bytes)
+                .with_header(Header::from_bytes("Content-Type", content_type).unwrap()),
+            Err(e) => error_response(500, &e.to_string()),
+        }
+    }
+
+    fn put_annotation(&mut self, idx: usize, update: &AnnotationUpdate) -> HttpResponse {
+        let record = &mut self.records[idx];
+        record.set_verdict(
+            update.annotator.as_deref(),
+            update.annotation,
+            update.comments.clone(),
+        );
+        match self.storage.save_record(&self.folder_path, record) {
+            Ok(_) => json_response(200, record),
+            Err(e) => error_response(500, &e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::img_visualizer::storage::JsonStorage;
+    use image::codecs::gif::GifEncoder;
+    use image::{Frame, RgbaImage};
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    fn start_server(name: &str) -> (String, String) {
+        let folder = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        image::RgbImage::new(4, 4)
+            .save(folder.join("sample.png"))
+            .unwrap();
+        std::fs::write(folder.join("notes.txt"), "not an image").unwrap();
+        serve_folder(&folder)
+    }
+
+    fn serve_folder(folder: &Path) -> (String, String) {
+        let folder_path = folder.to_str().unwrap().to_string();
+        let json_path = folder.join("output.json").to_str().unwrap().to_string();
+
+        let storage = Arc::new(JsonStorage::new(&json_path));
+        let server = AnnotationServer::bind("127.0.0.1:0", &folder_path, storage).unwrap();
+        let base_url = format!("http://{}", server.local_addr().unwrap());
+        std::thread::spawn(move || server.run());
+        (base_url, folder_path)
+    }
+
+    fn index_of(entries: &[ImageEntry], file_name: &str) -> usize {
+        entries
+            .iter()
+            .find(|entry| entry.image_path.ends_with(file_name))
+            .unwrap()
+            .index
+    }
+
+    #[test]
+    fn test_list_and_get_images() {
+        let (base_url, _) = start_server("annotator_test_server_images");
+        let body = reqwest::blocking::get(format!("{}/images", base_url))
+            .unwrap()
+            .text()
+            .unwrap();
+        let entries: Vec<ImageEntry> = serde_json::from_str(&body).unwrap();
+        assert_eq!(entries.len(), 2);
+        let png_index = index_of(&entries, "sample.png");
+        let txt_index = index_of(&entries, "notes.txt");
+        assert!(entries[png_index].valid);
+        assert!(!entries[txt_index].valid);
+
+        let response =
+            reqwest::blocking::get(format!("{}/images/{}", base_url, png_index)).unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["Content-Type"], "image/png");
+        let response =
+            reqwest::blocking::get(format!("{}/images/{}", base_url, txt_index)).unwrap();
+        assert_eq!(response.status(), 415);
+        let response = reqwest::blocking::get(format!("{}/images/10", base_url)).unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[test]
+    fn test_refresh_folder() {
+        let folder = std::env::temp_dir().join("annotator_test_server_refresh");
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        image::RgbImage::new(4, 4)
+            .save(folder.join("first.png"))
+            .unwrap();
+        let folder_path = folder.to_str().unwrap().to_string();
+        let storage = Arc::new(JsonStorage::new(
+            folder.join("output.json").to_str().unwrap(),
+        ));
+        let mut server = AnnotationServer::bind("127.0.0.1:0", &folder_path, storage).unwrap();
+        assert_eq!(server.entries.len(), 1);
+
+        std::fs::write(folder.join("notes.txt"), "not an image").unwrap();
+        server.refresh_folder();
+        assert_eq!(server.entries.len(), 2);
+        assert!(!server.entries[index_of(&server.entries, "notes.txt")].valid);
+        assert_eq!(server.records.len(), 2);
+
+        std::fs::remove_file(folder.join("first.png")).unwrap();
+        server.refresh_folder();
+        assert_eq!(server.entries.len(), 1);
+        assert_eq!(server.entries[0].index, 0);
+        assert!(server.entries[0].image_path.ends_with("notes.txt"));
+    }
+
+    #[test]
+    fn test_mime_type() {
+        assert_eq!(mime_type(imghdr::Type::Jpeg), "image/jpeg");
+        assert_eq!(mime_type(imghdr::Type::Exr), "image/x-exr");
+        assert_eq!(mime_type(imghdr::Type::Rgb), "application/octet-stream");
+        assert_eq!(mime_type(imghdr::Type::Rast), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_get_page() {
+        let folder = std::env::temp_dir().join("annotator_test_server_pages");
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        {
+            let file = std::fs::File::create(folder.join("scan.tif")).unwrap();
+            let mut encoder = TiffEncoder::new(file).unwrap();
+            encoder
+                .write_image::<colortype::Gray16>(2, 1, &[0, 1000])
+                .unwrap();
+            encoder
+                .write_image::<colortype::Gray16>(3, 1, &[4000, 8000, 65535])
+                .unwrap();
+        }
+        let (base_url, _) = serve_folder(&folder);
+        let response = reqwest::blocking::get(format!("{}/images/1", base_url)).unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["Content-Type"], "image/png");
+        let page = image::load_from_memory(&response.bytes().unwrap()).unwrap();
+        assert_eq!((page.width(), page.height()), (3, 1));
+    }
+
+    #[test]
+    fn test_get_frames() {
+        let folder = std::env::temp_dir().join("annotator_test_server_frames");
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        {
+            let file = std::fs::File::create(folder.join("clip.gif")).unwrap();
+            // The red channel of each frame is its index times 100
+            let frames = (0..2u8).map(|index| {
+                Frame::new(RgbaImage::from_pixel(
+                    2,
+                    2,
+                    image::Rgba([index * 100, 0, 0, 255]),
+                ))
+            });
+            GifEncoder::new(file).encode_frames(frames).unwrap();
+        }
+        let (base_url, _) = serve_folder(&folder);
+        for (index, red) in [(0, 0), (1, 100)] {
+            let response =
+                reqwest::blocking::get(format!("{}/images/{}", base_url, index)).unwrap();
+            assert_eq!(response.status(), 200);
+            assert_eq!(response.headers()["Content-Type"], "image/png");
+            let frame = image::load_from_memory(&response.bytes().unwrap()).unwrap();
+            assert_eq!(frame.to_rgba8().get_pixel(0, 0).0, [red, 0, 0, 255]);
+        }
+    }
+
+    #[test]
+    fn test_put_annotation_and_export() {
+        let (base_url, folder_path) = start_server("annotator_test_server_annotations");
+        let client = reqwest::blocking::Client::new();
+        let update = AnnotationUpdate {
+            annotation: Some(false),
+            comments: Some("rotated".to_string()),
+            annotator: None,
+        };
+        let response = client
+            .put(format!("{}/annotations/0", base_url))
+            .body(serde_json::to_string(&update).unwrap())
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let body = client
+            .get(format!("{}/annotations/0", base_url))
+            .send()
+            .unwrap()
+            .text()
+            .unwrap();
+        let record: Properties = serde_json::from_str(&body).unwrap();
+        assert_eq!(record.annotation, Some(false));
+        assert_eq!(record.comments, Some("rotated".to_string()));
+
+        let body = client
+            .post(format!("{}/export", base_url))
+            .send()
+            .unwrap()
+            .text()
+            .unwrap();
+        let store: AnnotatedStore = serde_json::from_str(&body).unwrap();
+        assert_eq!(store.image_to_properties_map[&folder_path][0], record);
+        let saved = std::fs::read_to_string(format!("{}/output.json", folder_path)).unwrap();
+        let saved_store: AnnotatedStore = serde_json::from_str(&saved).unwrap();
+        assert_eq!(saved_store, store);
+    }
+}
@@ -0,0 +1,359 @@
+use chrono::Local;
+use std::collections::HashSet;
+
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions, SqliteRow};
+use sqlx::Row;
+use tokio::runtime::Runtime;
+
+use super::render_image::Properties;
+use super::storage::AnnotationStatus;
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS folders (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    path TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS images (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    folder_id INTEGER NOT NULL REFERENCES folders(id),
+    idx INTEGER NOT NULL,
+    image_path TEXT NOT NULL,
+    UNIQUE(folder_id, image_path)
+);
+CREATE TABLE IF NOT EXISTS annotations (
+    image_id INTEGER PRIMARY KEY REFERENCES images(id),
+    annotation INTEGER,
+    comments TEXT,
+    last_updated TEXT,
+    properties TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    image_id INTEGER NOT NULL REFERENCES images(id),
+    annotation INTEGER,
+    comments TEXT,
+    changed_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS images_by_folder ON images(folder_id, idx);
+CREATE INDEX IF NOT EXISTS history_by_image ON history(image_id);
+"#;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub annotation: Option<bool>,
+    pub comments: Option<String>,
+    pub changed_at: String,
+}
+
+// The storage trait is blocking for the CLI, the server and the GUI alike, so every query
+// is driven to completion on a small runtime owned by the store. `block_on` can't run on a
+// runtime worker, so async code calls the store through `spawn_blocking`.
+#[derive(Debug)]
+pub struct SqliteStore {
+    runtime: Runtime,
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> Result<SqliteStore, sqlx::Error> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true);
+        let pool = runtime.block_on(async {
+            let pool = SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect_with(options)
+                .await?;
+            sqlx::raw_sql(SCHEMA).execute(&pool).await?;
+            Ok::<_, sqlx::Error>(pool)
+        })?;
+        Ok(SqliteStore { runtime, pool })
+    }
+
+    // Writes only the records that differ from what is already stored, and appends a
+    // history row whenever the verdict or the comment of an image changed. Images of the
+    // folder missing from `records` are removed. Returns the number of records written.
+    pub fn save_folder(
+        &self,
+        folder_path: &str,
+        records: &[Properties],
+    ) -> Result<usize, sqlx::Error> {
+        self.write_records(folder_path, records, true)
+    }
+
+    // Same as `save_folder`, but leaves the other images of the folder untouched
+    pub fn save_records(
+        &self,
+        folder_path: &str,
+        records: &[Properties],
+    ) -> Result<usize, sqlx::Error> {
+        self.write_records(folder_path, records, false)
+    }
+
+    fn write_records(
+        &self,
+        folder_path: &str,
+        records: &[Properties],
+        remove_missing: bool,
+    ) -> Result<usize, sqlx::Error> {
+        self.runtime.block_on(async {
+            let mut tx = self.pool.begin().await?;
+            sqlx::query("INSERT OR IGNORE INTO folders (path) VALUES (?)")
+                .bind(folder_path)
+                .execute(&mut *tx)
+                .await?;
+            let folder_id: i64 = sqlx::query("SELECT id FROM folders WHERE path = ?")
+                .bind(folder_path)
+                .fetch_one(&mut *tx)
+                .await?
+                .get(0);
+
+            let mut written = 0;
+            for record in records {
+                let serialized = serde_json::to_string(record).unwrap_or_default();
+                sqlx::query(
+                    "INSERT INTO images (folder_id, idx, image_path) VALUES (?, ?, ?)
+                     ON CONFLICT(folder_id, image_path) DO UPDATE SET idx = excluded.idx",
+                )
+                .bind(folder_id)
+                .bind(record.index as i64)
+                .bind(&record.image_path)
+                .execute(&mut *tx)
+                .await?;
+                let image_id: i64 =
+                    sqlx::query("SELECT id FROM images WHERE folder_id = ? AND image_path = ?")
+                        .bind(folder_id)
+                        .bind(&record.image_path)
+                        .fetch_one(&mut *tx)
+                        .await?
+                        .get(0);
+
+                let existing = sqlx::query(
+                    "SELECT annotation, comments, properties FROM annotations WHERE image_id = ?",
+                )
+                .bind(image_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+                let verdict_changed = match &existing {
+                    Some(row) => {
+                        if row.get::<String, _>(2) == serialized {
+                            continue;
+                        }
+                        row.get::<Option<bool>, _>(0) != record.annotation
+                            || row.get::<Option<String>, _>(1) != record.comments
+                    }
+                    None => record.annotation.is_some() || record.comments.is_some(),
+                };
+
+                sqlx::query(
+                    "INSERT INTO annotations (image_id, annotation, comments, last_updated, properties)
+                     VALUES (?, ?, ?, ?, ?)
+                     ON CONFLICT(image_id) DO UPDATE SET
+                        annotation = excluded.annotation,
+                        comments = excluded.comments,
+                        last_updated = excluded.last_updated,
+                        properties = excluded.properties",
+                )
+                .bind(image_id)
+                .bind(record.annotation)
+                .bind(&record.comments)
+                .bind(&record.last_updated)
+                .bind(&serialized)
+                .execute(&mut *tx)
+                .await?;
+
+                if verdict_changed {
+                    sqlx::query(
+                        "INSERT INTO history (image_id, annotation, comments, changed_at)
+                         VALUES (?, ?, ?, ?)",
+                    )
+                    .bind(image_id)
+                    .bind(record.annotation)
+                    .bind(&record.comments)
+                    .bind(Local::now().to_string())
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                written += 1;
+            }
+
+            if remove_missing {
+                let stored = sqlx::query("SELECT id, image_path FROM images WHERE folder_id = ?")
+                    .bind(folder_id)
+                    .fetch_all(&mut *tx)
+                    .await?;
+                let kept: HashSet<&str> = records
+                    .iter()
+                    .map(|record| record.image_path.as_str())
+                    .collect();
+                for row in stored {
+                    if kept.contains(row.get::<&str, _>(1)) {
+                        continue;
+                    }
+                    let image_id: i64 = row.get(0);
+                    for table_query in [
+                        "DELETE FROM history WHERE image_id = ?",
+                        "DELETE FROM annotations WHERE image_id = ?",
+                        "DELETE FROM images WHERE id = ?",
+                    ] {
+                        sqlx::query(table_query)
+                            .bind(image_id)
+                            .execute(&mut *tx)
+                            .await?;
+                    }
+                }
+            }
+            tx.commit().await?;
+            Ok(written)
+        })
+    }
+
+    pub fn load_folder(&self, folder_path: &str) -> Result<Option<Vec<Properties>>, sqlx::Error> {
+        self.runtime.block_on(async {
+            let folder = sqlx::query("SELECT id FROM folders WHERE path = ?")
+                .bind(folder_path)
+                .fetch_optional(&self.pool)
+                .await?;
+            let folder_id: i64 = match folder {
+                Some(row) => row.get(0),
+                None => return Ok(None),
+            };
+            let rows = sqlx::query(
+                "SELECT a.properties FROM images i
+                 JOIN annotations a ON a.image_id = i.id
+                 WHERE i.folder_id = ? ORDER BY i.idx",
+            )
+            .bind(folder_id)
+            .fetch_all(&self.pool)
+            .await?;
+            rows.iter()
+                .map(decode_properties)
+                .collect::<Result<Vec<_>, _>>()
+                .map(Some)
+        })
+    }
+
+    pub fn query_by_status(
+        &self,
+        folder_path: &str,
+        status: AnnotationStatus,
+    ) -> Result<Vec<Properties>, sqlx::Error> {
+        let condition = match status {
+            AnnotationStatus::Correct => "a.annotation = 1",
+            AnnotationStatus::Incorrect => "a.annotation = 0",
+            AnnotationStatus::Unreviewed => "a.annotation IS NULL",
+        };
+        self.runtime.block_on(async {
+            let rows = sqlx::query(&format!(
+                "SELECT a.properties FROM folders f
+                 JOIN images i ON i.folder_id = f.id
+                 JOIN annotations a ON a.image_id = i.id
+                 WHERE f.path = ? AND {} ORDER BY i.idx",
+                condition
+            ))
+            .bind(folder_path)
+            .fetch_all(&self.pool)
+            .await?;
+            rows.iter().map(decode_properties).collect()
+        })
+    }
+
+    pub fn list_folders(&self) -> Result<Vec<String>, sqlx::Error> {
+        self.runtime.block_on(async {
+            let rows = sqlx::query("SELECT path FROM folders ORDER BY path")
+                .fetch_all(&self.pool)
+                .await?;
+            Ok(rows.iter().map(|row| row.get(0)).collect())
+        })
+    }
+
+    pub fn history(&self, image_path: &str) -> Result<Vec<HistoryEntry>, sqlx::Error> {
+        self.runtime.block_on(async {
+            let rows = sqlx::query(
+                "SELECT h.annotation, h.comments, h.changed_at FROM history h
+                 JOIN images i ON i.id = h.image_id
+                 WHERE i.image_path = ? ORDER BY h.id",
+            )
+            .bind(image_path)
+            .fetch_all(&self.pool)
+            .await?;
+            Ok(rows
+                .iter()
+                .map(|row| HistoryEntry {
+                    annotation: row.get(0),
+                    comments: row.get(1),
+                    changed_at: row.get(2),
+                })
+                .collect())
+        })
+    }
+}
+
+fn decode_properties(row: &SqliteRow) -> Result<Properties, sqlx::Error> {
+    serde_json::from_str::<Properties>(&row.get::<String, _>(0))
+        .map_err(|e| sqlx::Error::Decode(Box::new(e)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample_record(index: usize, annotation: Option<bool>) -> Properties {
+        Properties {
+            index,
+            image_path: format!("test/sample_{}.jpg", index),
+            annotation,
+            comments: None,
+            last_updated: None,
+            ..Default::default()
+        }
+    }
+
+    fn open_temp(name: &str) -> (SqliteStore, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        (SqliteStore::open(path.to_str().unwrap()).unwrap(), path)
+    }
+
+    #[test]
+    fn test_save_and_load_folder() {
+        let (store, path) = open_temp("annotator_test_save_and_load.sqlite");
+        let records = vec![sample_record(0, Some(true)), sample_record(1, None)];
+        assert_eq!(store.save_folder("test", &records).unwrap(), 2);
+        assert_eq!(store.load_folder("test").unwrap(), Some(records));
+        assert_eq!(store.load_folder("missing").unwrap(), None);
+        assert_eq!(store.list_folders().unwrap(), vec!["test".to_string()]);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_save_folder_is_incremental() {
+        let (store, path) = open_temp("annotator_test_incremental.sqlite");
+        let mut records = vec![sample_record(0, None), sample_record(1, None)];
+        store.save_folder("test", &records).unwrap();
+        assert_eq!(store.save_folder("test", &records).unwrap(), 0);
+
+        records[1].annotation = Some(false);
+        records[1].comments = Some("blurry".to_string());
+        assert_eq!(store.save_folder("test", &records).unwrap(), 1);
+
+        let history = store.history("test/sample_1.jpg").unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].annotation, Some(false));
+        assert_eq!(history[0].comments, Some("blurry".to_string()));
+        assert!(store.history("test/sample_0.jpg").unwrap().is_empty());
+
+        // Records missing from a folder save are removed, unless only some records are saved
+        store.save_records("test", &records[1..]).unwrap();
+        assert_eq!(store.load_folder("test").unwrap().unwrap().len(), 2);
+        store.save_folder("test", &records[1..]).unwrap();
+        assert_eq!(
+            store.load_folder("test").unwrap(),
+            Some(records[1..].to_vec())
+        );
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+use once_cell::sync::OnceCell;
+
+use super::annotators::current_annotator;
+use super::config::StorageBackend;
+use super::render_image::{
+    load_json_and_update, write_json, AnnotatedStore, Properties, OUTPUT_PATH,
+};
+use super::sqlite_store::SqliteStore;
+
+#[derive(PartialEq, Clone, Eq, Copy, Debug)]
+pub enum AnnotationStatus {
+    Correct,
+    Incorrect,
+    Unreviewed,
+}
+
+impl AnnotationStatus {
+    pub fn matches(&self, record: &Properties, annotator: Option<&str>) -> bool {
+        let annotation = record.effective_verdict(annotator);
+        match self {
+            Self::Correct => annotation == Some(true),
+            Self::Incorrect => annotation == Some(false),
+            Self::Unreviewed => annotation.is_none(),
+        }
+    }
+}
+
+// Everything `Steps` needs from a place where annotations are kept. Records of a folder
+// are identified by their `image_path`.
+pub trait AnnotationStorage: std::fmt::Debug + Send + Sync {
+    fn load_folder(&self, folder_path: &str) -> io::Result<Option<Vec<Properties>>>;
+
+    // Replaces all the records of the folder
+    fn save_folder(&self, folder_path: &str, records: &[Properties]) -> io::Result<()>;
+
+    // Inserts the record, or replaces the one with the same image path
+    fn save_record(&self, folder_path: &str, record: &Properties) -> io::Result<()>;
+
+    fn list_folders(&self) -> io::Result<Vec<String>>;
+
+    // Every folder with its records, sorted by folder path
+    fn load_all(&self) -> io::Result<Vec<(String, Vec<Properties>)>> {
+        let mut folders = vec![];
+        for folder_path in self.list_folders()? {
+            if let Some(records) = self.load_folder(&folder_path)? {
+                folders.push((folder_path, records));
+            }
+        }
+        Ok(folders)
+    }
+
+    // Replaces the records of every folder given, the other folders are left as they are
+    fn save_all(&self, folders: &[(String, Vec<Properties>)]) -> io::Result<()> {
+        for (folder_path, records) in folders {
+            self.save_folder(folder_path, records)?;
+        }
+        Ok(())
+    }
+
+    fn query_by_status(
+        &self,
+        folder_path: &str,
+        status: AnnotationStatus,
+    ) -> io::Result<Vec<Properties>> {
+        filter_by_status(self.load_folder(folder_path)?, status)
+    }
+}
+
+fn filter_by_status(
+    records: Option<Vec<Properties>>,
+    status: AnnotationStatus,
+) -> io::Result<Vec<Properties>> {
+    Ok(records
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|record| status.matches(record, current_annotator()))
+        .collect())
+}
+
+// The JSON file that has always been used as output, re-read and rewritten on every save.
+#[derive(Debug, Default, Clone)]
+pub struct JsonStorage {
+    // None writes to OUTPUT_PATH
+    path: Option<String>,
+}
+
+impl JsonStorage {
+    pub fn new(path: &str) -> JsonStorage {
+        JsonStorage {
+            path: Some(path.to_string()),
+        }
+    }
+
+    fn path(&self) -> String {
+        match &self.path {
+            Some(path) => path.clone(),
+            None => unsafe { (*std::ptr::addr_of!(OUTPUT_PATH)).to_string() },
+        }
+    }
+
+    fn read(&self) -> io::Result<AnnotatedStore> {
+        let path = self.path();
+        if !std::path::Path::new(&path).exists() {
+            return Ok(AnnotatedStore::default());
+        }
+        let content = std::fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl AnnotationStorage for JsonStorage {
+    fn load_folder(&self, folder_path: &str) -> io::Result<Option<Vec<Properties>>> {
+        Ok(self.read()?.image_to_properties_map.remove(folder_path))
+    }
+
+    fn save_folder(&self, folder_path: &str, records: &[Properties]) -> io::Result<()> {
+        let mut store = AnnotatedStore::default();
+        store
+            .image_to_properties_map
+            .insert(folder_path.to_string(), records.to_vec());
+        match &self.path {
+            Some(path) => load_json_and_update(path, &store),
+            None => write_json(&store),
+        }
+    }
+
+    fn save_record(&self, folder_path: &str, record: &Properties) -> io::Result<()> {
+        let mut records = self.load_folder(folder_path)?.unwrap_or_default();
+        match records
+            .iter_mut()
+            .find(|existing| existing.image_path == record.image_path)
+        {
+            Some(existing) => *existing = record.clone(),
+            None => records.push(record.clone()),
+        }
+        self.save_folder(folder_path, &records)
+    }
+
+    fn list_folders(&self) -> io::Result<Vec<String>> {
+        let mut folders: Vec<String> = self.read()?.image_to_properties_map.into_keys().collect();
+        folders.sort();
+        Ok(folders)
+    }
+
+    // The file is parsed once rather than once per folder
+    fn load_all(&self) -> io::Result<Vec<(String, Vec<Properties>)>> {
+        let mut folders: Vec<(String, Vec<Properties>)> =
+            self.read()?.image_to_properties_map.into_iter().collect();
+        folders.sort_by(|first, second| first.0.cmp(&second.0));
+        Ok(folders)
+    }
+
+    // Read and rewritten once for all the folders
+    fn save_all(&self, folders: &[(String, Vec<Properties>)]) -> io::Result<()> {
+        let store = AnnotatedStore {
+            image_to_properties_map: folders.iter().cloned().collect(),
+        };
+        match &self.path {
+            Some(path) => load_json_and_update(path, &store),
+            None => write_json(&store),
+        }
+    }
+}
+
+impl AnnotationStorage for SqliteStore {
+    fn load_folder(&self, folder_path: &str) -> io::Result<Option<Vec<Properties>>> {
+        SqliteStore::load_folder(self, folder_path).map_err(io::Error::other)
+    }
+
+    fn save_folder(&self, folder_path: &str, records: &[Properties]) -> io::Result<()> {
+        SqliteStore::save_folder(self, folder_path, records)
+            .map(|_| ())
+            .map_err(io::Error::other)
+    }
+
+    fn save_record(&self, folder_path: &str, record: &Properties) -> io::Result<()> {
+        SqliteStore::save_records(self, folder_path, std::slice::from_ref(record))
+            .map(|_| ())
+            .map_err(io::Error::other)
+    }
+
+    fn list_folders(&self) -> io::Result<Vec<String>> {
+        SqliteStore::list_folders(self).map_err(io::Error::other)
+    }
+
+    fn query_by_status(
+        &self,
+        folder_path: &str,
+        status: AnnotationStatus,
+    ) -> io::Result<Vec<Properties>> {
+        // Only the verdict of a single annotator has its own column
+        match current_annotator() {
+            Some(_) => filter_by_status(AnnotationStorage::load_folder(self, folder_path)?, status),
+            None => {
+                SqliteStore::query_by_status(self, folder_path, status).map_err(io::Error::other)
+            }
+        }
+    }
+}
+
+pub fn open_storage(backend: &StorageBackend) -> io::Result<Arc<dyn AnnotationStorage>> {
+    match backend {
+        StorageBackend::Json => Ok(Arc::new(JsonStorage::default())),
+        StorageBackend::Sqlite(db_path) => SqliteStore::open(db_path)
+            .map(|store| Arc::new(store) as Arc<dyn AnnotationStorage>)
+            .map_err(io::Error::other),
+    }
+}
+
+// Brings back the saved records of images that are still in the folder, keeping the
+// current position of every image
+pub fn restore_records(records: &mut [Properties], saved_records: &[Properties]) {
+    let mut saved_by_path: HashMap<&str, &Properties> = saved_records
+        .iter()
+        .map(|saved| (saved.image_path.as_str(), saved))
+        .collect();
+    for record in records.iter_mut() {
+        if let Some(saved) = saved_by_path.remove(record.image_path.as_str()) {
+            *record = Properties {
+                index: record.index,
+                ..saved.clone()
+            };
+        }
+    }
+}
+
+// Copies every folder of `from` into `to`, returns the number of records copied
+pub fn copy_storage(from: &dyn AnnotationStorage, to: &dyn AnnotationStorage) -> io::Result<usize> {
+    let folders = from.load_all()?;
+    to.save_all(&folders)?;
+    Ok(folders.iter().map(|(_, records)| records.len()).sum())
+}
+
+static STORAGE: OnceCell<StorageHandle> = OnceCell::new();
+
+// Cheap to clone, every clone talks to the same backend
+#[derive(Clone, Debug)]
+pub struct StorageHandle(Arc<dyn AnnotationStorage>);
+
+impl StorageHandle {
+    // Opens the backend once at startup, later calls are ignored
+    pub fn init(backend: &StorageBackend) -> io::Result<()> {
+        let storage = open_storage(backend)?;
+        let _ = STORAGE.set(StorageHandle(storage));
+        Ok(())
+    }
+}
+
+impl Default for StorageHandle {
+    fn default() -> Self {
+        STORAGE
+            .get_or_init(|| StorageHandle(Arc::new(JsonStorage::default())))
+            .clone()
+    }
+}
+
+impl std::ops::Deref for StorageHandle {
+    type Target = dyn AnnotationStorage;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+// Shared behaviour every backend has to provide, run against each implementation below.
+#[cfg(test)]
+pub mod conformance {
+    use super::*;
+
+    fn record(index: usize, annotation: Option<bool>, comments: Option<&str>) -> Properties {
+        Properties {
+            index,
+            image_path: format!("folder/image_{}.jpg", index),
+            annotation,
+            comments: comments.map(|comment| comment.to_string()),
+            last_updated: None,
+            ..Default::default()
+        }
+    }
+
+    pub fn run(storage: &dyn AnnotationStorage) {
+        assert_eq!(storage.load_folder("folder").unwrap(), None);
+        assert!(storage.list_folders().unwrap().is_empty());
+
+        let records = vec![
+            record(0, Some(true), None),
+            record(1, Some(false), Some("blurry")),
+            record(2, None, None),
+        ];
+        storage.save_folder("folder", &records).unwrap();
+        storage
+            .save_folder("other", &[record(0, None, None)])
+            .unwrap();
+        assert_eq!(
+            storage.load_folder("folder").unwrap(),
+            Some(records.clone())
+        );
+        assert_eq!(
+            storage.list_folders().unwrap(),
+            vec!["folder".to_string(), "other".to_string()]
+        );
+
+        // Replacing an existing record keeps the others untouched
+        let updated = record(2, Some(false), Some("cropped"));
+        storage.save_record("folder", &updated).unwrap();
+        let loaded = storage.load_folder("folder").unwrap().unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded[0], records[0]);
+        assert_eq!(loaded[2], updated);
+
+        // Saving a record for an unknown image appends it
+        let appended = record(3, None, None);
+        storage.save_record("folder", &appended).unwrap();
+        assert_eq!(storage.load_folder("folder").unwrap().unwrap()[3], appended);
+
+        let correct = storage
+            .query_by_status("folder", AnnotationStatus::Correct)
+            .unwrap();
+        assert_eq!(correct, vec![records[0].clone()]);
+        let incorrect = storage
+            .query_by_status("folder", AnnotationStatus::Incorrect)
+            .unwrap();
+        assert_eq!(incorrect, vec![records[1].clone(), updated]);
+        let unreviewed = storage
+            .query_by_status("folder", AnnotationStatus::Unreviewed)
+            .unwrap();
+        assert_eq!(unreviewed, vec![appended]);
+        assert!(storage
+            .query_by_status("missing", AnnotationStatus::Unreviewed)
+            .unwrap()
+            .is_empty());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::img_visualizer::render_image::Resolution;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_json_storage_conformance() {
+        let path = temp_path("annotator_test_conformance.json");
+        conformance::run(&JsonStorage::new(&path));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_json_storage_reports_errors() {
+        let path = temp_path("annotator_test_invalid.json");
+        std::fs::write(&path, "{ not json").unwrap();
+        let storage = JsonStorage::new(&path);
+        assert!(storage.load_folder("folder").is_err());
+        assert!(storage.save_folder("folder", &[]).is_err());
+        // The file is left as it was
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{ not json");
+        let _ = std::fs::remove_file(path);
+
+        let unwritable = std::env::temp_dir()
+            .join("annotator_test_no_such_folder")
+            .join("output.json");
+        let storage = JsonStorage::new(unwritable.to_str().unwrap());
+        assert!(storage.save_folder("folder", &[]).is_err());
+    }
+
+    #[test]
+    fn test_status_of_several_annotators() {
+        let mut record = Properties {
+            annotation: Some(true),
+            ..Default::default()
+        };
+        record.set_verdict(Some("alice"), Some(false), None);
+        assert!(AnnotationStatus::Correct.matches(&record, None));
+        assert!(AnnotationStatus::Incorrect.matches(&record, Some("alice")));
+        assert!(AnnotationStatus::Unreviewed.matches(&record, Some("bob")));
+
+        record.resolution = Some(Resolution {
+            adjudicator: "carol".to_string(),
+            annotation: Some(true),
+            ..Default::default()
+        });
+        assert!(AnnotationStatus::Correct.matches(&record, Some("alice")));
+        assert!(AnnotationStatus::Correct.matches(&record, Some("bob")));
+    }
+
+    #[test]
+    fn test_copy_storage_json_to_sqlite() {
+        let json_path = temp_path("annotator_test_copy.json");
+        let db_path = temp_path("annotator_test_copy.sqlite");
+        let json_storage = JsonStorage::new(&json_path);
+        let records = vec![Properties {
+            index: 0,
+            image_path: String::from("test/sample.jpg"),
+            annotation: Some(false),
+            comments: Some(String::from("blurry")),
+            last_updated: None,
+            ..Default::default()
+        }];
+        json_storage.save_folder("first", &records).unwrap();
+        json_storage.save_folder("second", &[]).unwrap();
+
+        let sqlite_storage = SqliteStore::open(&db_path).unwrap();
+        assert_eq!(copy_storage(&json_storage, &sqlite_storage).unwrap(), 1);
+        assert_eq!(
+            AnnotationStorage::load_folder(&sqlite_storage, "first").unwrap(),
+            Some(records.clone())
+        );
+        assert_eq!(
+            AnnotationStorage::list_folders(&sqlite_storage).unwrap(),
+            vec!["first".to_string(), "second".to_string()]
+        );
+
+        // And back to another JSON file
+        let copy_path = temp_path("annotator_test_copy_back.json");
+        let json_copy = JsonStorage::new(&copy_path);
+        assert_eq!(copy_storage(&sqlite_storage, &json_copy).unwrap(), 1);
+        assert_eq!(
+            json_copy.load_all().unwrap(),
+            vec![
+                ("first".to_string(), records),
+                ("second".to_string(), vec![])
+            ]
+        );
+        let _ = std::fs::remove_file(json_path);
+        let _ = std::fs::remove_file(db_path);
+        let _ = std::fs::remove_file(copy_path);
+    }
+
+    #[test]
+    fn test_sqlite_storage_conformance() {
+        let path = temp_path("annotator_test_conformance.sqlite");
+        conformance::run(&SqliteStore::open(&path).unwrap());
+        let _ = std::fs::remove_file(path);
+    }
+}
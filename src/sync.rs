@@ -0,0 +1,654 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use once_cell::sync::OnceCell;
+use reqwest::blocking::Client;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use super::annotators::merge_verdicts;
+use super::render_image::Properties;
+use super::storage::StorageHandle;
+
+// The annotation server is expected to expose, for every folder path (URL encoded as a
+// single path segment):
+//   GET {base_url}/folders/{folder_path}/records -> JSON list of `Properties` (404 if unknown)
+//   PUT {base_url}/folders/{folder_path}/records <- JSON list of `Properties` to upsert
+// Requests carry `Authorization: Bearer {token}` when a token is configured.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncConfig {
+    pub base_url: String,
+    pub token: Option<String>,
+    pub pull_interval: Duration,
+    // Remembers what was synced last and the pushes that couldn't be sent, across restarts
+    pub state_path: String,
+}
+
+impl SyncConfig {
+    pub fn new(base_url: &str) -> SyncConfig {
+        SyncConfig {
+            base_url: base_url.to_string(),
+            token: None,
+            pull_interval: Duration::from_secs(30),
+            state_path: home::home_dir()
+                .unwrap_or_default()
+                .join("annotator_sync_state.json")
+                .to_string_lossy()
+                .to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncConflict {
+    pub folder_path: String,
+    pub local: Properties,
+    pub remote: Properties,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncOutcome {
+    pub pushed: usize,
+    pub pulled: usize,
+    pub queued: usize,
+    pub conflicts: Vec<SyncConflict>,
+    pub error: Option<String>,
+}
+
+impl SyncOutcome {
+    pub fn summary(&self) -> String {
+        match &self.error {
+            Some(e) => format!("Sync failed: {}", e),
+            None => format!(
+                "Synced: {} pushed, {} pulled, {} queued offline, {} conflicts",
+                self.pushed,
+                self.pulled,
+                self.queued,
+                self.conflicts.len()
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncDirection {
+    Push,
+    Pull,
+}
+
+// A push or pull run by a background worker on a copy of the records of a folder
+#[derive(Clone, Debug)]
+pub struct SyncJob {
+    pub folder_path: String,
+    // The records as they were sent, and as they came back
+    pub sent: Vec<Properties>,
+    pub records: Vec<Properties>,
+    pub outcome: SyncOutcome,
+}
+
+impl SyncJob {
+    // Takes the synced records into `local`. A record edited while the job ran keeps the
+    // edit and only gets the verdicts that came back.
+    pub fn apply_to(&self, local: &mut [Properties]) {
+        let positions: HashMap<String, usize> = local
+            .iter()
+            .enumerate()
+            .map(|(position, record)| (record.image_path.clone(), position))
+            .collect();
+        for (sent, synced) in self.sent.iter().zip(self.records.iter()) {
+            let local_record = match positions.get(&synced.image_path) {
+                Some(position) => &mut local[*position],
+                None => continue,
+            };
+            // Positions change when files are added or removed, not the record
+            let unchanged = Properties {
+                index: sent.index,
+                ..local_record.clone()
+            } == *sent;
+            match unchanged {
+                true => {
+                    *local_record = Properties {
+                        index: local_record.index,
+                        ..synced.clone()
+                    }
+                }
+                false => {
+                    merge_verdicts(local_record, synced);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+struct QueuedPush {
+    folder_path: String,
+    records: Vec<Properties>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct SyncState {
+    // `last_updated` of every record as of its last successful sync, by folder then image path
+    synced: HashMap<String, HashMap<String, Option<String>>>,
+    // Pushes that failed because the server couldn't be reached
+    queue: Vec<QueuedPush>,
+}
+
+enum SyncError {
+    // Server unreachable, the push is kept in the offline queue
+    Offline(String),
+    Failed(String),
+}
+
+impl From<reqwest::Error> for SyncError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_connect() || e.is_timeout() || e.is_request() {
+            SyncError::Offline(e.to_string())
+        } else {
+            SyncError::Failed(e.to_string())
+        }
+    }
+}
+
+// Two records hold the same verdict if they only differ in position, timestamp, what was
+// read from the file on each machine, or in the per-annotator verdicts, which are merged
+// separately
+fn same_content(first: &Properties, second: &Properties) -> bool {
+    let normalize = |record: &Properties| Properties {
+        index: 0,
+        last_updated: None,
+        verdicts: Default::default(),
+        image_info: None,
+        quality_warnings: vec![],
+        fingerprint: None,
+        file_status: None,
+        ..record.clone()
+    };
+    normalize(first) == normalize(second)
+}
+
+fn is_unreviewed(record: &Properties) -> bool {
+    record.annotation.is_none() && record.comments.is_none()
+}
+
+#[derive(Debug)]
+pub struct SyncClient {
+    config: SyncConfig,
+    http: Client,
+    state: Mutex<SyncState>,
+}
+
+impl SyncClient {
+    pub fn new(config: SyncConfig) -> Result<SyncClient, String> {
+        Url::parse(&config.base_url).map_err(|e| format!("{}: {}", config.base_url, e))?;
+        let http = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|e| e.to_string())?;
+        let state = std::fs::read_to_string(&config.state_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Ok(SyncClient {
+            config,
+            http,
+            state: Mutex::new(state),
+        })
+    }
+
+    fn records_url(&self, folder_path: &str) -> Url {
+        // Validated in `new`
+        let mut url = Url::parse(&self.config.base_url).unwrap();
+        url.path_segments_mut()
+            .unwrap()
+            .pop_if_empty()
+            .extend(["folders", folder_path, "records"]);
+        url
+    }
+
+    fn fetch(&self, folder_path: &str) -> Result<Vec<Properties>, SyncError> {
+        let mut request = self.http.get(self.records_url(folder_path));
+        if let Some(token) = &self.config.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send()?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(vec![]);
+        }
+        let body = response.error_for_status()?.text()?;
+        serde_json::from_str(&body).map_err(|e| SyncError::Failed(e.to_string()))
+    }
+
+    fn send(&self, folder_path: &str, records: &[Properties]) -> Result<(), SyncError> {
+        let mut request = self
+            .http
+            .put(self.records_url(folder_path))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(records).unwrap_or_default());
+        if let Some(token) = &self.config.token {
+            request = request.bearer_auth(token);
+        }
+        request.send()?.error_for_status()?;
+        Ok(())
+    }
+
+    fn save_state(&self, state: &SyncState) {
+        let res = std::fs::write(
+            &self.config.state_path,
+            serde_json::to_string_pretty(state).unwrap_or_default(),
+        );
+        if let Err(e) = res {
+            println!(
+                "Couldn't save the sync state to {}, error: {}",
+                self.config.state_path, e
+            );
+        }
+    }
+
+    // Applies remote changes to `local` where only the remote side changed since the last
+    // sync, and reports the records changed on both sides as conflicts.
+    fn merge(
+        &self,
+        state: &mut SyncState,
+        folder_path: &str,
+        local: &mut [Properties],
+        remote: &[Properties],
+        outcome: &mut SyncOutcome,
+    ) {
+        let synced = state.synced.entry(folder_path.to_string()).or_default();
+        let positions: HashMap<String, usize> = local
+            .iter()
+            .enumerate()
+            .map(|(position, record)| (record.image_path.clone(), position))
+            .collect();
+        for remote_record in remote {
+            let local_record = match positions.get(&remote_record.image_path) {
+                Some(position) => &mut local[*position],
+                None => continue,
+            };
+            // Annotators only edit their own verdict, those never conflict
+            let verdicts_pulled = merge_verdicts(local_record, remote_record);
+            if same_content(local_record, remote_record) {
+                synced.insert(
+                    remote_record.image_path.clone(),
+                    local_record.last_updated.clone(),
+                );
+                if verdicts_pulled {
+                    outcome.pulled += 1;
+                }
+                continue;
+            }
+            let base = synced.get(&remote_record.image_path).cloned();
+            if base.as_ref() == Some(&remote_record.last_updated) {
+                // Only the local side changed, it gets pushed
+                continue;
+            }
+            let take_remote = match &base {
+                Some(synced_version) => *synced_version == local_record.last_updated,
+                None => is_unreviewed(local_record),
+            };
+            if take_remote {
+                let previous = std::mem::replace(local_record, remote_record.clone());
+                local_record.index = previous.index;
+                merge_verdicts(local_record, &previous);
+                synced.insert(
+                    remote_record.image_path.clone(),
+                    remote_record.last_updated.clone(),
+                );
+                outcome.pulled += 1;
+                continue;
+            }
+            if base.is_none() && is_unreviewed(remote_record) {
+                continue;
+            }
+            outcome.conflicts.push(SyncConflict {
+                folder_path: folder_path.to_string(),
+                local: local_record.clone(),
+                remote: remote_record.clone(),
+            });
+        }
+    }
+
+    fn push_locked(
+        &self,
+        state: &mut SyncState,
+        folder_path: &str,
+        local: &mut [Properties],
+        outcome: &mut SyncOutcome,
+    ) -> Result<(), SyncError> {
+        let remote = self.fetch(folder_path)?;
+        self.merge(state, folder_path, local, &remote, outcome);
+
+        let conflicting: HashSet<&str> = outcome
+            .conflicts
+            .iter()
+            .map(|conflict| conflict.local.image_path.as_str())
+            .collect();
+        let synced = state.synced.entry(folder_path.to_string()).or_default();
+        let changed: Vec<Properties> = local
+            .iter()
+            .filter(|record| synced.get(&record.image_path) != Some(&record.last_updated))
+            .filter(|record| !conflicting.contains(record.image_path.as_str()))
+            .cloned()
+            .collect();
+        if changed.is_empty() {
+            return Ok(());
+        }
+        self.send(folder_path, &changed)?;
+        for record in changed.iter() {
+            synced.insert(record.image_path.clone(), record.last_updated.clone());
+        }
+        outcome.pushed += changed.len();
+        Ok(())
+    }
+
+    fn flush_queue(&self, state: &mut SyncState, outcome: &mut SyncOutcome) {
+        for mut queued in std::mem::take(&mut state.queue) {
+            let mut flushed = SyncOutcome::default();
+            match self.push_locked(
+                state,
+                &queued.folder_path,
+                &mut queued.records,
+                &mut flushed,
+            ) {
+                Ok(_) => {
+                    outcome.pushed += flushed.pushed;
+                    outcome.conflicts.append(&mut flushed.conflicts);
+                }
+                Err(_) => state.queue.push(queued),
+            }
+        }
+    }
+
+    // Pulls the remote records of the folder into `local`, then pushes the local records
+    // that changed since the last sync. Records changed on both sides are left out and
+    // returned as conflicts. If the server can't be reached, the push is queued.
+    pub fn push(&self, folder_path: &str, local: &mut [Properties]) -> SyncOutcome {
+        let mut outcome = SyncOutcome::default();
+        let mut state = self.state.lock().unwrap();
+        self.flush_queue(&mut state, &mut outcome);
+        match self.push_locked(&mut state, folder_path, local, &mut outcome) {
+            Ok(_) => {}
+            Err(SyncError::Offline(e)) => {
+                // Keep only the latest version of every queued record, leaving out the
+                // images nobody has looked at yet
+                let synced = state.synced.entry(folder_path.to_string()).or_default();
+                let records: Vec<Properties> = local
+                    .iter()
+                    .filter(|record| match synced.get(&record.image_path) {
+                        Some(synced_version) => *synced_version != record.last_updated,
+                        None => !is_unreviewed(record),
+                    })
+                    .cloned()
+                    .collect();
+                outcome.queued += records.len();
+                state.queue.retain(|push| push.folder_path != folder_path);
+                state.queue.push(QueuedPush {
+                    folder_path: folder_path.to_string(),
+                    records,
+                });
+                outcome.error = Some(format!("offline, push queued ({})", e));
+            }
+            Err(SyncError::Failed(e)) => outcome.error = Some(e),
+        }
+        self.save_state(&state);
+        outcome
+    }
+
+    pub fn pull(&self, folder_path: &str, local: &mut [Properties]) -> SyncOutcome {
+        let mut outcome = SyncOutcome::default();
+        let mut state = self.state.lock().unwrap();
+        self.flush_queue(&mut state, &mut outcome);
+        match self.fetch(folder_path) {
+            Ok(remote) => self.merge(&mut state, folder_path, local, &remote, &mut outcome),
+            Err(SyncError::Offline(e)) | Err(SyncError::Failed(e)) => outcome.error = Some(e),
+        }
+        self.save_state(&state);
+        outcome
+    }
+
+    // Settles a conflict, either by pushing the local record over the remote one or by
+    // taking the remote record. Returns the record to keep locally.
+    pub fn resolve(&self, conflict: &SyncConflict, keep_local: bool) -> Result<Properties, String> {
+        let mut state = self.state.lock().unwrap();
+        let kept = match keep_local {
+            true => {
+                self.send(&conflict.folder_path, std::slice::from_ref(&conflict.local))
+                    .map_err(|e| match e {
+                        SyncError::Offline(e) | SyncError::Failed(e) => e,
+                    })?;
+                conflict.local.clone()
+            }
+            false => {
+                let mut remote = Properties {
+                    index: conflict.local.index,
+                    ..conflict.remote.clone()
+                };
+                merge_verdicts(&mut remote, &conflict.local);
+                remote
+            }
+        };
+        state
+            .synced
+            .entry(conflict.folder_path.clone())
+            .or_default()
+            .insert(kept.image_path.clone(), kept.last_updated.clone());
+        self.save_state(&state);
+        Ok(kept)
+    }
+
+    pub fn pull_interval(&self) -> Duration {
+        self.config.pull_interval
+    }
+}
+
+// Runs a push or pull on a blocking worker, the requests can take up to the timeout.
+// Records pulled by a push are saved along with the stored records of the folder.
+pub async fn sync_in_background(
+    client: Arc<SyncClient>,
+    storage: StorageHandle,
+    folder_path: String,
+    direction: SyncDirection,
+    sent: Vec<Properties>,
+) -> SyncJob {
+    let job_folder = folder_path.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut records = sent.clone();
+        let outcome = match direction {
+            SyncDirection::Push => client.push(&folder_path, &mut records),
+            SyncDirection::Pull => client.pull(&folder_path, &mut records),
+        };
+        if direction == SyncDirection::Push && outcome.pulled > 0 {
+            if let Err(e) = save_synced(&storage, &folder_path, &records) {
+                println!("Error: {}", e);
+            }
+        }
+        SyncJob {
+            folder_path,
+            sent,
+            records,
+            outcome,
+        }
+    })
+    .await
+    .unwrap_or_else(|e| SyncJob {
+        folder_path: job_folder,
+        sent: vec![],
+        records: vec![],
+        outcome: SyncOutcome {
+            error: Some(e.to_string()),
+            ..Default::default()
+        },
+    })
+}
+
+// Replaces the stored records of the synced images, the others are left as they are
+fn save_synced(
+    storage: &StorageHandle,
+    folder_path: &str,
+    records: &[Properties],
+) -> std::io::Result<()> {
+    let mut stored = storage.load_folder(folder_path)?.unwrap_or_default();
+    for record in records {
+        match stored
+            .iter_mut()
+            .find(|stored_record| stored_record.image_path == record.image_path)
+        {
+            Some(stored_record) => *stored_record = record.clone(),
+            None => stored.push(record.clone()),
+        }
+    }
+    storage.save_folder(folder_path, &stored)
+}
+
+static SYNC_CLIENT: OnceCell<Arc<SyncClient>> = OnceCell::new();
+
+// Set up once at startup when a sync URL is given on the command line
+pub fn init_sync(config: SyncConfig) -> Result<(), String> {
+    let _ = SYNC_CLIENT.set(Arc::new(SyncClient::new(config)?));
+    Ok(())
+}
+
+pub fn sync_client() -> Option<Arc<SyncClient>> {
+    SYNC_CLIENT.get().cloned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(index: usize, annotation: Option<bool>, last_updated: &str) -> Properties {
+        Properties {
+            index,
+            image_path: format!("folder/image_{}.jpg", index),
+            annotation,
+            comments: None,
+            last_updated: Some(last_updated.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn client(base_url: &str, name: &str) -> SyncClient {
+        let state_path = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&state_path);
+        let mut config = SyncConfig::new(base_url);
+        config.token = Some("secret".to_string());
+        config.state_path = state_path.to_str().unwrap().to_string();
+        SyncClient::new(config).unwrap()
+    }
+
+    #[test]
+    fn test_push_sends_changed_records() {
+        let mut server = mockito::Server::new();
+        let records_path = "/api/folders/%2Fdata%2Fimages/records";
+        let get_mock = server
+            .mock("GET", records_path)
+            .match_header("authorization", "Bearer secret")
+            .with_status(404)
+            .create();
+        let local = vec![record(0, Some(true), "t1"), record(1, None, "t1")];
+        let put_mock = server
+            .mock("PUT", records_path)
+            .match_header("authorization", "Bearer secret")
+            .match_body(mockito::Matcher::Json(
+                serde_json::to_value(&local).unwrap(),
+            ))
+            .create();
+
+        let client = client(
+            &format!("{}/api", server.url()),
+            "annotator_test_sync_push.json",
+        );
+        let outcome = client.push("/data/images", &mut local.clone());
+        get_mock.assert();
+        put_mock.assert();
+        assert_eq!(outcome.pushed, 2);
+        assert!(outcome.conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_pull_applies_remote_changes_and_detects_conflicts() {
+        let mut server = mockito::Server::new();
+        let records_path = "/folders/folder/records";
+        let client = client(&server.url(), "annotator_test_sync_pull.json");
+
+        // Both sides start from the same synced state
+        let mut local = vec![record(0, None, "t1"), record(1, None, "t1")];
+        let get_mock = server
+            .mock("GET", records_path)
+            .with_body(serde_json::to_string(&local).unwrap())
+            .create();
+        assert_eq!(client.pull("folder", &mut local), SyncOutcome::default());
+        get_mock.remove();
+
+        // Remote changes both images, the local side changed only the second one
+        local[1] = record(1, Some(true), "t2");
+        let remote = vec![record(0, Some(false), "t3"), record(1, Some(false), "t3")];
+        server
+            .mock("GET", records_path)
+            .with_body(serde_json::to_string(&remote).unwrap())
+            .create();
+        let outcome = client.pull("folder", &mut local);
+        assert_eq!(outcome.pulled, 1);
+        assert_eq!(local[0], remote[0]);
+        assert_eq!(outcome.conflicts.len(), 1);
+        assert_eq!(outcome.conflicts[0].local, record(1, Some(true), "t2"));
+        assert_eq!(outcome.conflicts[0].remote, remote[1]);
+
+        // Taking the remote side settles the conflict
+        let kept = client.resolve(&outcome.conflicts[0], false).unwrap();
+        assert_eq!(kept, remote[1]);
+        local[1] = kept;
+        assert!(client.pull("folder", &mut local).conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_offline_push_is_queued_and_flushed() {
+        let mut server = mockito::Server::new();
+        // Nothing listens on the discard port
+        let mut offline = client("http://127.0.0.1:9", "annotator_test_sync_offline.json");
+        let mut local = vec![record(0, Some(false), "t1"), record(1, None, "t1")];
+        let outcome = offline.push("folder", &mut local);
+        assert!(outcome.error.is_some());
+        assert_eq!(outcome.queued, 1);
+
+        // The queue is kept in the state file and sent once the server is reachable
+        offline.config.base_url = server.url();
+        let online = SyncClient::new(offline.config.clone()).unwrap();
+        let records_path = "/folders/folder/records";
+        server.mock("GET", records_path).with_status(404).create();
+        let put_mock = server
+            .mock("PUT", records_path)
+            .match_body(mockito::Matcher::Json(
+                serde_json::to_value(vec![record(0, Some(false), "t1")]).unwrap(),
+            ))
+            .expect(1)
+            .create();
+        let outcome = online.pull("other", &mut []);
+        put_mock.assert();
+        assert_eq!(outcome.pushed, 1);
+        let _ = std::fs::remove_file(&online.config.state_path);
+    }
+
+    #[test]
+    fn test_sync_job_keeps_edits_made_meanwhile() {
+        let sent = vec![record(0, None, "t1"), record(1, None, "t1")];
+        let mut synced = vec![record(0, Some(false), "t2"), record(1, Some(false), "t2")];
+        synced[1].set_verdict(Some("bob"), Some(true), None);
+        let job = SyncJob {
+            folder_path: "folder".to_string(),
+            sent: sent.clone(),
+            records: synced.clone(),
+            outcome: SyncOutcome::default(),
+        };
+        // The second image was marked while the pull ran
+        let mut local = vec![sent[0].clone(), record(1, Some(true), "t3")];
+        local[0].index = 5;
+        job.apply_to(&mut local);
+        assert_eq!(local[0].index, 5);
+        assert_eq!(local[0].annotation, Some(false));
+        assert_eq!(local[1].annotation, Some(true));
+        assert_eq!(local[1].verdicts, synced[1].verdicts);
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use once_cell::sync::OnceCell;
+use sha2::{Digest, Sha256};
+
+use super::adjustments::DisplayAdjustments;
+use super::formats::split_item;
+use super::metadata::open_oriented;
+use super::render_image::validate_image;
+
+// Longest side of a thumbnail, in pixels
+pub const THUMBNAIL_SIZE: u32 = 120;
+// Images handed to a background worker at once
+pub const BUILD_BATCH_SIZE: usize = 64;
+
+const INDEX_FILE: &str = "index.json";
+// Changes the name of every thumbnail when the way they are built changes
+const THUMBNAIL_VERSION: u8 = 3;
+
+// Thumbnails stored as PNG files in `dir`, named after the path, size and modification time
+// of their image, so they are rebuilt when the image changes. The index maps every
+// thumbnail back to its image, to find the stale ones.
+#[derive(Debug)]
+pub struct ThumbnailCache {
+    dir: PathBuf,
+    index: Mutex<HashMap<String, String>>,
+}
+
+pub fn default_cache_dir() -> PathBuf {
+    home::home_dir()
+        .unwrap_or_default()
+        .join(".cache")
+        .join("annotator-rust")
+        .join("thumbnails")
+}
+
+static THUMBNAIL_CACHE: OnceCell<Arc<ThumbnailCache>> = OnceCell::new();
+
+// Set once at startup, otherwise the default directory is used
+pub fn init_thumbnails(dir: &Path) -> io::Result<()> {
+    let cache = Arc::new(ThumbnailCache::open(dir)?);
+    let _ = THUMBNAIL_CACHE.set(cache);
+    Ok(())
+}
+
+pub fn thumbnail_cache() -> Option<Arc<ThumbnailCache>> {
+    THUMBNAIL_CACHE
+        .get_or_try_init(|| ThumbnailCache::open(&default_cache_dir()).map(Arc::new))
+        .map_err(|e| println!("Couldn't open the thumbnail cache, error: {}", e))
+        .ok()
+        .cloned()
+}
+
+impl ThumbnailCache {
+    pub fn open(dir: &Path) -> io::Result<ThumbnailCache> {
+        std::fs::create_dir_all(dir)?;
+        let index = match std::fs::read_to_string(dir.join(INDEX_FILE)) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(ThumbnailCache {
+            dir: dir.to_path_buf(),
+            index: Mutex::new(index),
+        })
+    }
+
+    // File name of the thumbnail for the image as it is now on disk
+    fn key(image_path: &Path) -> io::Result<String> {
+        let metadata = std::fs::metadata(split_item(image_path).0)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut hasher = Sha256::new();
+        hasher.update([THUMBNAIL_VERSION]);
+        hasher.update(image_path.to_string_lossy().as_bytes());
+        hasher.update(metadata.len().to_le_bytes());
+        hasher.update(modified.as_nanos().to_le_bytes());
+        Ok(format!("{:x}.png", hasher.finalize()))
+    }
+
+    // The thumbnail if it was built for the current version of the image
+    pub fn get(&self, image_path: &Path) -> Option<PathBuf> {
+        let thumbnail_path = self.dir.join(Self::key(image_path).ok()?);
+        thumbnail_path.exists().then_some(thumbnail_path)
+    }
+
+    pub fn get_or_build(&self, image_path: &Path) -> Result<PathBuf, String> {
+        if let Some(thumbnail_path) = self.get(image_path) {
+            return Ok(thumbnail_path);
+        }
+        let key = Self::key(image_path).map_err(|e| e.to_string())?;
+        validate_image(image_path).map_err(|e| e.to_string())?;
+        let thumbnail = DisplayAdjustments::default().render(
+            open_oriented(image_path)
+                .map_err(|e| e.to_string())?
+                .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE),
+        );
+        let thumbnail_path = self.dir.join(&key);
+        thumbnail.save(&thumbnail_path).map_err(|e| e.to_string())?;
+        self.index
+            .lock()
+            .unwrap()
+            .insert(key, image_path.to_string_lossy().to_string());
+        Ok(thumbnail_path)
+    }
+
+    // Builds the missing thumbnails, skipping files that aren't images
+    pub fn build_all(&self, image_paths: &[PathBuf]) -> Vec<(PathBuf, PathBuf)> {
+        let built = image_paths
+            .iter()
+            .filter_map(|image_path| {
+                let thumbnail_path = self.get_or_build(image_path).ok()?;
+                Some((image_path.clone(), thumbnail_path))
+            })
+            .collect();
+        if let Err(e) = self.save_index() {
+            println!("Couldn't save the thumbnail index, error: {}", e);
+        }
+        built
+    }
+
+    fn save_index(&self) -> io::Result<()> {
+        let index = self.index.lock().unwrap();
+        std::fs::write(
+            self.dir.join(INDEX_FILE),
+            serde_json::to_string(&*index).unwrap_or_default(),
+        )
+    }
+
+    // Removes the thumbnails of images that changed or no longer exist, and files the index
+    // doesn't know about. Returns the number of files removed.
+    pub fn prune(&self) -> io::Result<usize> {
+        let mut removed = 0;
+        {
+            let mut index = self.index.lock().unwrap();
+            index.retain(|key, image_path| {
+                let current = Self::key(Path::new(image_path)).ok();
+                current.as_ref() == Some(key)
+            });
+            for entry in std::fs::read_dir(&self.dir)? {
+                let path = entry?.path();
+                let name = path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string();
+                if name != INDEX_FILE && !index.contains_key(&name) {
+                    std::fs::remove_file(&path)?;
+                    removed += 1;
+                }
+            }
+        }
+        self.save_index()?;
+        Ok(removed)
+    }
+}
+
+// Builds a batch on a blocking worker of the app's runtime
+pub async fn build_in_background(
+    cache: Arc<ThumbnailCache>,
+    image_paths: Vec<PathBuf>,
+) -> Vec<(PathBuf, PathBuf)> {
+    tokio::task::spawn_blocking(move || cache.build_all(&image_paths))
+        .await
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_and_prune_thumbnails() {
+        let folder = std::env::temp_dir().join("annotator_test_thumbnails");
+        let _ = std::fs::remove_dir_all(&folder);
+        let images_dir = folder.join("images");
+        std::fs::create_dir_all(&images_dir).unwrap();
+        let kept = images_dir.join("kept.png");
+        let removed = images_dir.join("removed.png");
+        image::RgbImage::new(400, 200).save(&kept).unwrap();
+        image::RgbImage::new(10, 10).save(&removed).unwrap();
+        let text_path = images_dir.join("notes.txt");
+        std::fs::write(&text_path, "not an image").unwrap();
+
+        let cache = ThumbnailCache::open(&folder.join("cache")).unwrap();
+        let built = cache.build_all(&[kept.clone(), removed.clone(), text_path]);
+        assert_eq!(built.len(), 2);
+        let thumbnail = image::open(&built[0].1).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (120, 60));
+        assert_eq!(cache.get(&kept), Some(built[0].1.clone()));
+
+        // The index survives reopening the cache
+        let cache = ThumbnailCache::open(&folder.join("cache")).unwrap();
+        std::fs::remove_file(&removed).unwrap();
+        assert_eq!(cache.prune().unwrap(), 1);
+        assert!(cache.get(&kept).is_some());
+        assert!(!built[1].1.exists());
+        assert_eq!(cache.prune().unwrap(), 0);
+    }
+}
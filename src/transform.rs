@@ -0,0 +1,204 @@
+use std::path::{Path, PathBuf};
+
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use super::formats::{part_of, split_item};
+use super::metadata::open_oriented;
+use super::render_image::Properties;
+
+// Rotation and flips that fix an image shown the wrong way round. Applied to the image as
+// displayed (after the EXIF orientation): the rotation first, then the flips.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ImageTransform {
+    // Clockwise, in degrees: 0, 90, 180 or 270
+    #[serde(default)]
+    pub rotation: u32,
+    #[serde(default)]
+    pub flip_horizontal: bool,
+    #[serde(default)]
+    pub flip_vertical: bool,
+}
+
+impl ImageTransform {
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // The transform followed by a clockwise rotation of the result
+    pub fn rotated(&self, degrees: u32) -> Self {
+        // A rotation after a single flip is the opposite rotation before it
+        let rotation = match self.flip_horizontal != self.flip_vertical {
+            true => self.rotation + 360 - degrees % 360,
+            false => self.rotation + degrees,
+        };
+        ImageTransform {
+            rotation: rotation % 360,
+            ..*self
+        }
+    }
+
+    // The transform followed by a flip of the result
+    pub fn flipped(&self, horizontal: bool) -> Self {
+        let mut flipped = *self;
+        match horizontal {
+            true => flipped.flip_horizontal = !flipped.flip_horizontal,
+            false => flipped.flip_vertical = !flipped.flip_vertical,
+        }
+        // Flipping both ways is the same as rotating by 180°
+        if flipped.flip_horizontal && flipped.flip_vertical {
+            flipped = ImageTransform {
+                rotation: (flipped.rotation + 180) % 360,
+                ..Default::default()
+            };
+        }
+        flipped
+    }
+
+    pub fn apply(&self, image: DynamicImage) -> DynamicImage {
+        let mut image = match self.rotation {
+            90 => image.rotate90(),
+            180 => image.rotate180(),
+            270 => image.rotate270(),
+            _ => image,
+        };
+        if self.flip_horizontal {
+            image = image.fliph();
+        }
+        if self.flip_vertical {
+            image = image.flipv();
+        }
+        image
+    }
+
+    pub fn describe(&self) -> String {
+        let mut steps = vec![];
+        if self.rotation != 0 {
+            steps.push(format!("rotate {}° clockwise", self.rotation));
+        }
+        if self.flip_horizontal {
+            steps.push("flip horizontally".to_string());
+        }
+        if self.flip_vertical {
+            steps.push("flip vertically".to_string());
+        }
+        match steps.is_empty() {
+            true => "none".to_string(),
+            false => steps.join(", then "),
+        }
+    }
+}
+
+// Where the corrected copy of an item is written. Pages and frames get their index in the
+// name, so that they don't overwrite each other.
+pub fn corrected_path(image_path: &Path, output_dir: &Path) -> PathBuf {
+    let (file, index) = split_item(image_path);
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match part_of(image_path) {
+        Some((kind, _)) => format!("{}_{}{}", stem, kind.as_display().to_lowercase(), index),
+        None => stem.to_string(),
+    };
+    // Frames and pages are written as PNG, the other images keep their format
+    let extension = match part_of(image_path) {
+        Some(_) => "png".to_string(),
+        None => file
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+    };
+    output_dir.join(name).with_extension(extension)
+}
+
+// Writes a corrected copy of every image with a recorded transform, returns the number of
+// copies written and the errors
+pub fn write_corrected_copies(records: &[Properties], output_dir: &Path) -> (usize, Vec<String>) {
+    let mut written = 0;
+    let mut errors = vec![];
+    for record in records {
+        let transform = match record.transform {
+            Some(valid_transform) if !valid_transform.is_identity() => valid_transform,
+            _ => continue,
+        };
+        let image_path = PathBuf::from(&record.image_path);
+        let output_path = corrected_path(&image_path, output_dir);
+        match open_oriented(&image_path).and_then(|image| transform.apply(image).save(&output_path))
+        {
+            Ok(_) => written += 1,
+            Err(e) => errors.push(format!("{}: {}", record.image_path, e)),
+        }
+    }
+    (written, errors)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compose_transforms() {
+        // 3 x 2, every pixel has a different value
+        let image = DynamicImage::ImageLuma8(
+            image::GrayImage::from_raw(3, 2, vec![10, 20, 30, 40, 50, 60]).unwrap(),
+        );
+        let steps = [
+            "rotate 90",
+            "flip h",
+            "rotate 270",
+            "flip v",
+            "rotate 180",
+            "flip h",
+        ];
+        let mut transform = ImageTransform::default();
+        let mut expected = image.clone();
+        for step in steps {
+            (transform, expected) = match step {
+                "flip h" => (transform.flipped(true), expected.fliph()),
+                "flip v" => (transform.flipped(false), expected.flipv()),
+                "rotate 90" => (transform.rotated(90), expected.rotate90()),
+                "rotate 180" => (transform.rotated(180), expected.rotate180()),
+                _ => (transform.rotated(270), expected.rotate270()),
+            };
+            assert_eq!(
+                transform.apply(image.clone()).to_luma8(),
+                expected.to_luma8(),
+                "after {}",
+                step
+            );
+            assert!(!(transform.flip_horizontal && transform.flip_vertical));
+        }
+        assert_eq!(
+            ImageTransform::default().rotated(90).rotated(270),
+            ImageTransform::default()
+        );
+        assert_eq!(ImageTransform::default().describe(), "none");
+    }
+
+    #[test]
+    fn test_write_corrected_copies() {
+        let folder = std::env::temp_dir().join("annotator_test_transform");
+        let output_dir = folder.join("corrected");
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let image_path = folder.join("sideways.png");
+        image::RgbImage::new(30, 20).save(&image_path).unwrap();
+        let records = vec![
+            Properties {
+                image_path: image_path.to_string_lossy().to_string(),
+                transform: Some(ImageTransform::default().rotated(90)),
+                ..Default::default()
+            },
+            Properties {
+                index: 1,
+                image_path: folder.join("upright.png").to_string_lossy().to_string(),
+                ..Default::default()
+            },
+        ];
+        assert_eq!(write_corrected_copies(&records, &output_dir), (1, vec![]));
+        let corrected = image::open(output_dir.join("sideways.png")).unwrap();
+        assert_eq!((corrected.width(), corrected.height()), (20, 30));
+        assert_eq!(
+            corrected_path(Path::new("a/clip.gif#frame=4"), Path::new("out")),
+            PathBuf::from("out/clip_frame4.png")
+        );
+    }
+}
@@ -0,0 +1,417 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use iced::futures::channel::mpsc;
+use iced::futures::{SinkExt, StreamExt};
+use iced::Subscription;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use notify_rust::Notification;
+
+use super::fingerprint::{FileFingerprint, FileStatus};
+use super::formats::{expand_items, part_of, part_path, split_item};
+use super::list_files;
+use super::render_image::{init_json_obj, Properties};
+
+// How often the open folder is listed again when its file system events aren't available
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+// Sent by `folder_events`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FolderEvent {
+    // Old and new path of a file renamed within the folder
+    Renamed(PathBuf, PathBuf),
+    // Files were added, removed or written, the folder is listed again
+    Changed,
+    // The folder can't be watched, it is listed every `WATCH_INTERVAL` instead
+    Unavailable(String),
+}
+
+fn folder_event(event: Event) -> Option<FolderEvent> {
+    match event.kind {
+        EventKind::Access(_) => None,
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => Some(
+            FolderEvent::Renamed(event.paths[0].clone(), event.paths[1].clone()),
+        ),
+        _ => Some(FolderEvent::Changed),
+    }
+}
+
+// File system events of the folder, without sub-directories. The events that arrive
+// together are sent at once, a single `Changed` for all the files written.
+pub fn folder_events(folder_path: String) -> Subscription<FolderEvent> {
+    iced::subscription::channel(folder_path.clone(), 100, |mut output| async move {
+        let (sender, mut receiver) = mpsc::unbounded();
+        let watcher = notify::recommended_watcher(move |result: notify::Result<Event>| {
+            if let Some(event) = result.ok().and_then(folder_event) {
+                let _ = sender.unbounded_send(event);
+            }
+        })
+        .and_then(|mut watcher| {
+            watcher.watch(Path::new(&folder_path), RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        });
+        // Watches the folder as long as the subscription is kept
+        let _watcher = match watcher {
+            Ok(valid_watcher) => Some(valid_watcher),
+            Err(e) => {
+                let _ = output.send(FolderEvent::Unavailable(e.to_string())).await;
+                None
+            }
+        };
+        while let Some(event) = receiver.next().await {
+            let mut events = vec![event];
+            while let Ok(next_event) = receiver.try_recv() {
+                if next_event != FolderEvent::Changed || !events.contains(&next_event) {
+                    events.push(next_event);
+                }
+            }
+            for event in events {
+                let _ = output.send(event).await;
+            }
+        }
+        // Without a watcher, nothing is sent anymore
+        iced::futures::future::pending().await
+    })
+}
+
+// Files added to, removed from or renamed in a folder since it was last listed
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FolderChanges {
+    // Images of the new files, with their pages and frames
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    // Old and new path of the files
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+}
+
+impl FolderChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.renamed.is_empty()
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} images added, {} files removed, {} files renamed",
+            self.added.len(),
+            self.removed.len(),
+            self.renamed.len()
+        )
+    }
+}
+
+// Files of the listed images, with the fingerprint of the first reviewed item of each file
+pub fn known_files(
+    all_images: &[PathBuf],
+    records: &[Properties],
+) -> HashMap<PathBuf, Option<FileFingerprint>> {
+    let mut known: HashMap<PathBuf, Option<FileFingerprint>> = HashMap::new();
+    for (idx, path) in all_images.iter().enumerate() {
+        let fingerprint = records
+            .get(idx)
+            .and_then(|record| record.fingerprint.clone());
+        let entry = known.entry(split_item(path).0).or_default();
+        if entry.is_none() {
+            *entry = fingerprint;
+        }
+    }
+    known
+}
+
+// `known` has the files already listed, with the fingerprint of the reviewed ones. A new
+// file with the same content as a reviewed file that is gone is taken as a rename.
+pub fn scan_folder(
+    folder_path: &String,
+    known: &HashMap<PathBuf, Option<FileFingerprint>>,
+    frame_step: u32,
+) -> FolderChanges {
+    let files = list_files(folder_path);
+    let listed: HashSet<&PathBuf> = files.iter().collect();
+    let mut added: Vec<PathBuf> = files
+        .iter()
+        .filter(|file| !known.contains_key(*file))
+        .cloned()
+        .collect();
+    let mut removed = vec![];
+    let mut renamed = vec![];
+    for (file, fingerprint) in known.iter() {
+        if listed.contains(file) {
+            continue;
+        }
+        let same_content = |candidate: &PathBuf| {
+            let Some(fingerprint) = fingerprint else {
+                return false;
+            };
+            // A rename keeps the modification time, used until the content is hashed
+            match fingerprint.is_hashed() {
+                true => {
+                    candidate
+                        .metadata()
+                        .is_ok_and(|metadata| metadata.len() == fingerprint.size)
+                        && FileFingerprint::of(candidate)
+                            .is_ok_and(|current| current.sha256 == fingerprint.sha256)
+                }
+                false => candidate
+                    .metadata()
+                    .is_ok_and(|metadata| fingerprint.same_metadata(&metadata)),
+            }
+        };
+        match added.iter().position(same_content) {
+            Some(position) => renamed.push((file.clone(), added.remove(position))),
+            None => removed.push(file.clone()),
+        }
+    }
+    removed.sort();
+    renamed.sort();
+    FolderChanges {
+        added: expand_items(added, frame_step),
+        removed,
+        renamed,
+    }
+}
+
+pub async fn scan_in_background(
+    folder_path: String,
+    known: HashMap<PathBuf, Option<FileFingerprint>>,
+    frame_step: u32,
+) -> FolderChanges {
+    tokio::task::spawn_blocking(move || scan_folder(&folder_path, &known, frame_step))
+        .await
+        .unwrap_or_default()
+}
+
+// Path of an image, or of one of its pages or frames, once its file is renamed
+pub fn renamed_item(image_path: &Path, new_file: &Path) -> PathBuf {
+    match part_of(image_path) {
+        Some((kind, index)) => part_path(new_file, kind, index),
+        None => new_file.to_path_buf(),
+    }
+}
+
+// Updates the images of a folder and their records, which stay in the same order. Records
+// of removed images move to `missing_records`, and come back if their file does. New images
+// go at the end so that the other images keep their position.
+pub fn apply_changes(
+    folder_path: &str,
+    all_images: &mut Vec<PathBuf>,
+    records: &mut Vec<Properties>,
+    missing_records: &mut Vec<Properties>,
+    changes: &FolderChanges,
+) {
+    let removed: HashSet<&Path> = changes.removed.iter().map(|file| file.as_path()).collect();
+    let renamed: HashMap<&Path, &Path> = changes
+        .renamed
+        .iter()
+        .map(|(old, new)| (old.as_path(), new.as_path()))
+        .collect();
+    let previous: Vec<(PathBuf, Properties)> =
+        all_images.drain(..).zip(records.drain(..)).collect();
+    for (image_path, mut record) in previous {
+        let file = split_item(&image_path).0;
+        if removed.contains(file.as_path()) {
+            missing_records.push(Properties {
+                file_status: Some(FileStatus::Missing),
+                ..record
+            });
+            continue;
+        }
+        let image_path = match renamed.get(file.as_path()) {
+            Some(new_file) => renamed_item(&image_path, new_file),
+            None => image_path,
+        };
+        record.image_path = image_path.to_string_lossy().to_string();
+        all_images.push(image_path);
+        records.push(record);
+    }
+    let new_records = init_json_obj(folder_path.to_string(), changes.added.clone())
+        .image_to_properties_map
+        .remove(folder_path)
+        .unwrap_or_default();
+    for (image_path, record) in changes.added.iter().zip(new_records) {
+        let record = match missing_records
+            .iter()
+            .position(|missing| missing.image_path == record.image_path)
+        {
+            Some(position) => Properties {
+                file_status: None,
+                ..missing_records.remove(position)
+            },
+            None => record,
+        };
+        all_images.push(image_path.clone());
+        records.push(record);
+    }
+    for (index, record) in records.iter_mut().enumerate() {
+        record.index = index;
+    }
+}
+
+// Desktop notification, the error is returned when notifications are not available
+pub fn notify_changes(folder_path: &str, changes: &FolderChanges) -> Result<(), String> {
+    Notification::new()
+        .summary("Images changed in the folder")
+        .body(&format!("{}: {}", folder_path, changes.summary()))
+        .show()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::img_visualizer::fingerprint::verify_record;
+
+    #[test]
+    fn test_scan_folder() {
+        let folder = std::env::temp_dir().join("annotator_test_watcher");
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        let folder_path = folder.to_string_lossy().to_string();
+        for (name, content) in [("a.png", "first"), ("b.png", "second"), ("c.png", "third")] {
+            std::fs::write(folder.join(name), content).unwrap();
+        }
+        let mut reviewed = Properties {
+            image_path: folder.join("b.png").to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        reviewed.set_verdict(None, Some(true), None);
+        // Hashed as on export
+        verify_record(&mut reviewed);
+        let known: HashMap<PathBuf, Option<FileFingerprint>> = HashMap::from([
+            (folder.join("a.png"), None),
+            (folder.join("b.png"), reviewed.fingerprint.clone()),
+            (folder.join("c.png"), None),
+        ]);
+        assert_eq!(
+            scan_folder(&folder_path, &known, 1),
+            FolderChanges::default()
+        );
+
+        std::fs::rename(folder.join("b.png"), folder.join("renamed.png")).unwrap();
+        std::fs::remove_file(folder.join("c.png")).unwrap();
+        std::fs::write(folder.join("d.png"), "fourth").unwrap();
+        assert_eq!(
+            scan_folder(&folder_path, &known, 1),
+            FolderChanges {
+                added: vec![folder.join("d.png")],
+                removed: vec![folder.join("c.png")],
+                renamed: vec![(folder.join("b.png"), folder.join("renamed.png"))],
+            }
+        );
+    }
+
+    #[test]
+    fn test_folder_event() {
+        let event = |kind: EventKind, paths: &[&str]| Event {
+            kind,
+            paths: paths.iter().map(PathBuf::from).collect(),
+            attrs: Default::default(),
+        };
+        assert_eq!(
+            folder_event(event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                &["folder/a.png", "folder/b.png"]
+            )),
+            Some(FolderEvent::Renamed(
+                PathBuf::from("folder/a.png"),
+                PathBuf::from("folder/b.png")
+            ))
+        );
+        // Only one side of the rename is known, the folder is listed again
+        assert_eq!(
+            folder_event(event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+                &["folder/a.png"]
+            )),
+            Some(FolderEvent::Changed)
+        );
+        assert_eq!(
+            folder_event(event(
+                EventKind::Access(notify::event::AccessKind::Any),
+                &["folder/a.png"]
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn test_apply_changes() {
+        let folder_path = "folder";
+        let path = |name: &str| PathBuf::from(folder_path).join(name);
+        let mut all_images = vec![path("a.png"), path("b.png"), path("c.png")];
+        let mut records = init_json_obj(folder_path.to_string(), all_images.clone())
+            .image_to_properties_map
+            .remove(folder_path)
+            .unwrap();
+        records[1].annotation = Some(false);
+        records[2].annotation = Some(true);
+        let mut missing_records = vec![];
+
+        let changes = FolderChanges {
+            added: vec![path("d.png")],
+            removed: vec![path("a.png")],
+            renamed: vec![(path("c.png"), path("e.png"))],
+        };
+        apply_changes(
+            folder_path,
+            &mut all_images,
+            &mut records,
+            &mut missing_records,
+            &changes,
+        );
+        assert_eq!(
+            all_images,
+            vec![path("b.png"), path("e.png"), path("d.png")]
+        );
+        let verdicts: Vec<(usize, &str, Option<bool>)> = records
+            .iter()
+            .map(|record| (record.index, record.image_path.as_str(), record.annotation))
+            .collect();
+        assert_eq!(
+            verdicts,
+            vec![
+                (0, "folder/b.png", Some(false)),
+                (1, "folder/e.png", Some(true)),
+                (2, "folder/d.png", None),
+            ]
+        );
+        assert_eq!(missing_records.len(), 1);
+        assert_eq!(missing_records[0].file_status, Some(FileStatus::Missing));
+
+        // The removed image comes back with its record
+        records[0].annotation = Some(true);
+        let changes = FolderChanges {
+            added: vec![path("a.png")],
+            ..Default::default()
+        };
+        apply_changes(
+            folder_path,
+            &mut all_images,
+            &mut records,
+            &mut missing_records,
+            &changes,
+        );
+        assert_eq!(all_images.len(), 4);
+        assert_eq!(records[3].index, 3);
+        assert_eq!(records[3].image_path, "folder/a.png");
+        assert_eq!(records[3].file_status, None);
+        assert!(missing_records.is_empty());
+
+        // Every file is removed
+        let changes = FolderChanges {
+            removed: all_images.clone(),
+            ..Default::default()
+        };
+        apply_changes(
+            folder_path,
+            &mut all_images,
+            &mut records,
+            &mut missing_records,
+            &changes,
+        );
+        assert!(all_images.is_empty());
+        assert!(records.is_empty());
+        assert_eq!(missing_records.len(), 4);
+    }
+}